@@ -7,17 +7,21 @@ use std::{
 };
 use regex::Regex;
 
+#[allow(dead_code)]
+mod lib;
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     let dry_run = !args.iter().any(|x| x == "--do-rename");
+    let with_outputs = args.iter().any(|x| x == "--with-outputs");
 
     let positional: Vec<&str> = args.iter()
-        .filter(|x| x.as_str() != "--do-rename")
+        .filter(|x| x.as_str() != "--do-rename" && x.as_str() != "--with-outputs")
         .map(|x| x.as_str())
         .collect();
 
     if positional.len() != 4 {
-        eprintln!("Usage: {} <directory> <regex> <replacement> [--do-rename]", args[0]);
+        eprintln!("Usage: {} <directory> <regex> <replacement> [--do-rename] [--with-outputs]", args[0]);
         process::exit(1);
     }
 
@@ -77,7 +81,25 @@ fn main() {
         }
         seen.insert(new_path.clone());
 
+        // A renamed spec takes the output its mapping rules give along
+        let moved_output = if with_outputs {
+            lib::mapping::renamed_output(&path.to_string_lossy(), &new_path.to_string_lossy())
+                .filter(|(output, _)| Path::new(output).exists())
+        } else {
+            None
+        };
+
         replacements.push((path, new_path));
+
+        if let Some((output, new_output)) = moved_output {
+            let new_output = PathBuf::from(new_output);
+            if seen.contains(&new_output) || new_output.exists() {
+                eprintln!("Error: output '{}' would clash with another file", new_output.display());
+                process::exit(1);
+            }
+            seen.insert(new_output.clone());
+            replacements.push((PathBuf::from(output), new_output));
+        }
     }
 
     if replacements.is_empty() {
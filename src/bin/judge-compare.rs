@@ -0,0 +1,231 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[allow(dead_code)]
+mod lib;
+
+use lib::history::RunRecord;
+use lib::judge::{self, JudgeMode, Verdict};
+use lib::provider::Provider;
+
+/// An evaluation request recorded by the driver, together with the full-file verdict it got.
+struct RecordedRun {
+    request_path: String,
+    inputs: judge::Inputs,
+    full_verdict: Option<Verdict>,
+}
+
+/// The inputs of the request at `request_path`, from the files saved beside it, or else, for
+/// runs recorded before those were saved, out of the request itself if it is a full-file one.
+fn load_run(request_path: &str, rejudge_full: bool) -> Option<RecordedRun> {
+    let request = fs::read_to_string(request_path).ok()?;
+    let inputs = judge::load_inputs(&judge::inputs_dir(request_path)).or_else(|| {
+        eprintln!("No saved inputs for {}, reading them out of the request", request_path);
+        judge::parse_full_prompt(&request)
+    })?;
+
+    // The recorded verdict only stands for the full prompt if the request was one.
+    let recorded_full = request
+        == judge::full_prompt(
+            &inputs.description,
+            &inputs.first,
+            &inputs.second,
+            &inputs.first_errors,
+            &inputs.second_errors,
+        );
+    let response_path = format!("{}-resp.txt", request_path.trim_end_matches(".txt"));
+    let full_verdict = if rejudge_full || !recorded_full || !Path::new(&response_path).exists() {
+        None
+    } else {
+        fs::read_to_string(&response_path)
+            .ok()
+            .and_then(|r| judge::parse_verdict(&r))
+    };
+
+    Some(RecordedRun {
+        request_path: request_path.to_string(),
+        inputs,
+        full_verdict,
+    })
+}
+
+/// The manifest of the run directory holding `request_path`, which may be in a subdirectory.
+fn manifest_for(request_path: &str) -> Option<RunRecord> {
+    Path::new(request_path).ancestors().skip(1).find_map(|dir| {
+        let manifest = fs::read_to_string(dir.join(lib::runs::MANIFEST_FILE)).ok()?;
+        serde_json::from_str(&manifest).ok()
+    })
+}
+
+/// The judge model that the recorded run used: "--judge-model" on its command line, or else
+/// the "judge_model" setting of its spec, or else the default Groq model of the configuration
+/// of the spec, as in llm-groq-5. Returns the configuration with it.
+fn recorded_judge(record: &RunRecord) -> Result<(lib::config::Config, String), String> {
+    let config = lib::config::for_spec(&record.input_file)?;
+    let settings = lib::frontmatter::settings_for(&record.input_file)?;
+    let from_args = record
+        .args
+        .iter()
+        .position(|arg| arg == "--judge-model")
+        .and_then(|i| record.args.get(i + 1))
+        .cloned();
+    let model = from_args
+        .or(settings.judge_model)
+        .unwrap_or_else(|| config.provider("groq").default_model.clone());
+    Ok((config, model))
+}
+
+/// The judge for `request_path`: the one of its recorded run, unless "--judge-model" says
+/// otherwise, built from the configuration of the spec.
+fn judge_for(request_path: &str, judge_model: Option<&String>) -> Box<dyn Provider> {
+    let (config, model) = match manifest_for(request_path) {
+        Some(record) => recorded_judge(&record).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }),
+        None => {
+            eprintln!("No run manifest for {}, using the project configuration", request_path);
+            let config = lib::config::project().clone();
+            let model = config.provider("groq").default_model.clone();
+            (config, model)
+        }
+    };
+    let model = judge_model.cloned().unwrap_or(model);
+    lib::provider::from_spec_in(&config, &model)
+}
+
+fn ask_judge(judge: &dyn Provider, prompt: &str, temperature: Option<f32>) -> Option<Verdict> {
+    let response = judge.evaluate_with(prompt, temperature, None);
+    let verdict = judge::parse_verdict(&response);
+    if verdict.is_none() {
+        eprintln!("Unexpected evaluation response: {}", response.trim());
+    }
+    verdict
+}
+
+fn verdict_name(verdict: Option<Verdict>) -> &'static str {
+    match verdict {
+        Some(Verdict::First) => "first",
+        Some(Verdict::Second) => "second",
+        None => "invalid",
+    }
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--diff-context <lines>] [--rejudge-full] [--judge-model <m>] [--temperature <t>] <.llm-runs/<run_id>/eval.txt>...",
+        program
+    );
+    std::process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut diff_context = 5;
+    let mut rejudge_full = false;
+    let mut judge_model = None;
+    let mut temperature = None;
+    let mut requests = Vec::new();
+
+    let mut iter = args[1..].iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--diff-context" => {
+                diff_context = iter
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or_else(|| usage(&args[0]));
+            }
+            "--rejudge-full" => rejudge_full = true,
+            "--judge-model" => judge_model = Some(iter.next().unwrap_or_else(|| usage(&args[0])).clone()),
+            "--temperature" => {
+                temperature = Some(
+                    iter.next()
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or_else(|| usage(&args[0])),
+                );
+            }
+            _ if arg.starts_with("--") => usage(&args[0]),
+            _ => requests.push(arg.clone()),
+        }
+    }
+
+    if requests.is_empty() {
+        usage(&args[0]);
+    }
+
+    let mut compared = 0;
+    let mut agreed = 0;
+    let mut full_bytes = 0;
+    let mut diff_bytes = 0;
+
+    for request_path in &requests {
+        eprintln!("Loading recorded run: {}", request_path);
+        let Some(run) = load_run(request_path, rejudge_full) else {
+            eprintln!("Skipping {}: no saved inputs and not a full-file evaluation request", request_path);
+            continue;
+        };
+
+        let full_prompt = judge::prompt(
+            JudgeMode::Full,
+            &run.inputs.description,
+            &run.inputs.first,
+            &run.inputs.second,
+            &run.inputs.first_errors,
+            &run.inputs.second_errors,
+            diff_context,
+        );
+        let diff_prompt = judge::prompt(
+            JudgeMode::Diff,
+            &run.inputs.description,
+            &run.inputs.first,
+            &run.inputs.second,
+            &run.inputs.first_errors,
+            &run.inputs.second_errors,
+            diff_context,
+        );
+
+        // Both modes are judged like the recorded run was, so that only the prompt differs.
+        let judge = judge_for(request_path, judge_model.as_ref());
+        let full_verdict = match run.full_verdict {
+            Some(v) => Some(v),
+            None => {
+                eprintln!("Calling {} for full-file evaluation", judge.name());
+                ask_judge(judge.as_ref(), &full_prompt, temperature)
+            }
+        };
+        eprintln!("Calling {} for diff evaluation", judge.name());
+        let diff_verdict = ask_judge(judge.as_ref(), &diff_prompt, temperature);
+
+        let agree = full_verdict.is_some() && full_verdict == diff_verdict;
+        compared += 1;
+        if agree {
+            agreed += 1;
+        }
+        full_bytes += full_prompt.len();
+        diff_bytes += diff_prompt.len();
+
+        println!(
+            "{}: full={} diff={} agree={} prompt bytes full={} diff={}",
+            run.request_path,
+            verdict_name(full_verdict),
+            verdict_name(diff_verdict),
+            if agree { "yes" } else { "no" },
+            full_prompt.len(),
+            diff_prompt.len()
+        );
+    }
+
+    println!("=== Summary ===");
+    println!("Compared runs: {}", compared);
+    println!("Agreement: {}/{}", agreed, compared);
+    if full_bytes > 0 {
+        println!(
+            "Prompt bytes: full={} diff={} ({:.0}%)",
+            full_bytes,
+            diff_bytes,
+            100.0 * diff_bytes as f64 / full_bytes as f64
+        );
+    }
+}
@@ -0,0 +1,335 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+use serde_json::{Value, json};
+
+use super::cargo;
+use super::provider::{AssistantTurn, Message, Provider, ToolCall, ToolSpec};
+
+pub const DEFAULT_MAX_STEPS: usize = 12;
+
+/// Files bigger than this are truncated by `read_file`.
+const MAX_READ_BYTES: usize = 200_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The model called `submit` with the full content of the output file.
+    Submitted(String),
+    /// The step budget ran out before a submission.
+    BudgetExhausted,
+}
+
+pub struct AgentRun {
+    pub outcome: Outcome,
+    pub messages: Vec<Message>,
+    pub steps: usize,
+}
+
+/// The local tools offered to the model. The tools do not write to the working tree: candidate
+/// contents are only ever compiled and tested in a scratch copy of the crate. The tests are
+/// model-written code, though, run with the file permissions of the current user; where
+/// `unshare` works, `super::cargo` runs them without network and with the API key files
+/// hidden, elsewhere only without the API keys in their environment, and kills them after
+/// `cargo::TEST_TIMEOUT`.
+pub struct Toolbox {
+    root: PathBuf,
+    output_file: String,
+    pub check: fn(&str, &str) -> Vec<String>,
+    pub test: fn(&str, &str) -> (bool, String),
+}
+
+impl Toolbox {
+    pub fn new(root: &Path, output_file: &str) -> Self {
+        Toolbox {
+            root: root.to_path_buf(),
+            output_file: output_file.to_string(),
+            check: cargo::check_candidate,
+            test: cargo::test_candidate,
+        }
+    }
+
+    pub fn specs() -> Vec<ToolSpec> {
+        let path_schema = json!({
+            "type": "object",
+            "properties": {"path": {"type": "string", "description": "Path relative to the crate root"}},
+            "required": ["path"],
+        });
+        let content_schema = json!({
+            "type": "object",
+            "properties": {"content": {"type": "string", "description": "The full content of the output file"}},
+            "required": ["content"],
+        });
+        vec![
+            ToolSpec {
+                name: "read_file",
+                description: "Read a file of the crate.",
+                parameters: path_schema,
+            },
+            ToolSpec {
+                name: "cargo_check",
+                description: "Compile the crate with the given content in place of the output file and return the compile errors of that file.",
+                parameters: content_schema.clone(),
+            },
+            ToolSpec {
+                name: "run_tests",
+                description: "Run the tests of the output file with the given content in its place and return the outcome.",
+                parameters: content_schema.clone(),
+            },
+            ToolSpec {
+                name: "submit",
+                description: "Deliver the final full content of the output file. This ends the session.",
+                parameters: content_schema,
+            },
+        ]
+    }
+
+    /// Reads a file under the crate root; absolute paths and paths escaping the root are refused.
+    pub fn read_file(&self, path: &str) -> Result<String, String> {
+        let relative = Path::new(path);
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(format!("refusing to read {}: only paths inside the crate are allowed", path));
+        }
+        let full = self.root.join(relative);
+        let canonical_root = self.root.canonicalize().map_err(|e| e.to_string())?;
+        let canonical = full.canonicalize().map_err(|e| format!("cannot read {}: {}", path, e))?;
+        if !canonical.starts_with(&canonical_root) {
+            return Err(format!("refusing to read {}: it resolves outside of the crate", path));
+        }
+        let mut content = fs::read_to_string(&canonical).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        if content.len() > MAX_READ_BYTES {
+            let mut cut = MAX_READ_BYTES;
+            while !content.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            content.truncate(cut);
+            content.push_str("\n[truncated]\n");
+        }
+        Ok(content)
+    }
+
+    /// Runs a non-final tool call and returns the text to hand back to the model.
+    fn execute(&self, call: &ToolCall) -> String {
+        let string_arg = |name: &str| call.arguments.get(name).and_then(Value::as_str);
+        match (call.name.as_str(), string_arg("path"), string_arg("content")) {
+            ("read_file", Some(path), _) => self.read_file(path).unwrap_or_else(|e| format!("error: {}", e)),
+            ("cargo_check", _, Some(content)) => {
+                let errors = (self.check)(&self.output_file, content);
+                if errors.is_empty() {
+                    "No compile errors.".to_string()
+                } else {
+                    errors.join("\n")
+                }
+            }
+            ("run_tests", _, Some(content)) => {
+                let (passed, output) = (self.test)(&self.output_file, content);
+                format!("{}\n{}", if passed { "Tests passed." } else { "Tests FAILED." }, output)
+            }
+            ("read_file" | "cargo_check" | "run_tests" | "submit", _, _) => {
+                format!("error: missing or invalid arguments for {}", call.name)
+            }
+            _ => format!("error: unknown tool {}", call.name),
+        }
+    }
+}
+
+pub struct Agent<'a> {
+    provider: &'a dyn Provider,
+    toolbox: &'a Toolbox,
+    max_steps: usize,
+    transcript_path: Option<String>,
+}
+
+impl<'a> Agent<'a> {
+    pub fn new(provider: &'a dyn Provider, toolbox: &'a Toolbox, max_steps: usize) -> Self {
+        Agent {
+            provider,
+            toolbox,
+            max_steps,
+            transcript_path: None,
+        }
+    }
+
+    /// Appends every message of the session as a JSON line to `path`.
+    pub fn with_transcript(mut self, path: &str) -> Self {
+        self.transcript_path = Some(path.to_string());
+        self
+    }
+
+    fn instructions(&self, task: &str) -> String {
+        format!(
+            "You are working inside a Rust crate, on the file {file}. You have the following tools: read_file reads a file of the crate (paths relative to the crate root), cargo_check compiles a proposed full content of {file} and returns its compile errors, run_tests runs the tests of {file} with a proposed full content, and submit delivers the final full content of {file}. Check your result with the tools before submitting it. You have at most {steps} steps, and you must finish by calling submit.\n\nThe task:\n\n{task}",
+            file = self.toolbox.output_file,
+            steps = self.max_steps,
+            task = task
+        )
+    }
+
+    fn log(&self, step: usize, message: &Message) {
+        let Some(path) = &self.transcript_path else {
+            return;
+        };
+        let line = json!({"step": step, "message": message});
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|_| panic!("Failed to open transcript file: {}", path));
+        writeln!(file, "{}", line).unwrap_or_else(|_| panic!("Failed to write transcript file: {}", path));
+    }
+
+    fn push(&self, messages: &mut Vec<Message>, step: usize, message: Message) {
+        self.log(step, &message);
+        messages.push(message);
+    }
+
+    pub fn run(&self, task: &str) -> AgentRun {
+        let tools = Toolbox::specs();
+        let mut messages = Vec::new();
+        self.push(&mut messages, 0, Message::User { content: self.instructions(task) });
+
+        for step in 1..=self.max_steps {
+            eprintln!("Agent step {}/{}: calling {}", step, self.max_steps, self.provider.name());
+            let turn: AssistantTurn = self.provider.chat(&messages, &tools);
+            let calls = turn.tool_calls.clone();
+            self.push(&mut messages, step, Message::Assistant { turn });
+
+            if calls.is_empty() {
+                eprintln!("Agent replied without calling a tool, reminding it to submit");
+                self.push(
+                    &mut messages,
+                    step,
+                    Message::User {
+                        content: "Please continue using the tools, and finish by calling submit with the full content of the file.".to_string(),
+                    },
+                );
+                continue;
+            }
+
+            for call in calls {
+                eprintln!("Agent calls tool {}", call.name);
+                if call.name == "submit"
+                    && let Some(content) = call.arguments.get("content").and_then(Value::as_str)
+                {
+                    self.push(
+                        &mut messages,
+                        step,
+                        Message::Tool {
+                            call_id: call.id.clone(),
+                            name: call.name.clone(),
+                            content: "Submitted.".to_string(),
+                        },
+                    );
+                    return AgentRun {
+                        outcome: Outcome::Submitted(content.to_string()),
+                        messages,
+                        steps: step,
+                    };
+                }
+                let result = self.toolbox.execute(&call);
+                self.push(
+                    &mut messages,
+                    step,
+                    Message::Tool {
+                        call_id: call.id,
+                        name: call.name,
+                        content: result,
+                    },
+                );
+            }
+        }
+
+        eprintln!("Agent step budget of {} exhausted", self.max_steps);
+        AgentRun {
+            outcome: Outcome::BudgetExhausted,
+            messages,
+            steps: self.max_steps,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::mock::MockProvider;
+    use tempfile::tempdir;
+
+    fn call(name: &str, arguments: Value) -> AssistantTurn {
+        AssistantTurn {
+            text: String::new(),
+            tool_calls: vec![ToolCall {
+                id: String::new(),
+                name: name.to_string(),
+                arguments,
+            }],
+        }
+    }
+
+    fn offline_toolbox(root: &Path) -> Toolbox {
+        let mut toolbox = Toolbox::new(root, "src/main.rs");
+        toolbox.check = |_, content| {
+            if content.contains("fn main") {
+                Vec::new()
+            } else {
+                vec!["error[E0601]: `main` function not found".to_string()]
+            }
+        };
+        toolbox.test = |_, _| (true, "test result: ok".to_string());
+        toolbox
+    }
+
+    #[test]
+    fn test_read_check_and_submit() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "// old\n").unwrap();
+        let transcript = dir.path().join("transcript.jsonl");
+
+        let provider = MockProvider::new(vec![
+            call("read_file", json!({"path": "src/main.rs"})),
+            call("cargo_check", json!({"content": "// nothing\n"})),
+            call("cargo_check", json!({"content": "fn main() {}\n"})),
+            call("submit", json!({"content": "fn main() {}\n"})),
+        ]);
+        let toolbox = offline_toolbox(dir.path());
+        let run = Agent::new(&provider, &toolbox, 10)
+            .with_transcript(transcript.to_str().unwrap())
+            .run("write a main");
+
+        assert_eq!(run.outcome, Outcome::Submitted("fn main() {}\n".to_string()));
+        assert_eq!(run.steps, 4);
+        let requests = provider.requests();
+        assert!(requests[1].contains("// old"));
+        assert!(requests[2].contains("E0601"));
+        assert!(requests[3].contains("No compile errors."));
+        let logged = fs::read_to_string(&transcript).unwrap();
+        assert_eq!(logged.lines().count(), run.messages.len());
+    }
+
+    #[test]
+    fn test_read_outside_crate_is_refused() {
+        let dir = tempdir().unwrap();
+        let toolbox = offline_toolbox(dir.path());
+        assert!(toolbox.read_file("../etc/passwd").is_err());
+        assert!(toolbox.read_file("/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_budget_exhausted() {
+        let dir = tempdir().unwrap();
+        let provider = MockProvider::new(vec![
+            AssistantTurn {
+                text: "thinking".to_string(),
+                tool_calls: Vec::new(),
+            },
+            call("run_tests", json!({"content": "fn main() {}\n"})),
+        ]);
+        let toolbox = offline_toolbox(dir.path());
+        let run = Agent::new(&provider, &toolbox, 2).run("write a main");
+        assert_eq!(run.outcome, Outcome::BudgetExhausted);
+        assert!(provider.requests()[1].contains("finish by calling submit"));
+    }
+}
@@ -0,0 +1,95 @@
+use reqwest::blocking::Client;
+use serde_json::{Value, json};
+
+use std::cell::OnceCell;
+
+use super::config::ProviderConfig;
+use super::error::{ErrorKind, LlmError};
+
+pub struct Anthropic {
+    client: Client,
+    key_source: ProviderConfig,
+    /// Looked up on the first request, see `api_key`.
+    api_key: OnceCell<Result<String, LlmError>>,
+    model: String,
+    url: String,
+    max_tokens: u32,
+}
+
+impl Anthropic {
+    /// `model` at the provider `provider` describes.
+    pub fn with_provider(provider: &ProviderConfig, model: &str) -> Self {
+        Anthropic {
+            client: Client::new(),
+            key_source: provider.clone(),
+            api_key: OnceCell::new(),
+            model: model.to_string(),
+            url: provider.url.clone(),
+            max_tokens: provider.max_tokens,
+        }
+    }
+
+    /// The API key, looked up on the first request. A missing or unreadable key is an auth
+    /// error, so that a fallback chain moves on to the next provider.
+    fn api_key(&self) -> Result<String, LlmError> {
+        self.api_key
+            .get_or_init(|| self.key_source.try_api_key().map_err(|e| LlmError::new(ErrorKind::Auth, e)))
+            .clone()
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn max_tokens(&self) -> u32 {
+        self.max_tokens
+    }
+
+    /// Sends `prompt` as a single user message and returns the text of the reply, or the API,
+    /// HTTP and network failures.
+    pub fn try_evaluate_with(&self, prompt: &str, temperature: Option<f32>) -> Result<String, LlmError> {
+        let mut body = json!({
+            "model": self.model,
+            "max_tokens": self.max_tokens,
+            "messages": [{"role": "user", "content": prompt}],
+        });
+        if let Some(temperature) = temperature {
+            body["temperature"] = json!(temperature);
+        }
+        let response = self.try_complete_json(&body)?;
+        let mut text = String::new();
+        for block in response["content"].as_array().into_iter().flatten() {
+            if block["type"] == "text" {
+                text.push_str(block["text"].as_str().unwrap_or_default());
+            }
+        }
+        Ok(text)
+    }
+
+    /// Posts a raw Messages API request and returns the parsed JSON reply, or the HTTP and
+    /// network failures. If the reply is not JSON or is an API error, outputs it to stderr and panics.
+    pub fn try_complete_json(&self, body: &Value) -> Result<Value, LlmError> {
+        let api_key = self.api_key()?;
+        let response = self
+            .client
+            .post(&self.url)
+            .header("x-api-key", &api_key)
+            .header("anthropic-version", "2023-06-01")
+            .json(body)
+            .send()
+            .map_err(LlmError::network)?;
+
+        let status = response.status();
+        let text = response.text().map_err(LlmError::network)?;
+        if !status.is_success() {
+            return Err(LlmError::from_status(status.as_u16(), &text));
+        }
+        match serde_json::from_str::<Value>(&text) {
+            Ok(parsed) if parsed.get("error").is_none() => Ok(parsed),
+            _ => {
+                eprintln!("{}", text);
+                panic!("Failed to parse JSON response");
+            }
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::SystemTime;
+
+use filetime::FileTime;
+
+/// Files that must not outlive an interrupted run: temporaries, drafts and the output lock.
+static TEMPORARIES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Scratch directories that must not outlive an interrupted run.
+static DIRECTORIES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Process groups running model-written code, which Ctrl-C does not reach, as they are groups
+/// of their own: killed if the process is interrupted.
+static GROUPS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Held while `write_all` renames its files into place, and by the signal handler from the
+/// moment it runs until the process exits, so an interruption never lands between two renames.
+static RENAMING: Mutex<()> = Mutex::new(());
+
+/// Waits for a `write_all` that is renaming its files, and keeps any other from renaming until
+/// the guard is dropped.
+fn hold_renames() -> MutexGuard<'static, ()> {
+    RENAMING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Marks `path` for removal if the process is interrupted.
+pub fn register(path: &str) {
+    let mut temporaries = TEMPORARIES.lock().unwrap_or_else(|e| e.into_inner());
+    if !temporaries.iter().any(|p| p == path) {
+        temporaries.push(path.to_string());
+    }
+}
+
+/// Undoes `register`, e.g. once a draft has been renamed or removed.
+pub fn forget(path: &str) {
+    TEMPORARIES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|p| p != path);
+}
+
+/// Marks the directory `path` for removal, with everything in it, if the process is interrupted.
+pub fn register_dir(path: &Path) {
+    DIRECTORIES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(path.to_path_buf());
+}
+
+/// Undoes `register_dir`, once the directory has been removed or is about to be.
+pub fn forget_dir(path: &Path) {
+    DIRECTORIES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|p| p != path);
+}
+
+/// Spawns `command`, which must be set up to lead a process group of its own, and marks the
+/// group for killing if the process is interrupted; an interruption waits for both.
+pub fn spawn_group(command: &mut Command) -> io::Result<Child> {
+    let mut groups = GROUPS.lock().unwrap_or_else(|e| e.into_inner());
+    let child = command.spawn()?;
+    groups.push(child.id());
+    Ok(child)
+}
+
+/// Undoes `spawn_group`, once the group has been killed.
+pub fn forget_group(pgid: u32) {
+    GROUPS.lock().unwrap_or_else(|e| e.into_inner()).retain(|g| *g != pgid);
+}
+
+/// Kills the process group `pgid`, with everything in it.
+pub fn kill_group(pgid: u32) {
+    let _ = Command::new("kill")
+        .args(["-KILL", "--", &format!("-{}", pgid)])
+        .stderr(Stdio::null())
+        .status();
+}
+
+/// Kills all registered process groups, then removes all registered files and directories.
+pub fn cleanup() {
+    let groups = std::mem::take(&mut *GROUPS.lock().unwrap_or_else(|e| e.into_inner()));
+    for pgid in groups {
+        kill_group(pgid);
+        eprintln!("Killed process group {}", pgid);
+    }
+    let temporaries = std::mem::take(&mut *TEMPORARIES.lock().unwrap_or_else(|e| e.into_inner()));
+    for path in temporaries {
+        if fs::remove_file(&path).is_ok() {
+            eprintln!("Removed {}", path);
+        }
+    }
+    let directories = std::mem::take(&mut *DIRECTORIES.lock().unwrap_or_else(|e| e.into_inner()));
+    for path in directories {
+        if fs::remove_dir_all(&path).is_ok() {
+            eprintln!("Removed {}", path.display());
+        }
+    }
+}
+
+/// Starts a thread that, on Ctrl-C or SIGTERM, kills the registered process groups, removes
+/// the registered files and directories, and exits with 130.
+/// Nothing is renamed over an output file before it is complete, so the output stays as it was;
+/// a `write_all` that is renaming its files is waited for, so the set is replaced as a whole.
+pub fn install_signal_cleanup() {
+    thread::spawn(|| {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to start signal handling runtime");
+        runtime.block_on(async {
+            let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("Failed to install SIGTERM handler");
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+        });
+        let _renaming = hold_renames();
+        eprintln!("Interrupted, stopping model-written code and removing temporary files");
+        cleanup();
+        std::process::exit(130);
+    });
+}
+
+/// Writes `content` to "<path>.tmp", syncs it and renames it over `path`, so that readers
+/// see either the old or the new content, never a partial one.
+pub fn write(path: &str, content: impl AsRef<[u8]>) -> io::Result<()> {
+    let temp_path = format!("{}.tmp", path);
+    register(&temp_path);
+    let result = write_temporary(&temp_path, path, content.as_ref()).and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    forget(&temp_path);
+    result
+}
+
+/// Writes and syncs the temporary file that will replace `path`, with the permissions of `path`.
+fn write_temporary(temp_path: &str, path: &str, content: &[u8]) -> io::Result<()> {
+    let mut file = File::create(temp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(temp_path, metadata.permissions())?;
+    }
+    Ok(())
+}
+
+/// Like `write`, for several files that only make sense together: every temporary file is
+/// written before any is renamed, and if a rename fails, the files already replaced get their
+/// previous content back, or are removed if they did not exist. An interruption waits for the
+/// renames to finish.
+pub fn write_all(files: &[(String, String)]) -> io::Result<()> {
+    let previous: Vec<Option<Vec<u8>>> = files.iter().map(|(path, _)| fs::read(path).ok()).collect();
+    let temp_paths: Vec<String> = files.iter().map(|(path, _)| format!("{}.tmp", path)).collect();
+    let discard = |temp_paths: &[String]| {
+        for temp_path in temp_paths {
+            let _ = fs::remove_file(temp_path);
+            forget(temp_path);
+        }
+    };
+
+    for ((path, content), temp_path) in files.iter().zip(&temp_paths) {
+        register(temp_path);
+        let written = match Path::new(path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => fs::create_dir_all(parent),
+            _ => Ok(()),
+        }
+        .and_then(|_| write_temporary(temp_path, path, content.as_bytes()));
+        if let Err(e) = written {
+            discard(&temp_paths);
+            return Err(e);
+        }
+    }
+
+    let _renaming = hold_renames();
+    for (index, ((path, _), temp_path)) in files.iter().zip(&temp_paths).enumerate() {
+        if let Err(e) = fs::rename(temp_path, path) {
+            for ((replaced, _), content) in files[..index].iter().zip(&previous) {
+                let _ = match content {
+                    Some(content) => write(replaced, content),
+                    None => fs::remove_file(replaced),
+                };
+            }
+            discard(&temp_paths[index..]);
+            return Err(e);
+        }
+        forget(temp_path);
+    }
+    Ok(())
+}
+
+/// Updates the mtime of `path` without touching its content.
+pub fn touch(path: &str) -> io::Result<()> {
+    filetime::set_file_mtime(path, FileTime::from_system_time(SystemTime::now()))
+}
+
+/// "<output_file>.lock", held while a driver works on the output file, so that two
+/// `make -j` jobs never process the same target. The lock is an advisory lock of the OS on
+/// the open file, so it goes away with the process that holds it; the file is removed when
+/// dropped, while still locked.
+pub struct OutputLock {
+    path: String,
+    _file: File,
+}
+
+/// Whether `path` still names the open `file`: a holder removes the lock file before it
+/// unlocks it, so a lock taken on a file that has since been removed is worth nothing.
+fn is_current(file: &File, path: &str) -> bool {
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(open), Ok(named)) => open.dev() == named.dev() && open.ino() == named.ino(),
+        _ => false,
+    }
+}
+
+impl OutputLock {
+    /// Takes the lock, without waiting. Fails with a message naming the holder if another
+    /// process has it. A lock file left behind by a process that no longer runs is not locked,
+    /// so it is simply taken over. Creates the directory of an output file that does not
+    /// exist yet.
+    pub fn acquire(output_file: &str) -> Result<Self, String> {
+        let path = format!("{}.lock", output_file);
+        if let Some(parent) = Path::new(&path).parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .map_err(|e| format!("Failed to open {}: {}", path, e))?;
+            match file.try_lock() {
+                Ok(()) if is_current(&file, &path) => {
+                    file.set_len(0)
+                        .and_then(|_| write!(file, "{}", std::process::id()))
+                        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+                    register(&path);
+                    return Ok(OutputLock { path, _file: file });
+                }
+                // Removed by its holder between our open and our lock: try the new one.
+                Ok(()) => continue,
+                Err(TryLockError::WouldBlock) => {
+                    let holder = fs::read_to_string(&path)
+                        .ok()
+                        .and_then(|p| p.trim().parse::<u32>().ok())
+                        .map(|pid| format!("process {}", pid))
+                        .unwrap_or_else(|| "another process".to_string());
+                    return Err(format!("{} is being processed by {} ({})", output_file, holder, path));
+                }
+                Err(TryLockError::Error(e)) => return Err(format!("Failed to lock {}: {}", path, e)),
+            }
+        }
+    }
+
+    /// Removes the lock before the process exits without unwinding.
+    pub fn release(self) {}
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        forget(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_replaces_content() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.rs").to_string_lossy().into_owned();
+        fs::write(&path, "old").unwrap();
+        write(&path, "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+    }
+
+    #[test]
+    fn test_write_all_rolls_back() {
+        let dir = tempdir().unwrap();
+        let first = dir.path().join("a.rs").to_string_lossy().into_owned();
+        let second = dir.path().join("tests/b.rs").to_string_lossy().into_owned();
+        fs::write(&first, "old").unwrap();
+        write_all(&[(first.clone(), "new a".to_string()), (second.clone(), "new b".to_string())]).unwrap();
+        assert_eq!(fs::read_to_string(&first).unwrap(), "new a");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "new b");
+
+        // A directory in the way of the last file makes its rename fail.
+        let blocked = dir.path().join("c.rs");
+        fs::create_dir_all(blocked.join("inside")).unwrap();
+        let blocked = blocked.to_string_lossy().into_owned();
+        let fresh = dir.path().join("d.rs").to_string_lossy().into_owned();
+        let result = write_all(&[
+            (first.clone(), "newer a".to_string()),
+            (fresh.clone(), "d".to_string()),
+            (blocked.clone(), "c".to_string()),
+        ]);
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&first).unwrap(), "new a");
+        assert!(!Path::new(&fresh).exists());
+        assert!(!Path::new(&format!("{}.tmp", blocked)).exists());
+    }
+
+    const SET_SIZE: usize = 100;
+
+    /// Rewrites a set of files in `dir` as a whole, over and over, until `stop` is set.
+    fn rewrite_set_until(dir: &Path, stop: &AtomicBool) {
+        let paths: Vec<String> = (0..SET_SIZE)
+            .map(|i| dir.join(format!("file{}.rs", i)).to_string_lossy().into_owned())
+            .collect();
+        for round in 0.. {
+            if stop.load(Ordering::SeqCst) {
+                return;
+            }
+            let files: Vec<(String, String)> = paths.iter().map(|p| (p.clone(), round.to_string())).collect();
+            write_all(&files).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_interruption_waits_for_renames() {
+        let dir = tempdir().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (dir, stop) = (dir.path().to_path_buf(), stop.clone());
+            thread::spawn(move || rewrite_set_until(&dir, &stop))
+        };
+        let (first, last) = (dir.path().join("file0.rs"), dir.path().join(format!("file{}.rs", SET_SIZE - 1)));
+        loop {
+            let (Ok(first), Ok(last)) = (fs::read_to_string(&first), fs::read_to_string(&last)) else {
+                continue;
+            };
+            if first != last {
+                break;
+            }
+        }
+
+        // Caught halfway through the renames, as the signal handler would be.
+        let renaming = hold_renames();
+        let contents: Vec<String> = (0..SET_SIZE)
+            .map(|i| fs::read_to_string(dir.path().join(format!("file{}.rs", i))).unwrap())
+            .collect();
+        assert!(contents.iter().all(|c| c == &contents[0]), "mixed set: {:?}", contents);
+        stop.store(true, Ordering::SeqCst);
+        drop(renaming);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("out.rs").to_string_lossy().into_owned();
+        let lock_path = format!("{}.lock", output);
+        let lock = OutputLock::acquire(&output).unwrap();
+        assert_eq!(fs::read_to_string(&lock_path).unwrap(), std::process::id().to_string());
+        assert!(OutputLock::acquire(&output).is_err());
+        lock.release();
+        assert!(!Path::new(&lock_path).exists());
+
+        // A lock file left behind by a process that is gone is not locked, and is taken over.
+        fs::write(&lock_path, "999999999").unwrap();
+        assert!(OutputLock::acquire(&output).is_ok());
+
+        // A holder that has not written its process id yet still holds the lock, and its lock
+        // file is left alone.
+        let holder = File::create(&lock_path).unwrap();
+        holder.lock().unwrap();
+        let error = OutputLock::acquire(&output).err().unwrap();
+        assert!(error.contains("another process"), "{}", error);
+        assert!(Path::new(&lock_path).exists());
+    }
+
+    #[test]
+    fn test_lock_under_contention() {
+        let dir = tempdir().unwrap();
+        let output = Arc::new(dir.path().join("out.rs").to_string_lossy().into_owned());
+        let holders = Arc::new(AtomicUsize::new(0));
+        let taken = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (output, holders, taken) = (output.clone(), holders.clone(), taken.clone());
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        let Ok(lock) = OutputLock::acquire(&output) else {
+                            continue;
+                        };
+                        assert_eq!(holders.fetch_add(1, Ordering::SeqCst), 0, "two holders of the lock");
+                        taken.fetch_add(1, Ordering::SeqCst);
+                        thread::yield_now();
+                        holders.fetch_sub(1, Ordering::SeqCst);
+                        lock.release();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(taken.load(Ordering::SeqCst) > 0);
+        assert!(!Path::new(&format!("{}.lock", output)).exists());
+    }
+}
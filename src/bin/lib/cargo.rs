@@ -0,0 +1,831 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::OnceLock;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+use tempfile::TempDir;
+
+/// Entries of the crate root that are copied into a scratch crate.
+const CRATE_ENTRIES: &[&str] = &["Cargo.toml", "Cargo.lock", "build.rs", "src", "tests", "benches", "examples"];
+
+/// How long the tests of a candidate, or a check command run on it, may take, compilation
+/// included, before they are killed and count as failed.
+pub const TEST_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// The environment variables that model-written code gets to see, besides the "CARGO_" and
+/// "RUSTUP_" ones that are not tokens, and HOME, TMPDIR, CARGO_HOME and RUSTUP_HOME, which
+/// `sandboxed` sets: what cargo and the toolchain need, and no API keys.
+const KEPT_ENV: &[&str] = &[
+    "PATH",
+    "USER",
+    "LANG",
+    "LC_ALL",
+    "TERM",
+    "RUSTFLAGS",
+    "RUSTDOCFLAGS",
+    "RUST_BACKTRACE",
+    "RUST_TEST_THREADS",
+];
+
+/// `unshare` options putting model-written code in user, mount and network namespaces of its
+/// own: it keeps the user's permissions on the file system, but has no network, and the mount
+/// namespace lets `HIDE_SCRIPT` cover files for it alone.
+const UNSHARE: &[&str] = &["--user", "--map-root-user", "--net", "--mount", "--fork", "--"];
+
+/// Run with "sh -c" inside the namespaces, with the files to hide, "--", and the command as
+/// arguments: mounts /dev/null over each of the files, then runs the command.
+const HIDE_SCRIPT: &str =
+    "while [ \"$1\" != -- ]; do mount --bind /dev/null \"$1\" || exit 125; shift; done; shift; exec \"$@\"";
+
+/// Errors and warnings reported by the compiler for one file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Runs `cargo check --message-format json` in the crate at `crate_dir`, optionally with a
+/// separate target directory, and returns the errors for `source_file` only.
+pub fn run_cargo_check_in(crate_dir: &Path, target_dir: Option<&Path>, source_file: &str) -> Vec<String> {
+    run_cargo_check_files_in(crate_dir, target_dir, &[source_file])
+}
+
+/// Like `run_cargo_check_in`, but returns the errors for all of `source_files`, from a single
+/// `cargo check`.
+pub fn run_cargo_check_files_in(crate_dir: &Path, target_dir: Option<&Path>, source_files: &[&str]) -> Vec<String> {
+    run_cargo_check_dependents_in(crate_dir, target_dir, source_files, &[]).0
+}
+
+/// The errors for `source_files` in the crate in the current directory, and the errors in
+/// `dependents`, the files using `source_files`, each with the file it is in, so that what a change breaks elsewhere shows.
+pub fn run_cargo_check_dependents(source_files: &[&str], dependents: &[&str]) -> (Vec<String>, Vec<(String, String)>) {
+    run_cargo_check_dependents_in(Path::new("."), None, source_files, dependents)
+}
+
+/// `run_cargo_check_dependents` in the crate at `crate_dir`.
+pub fn run_cargo_check_dependents_in(
+    crate_dir: &Path,
+    target_dir: Option<&Path>,
+    source_files: &[&str],
+    dependents: &[&str],
+) -> (Vec<String>, Vec<(String, String)>) {
+    let mut checked = source_files.to_vec();
+    checked.extend(dependents);
+    let stdout = cargo_check_output(crate_dir, target_dir, &checked);
+    let mut errors: Vec<String> = source_files
+        .iter()
+        .flat_map(|source_file| messages_for_file(&stdout, source_file, "error"))
+        .collect();
+    if errors.len() > 20 {
+        errors.truncate(20);
+    }
+    let dependent_errors = dependents
+        .iter()
+        .flat_map(|dependent| {
+            messages_for_file(&stdout, dependent, "error")
+                .into_iter()
+                .map(|error| (dependent.to_string(), error))
+        })
+        .collect();
+    (errors, dependent_errors)
+}
+
+/// Like `run_cargo_check_in`, but returns all the errors and warnings for the file.
+pub fn run_cargo_diagnostics_in(crate_dir: &Path, target_dir: Option<&Path>, source_file: &str) -> Diagnostics {
+    let stdout = cargo_check_output(crate_dir, target_dir, &[source_file]);
+    Diagnostics {
+        errors: messages_for_file(&stdout, source_file, "error"),
+        warnings: messages_for_file(&stdout, source_file, "warning"),
+    }
+}
+
+/// The cargo target selection that builds `source_file`: plain `cargo check` leaves out the
+/// integration tests, benchmarks and examples.
+fn target_args(source_file: &str) -> &'static [&'static str] {
+    let relative = crate_relative(source_file);
+    if relative.starts_with("tests/") {
+        &["--tests"]
+    } else if relative.starts_with("benches/") {
+        &["--benches"]
+    } else if relative.starts_with("examples/") {
+        &["--examples"]
+    } else {
+        &[]
+    }
+}
+
+fn cargo_check_output(crate_dir: &Path, target_dir: Option<&Path>, source_files: &[&str]) -> String {
+    let mut targets: Vec<&str> = source_files.iter().flat_map(|f| target_args(f)).copied().collect();
+    targets.dedup();
+    cargo_check_json(crate_dir, target_dir, &targets, source_files)
+}
+
+/// Every error of `cargo check --all-targets` in the crate at `crate_dir`, with the file it is
+/// in ("" if it has none).
+pub fn run_cargo_check_crate_in(crate_dir: &Path, target_dir: Option<&Path>) -> Vec<(String, String)> {
+    let stdout = cargo_check_json(crate_dir, target_dir, &["--all-targets"], &[]);
+    stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|json| json.get("reason").and_then(|r| r.as_str()) == Some("compiler-message"))
+        .filter_map(|json| {
+            let message = json.get("message")?;
+            if message.get("level").and_then(|l| l.as_str()) != Some("error") {
+                return None;
+            }
+            let spans = message.get("spans").and_then(|s| s.as_array());
+            let span = spans.and_then(|spans| {
+                spans
+                    .iter()
+                    .find(|span| span.get("is_primary").and_then(|p| p.as_bool()) == Some(true))
+                    .or_else(|| spans.first())
+            });
+            let file = span
+                .and_then(|span| span.get("file_name")?.as_str())
+                .unwrap_or_default()
+                .to_string();
+            let text = message
+                .get("rendered")
+                .or_else(|| message.get("message"))
+                .and_then(|m| m.as_str())?
+                .to_string();
+            Some((file, text))
+        })
+        .collect()
+}
+
+/// `run_cargo_check_crate_in` for the crate in the current directory.
+pub fn run_cargo_check_crate() -> Vec<(String, String)> {
+    run_cargo_check_crate_in(Path::new("."), None)
+}
+
+/// The errors of `after`, from `run_cargo_check_crate`, in the files that have more errors than
+/// in `before`: what a change broke, as the messages of the files it did not touch stay the same
+/// but the line numbers of the ones it touched do not.
+pub fn new_errors(before: &[(String, String)], after: &[(String, String)]) -> Vec<(String, String)> {
+    let count = |errors: &[(String, String)], file: &str| errors.iter().filter(|(f, _)| f == file).count();
+    after
+        .iter()
+        .filter(|(file, _)| count(after, file) > count(before, file))
+        .cloned()
+        .collect()
+}
+
+/// Runs `cargo check` of `checked`. A separate target directory is that of a scratch crate,
+/// holding model-written code whose build scripts and procedural macros the check runs, so then
+/// it runs `sandboxed` and is killed after `TEST_TIMEOUT`.
+fn cargo_check_json(crate_dir: &Path, target_dir: Option<&Path>, extra_args: &[&str], checked: &[&str]) -> String {
+    let mut args = vec!["check", "--message-format", "json"];
+    args.extend(extra_args);
+    let Some(target_dir) = target_dir else {
+        let output = Command::new("cargo")
+            .args(&args)
+            .current_dir(crate_dir)
+            .output()
+            .expect("Failed to execute cargo check command");
+        return String::from_utf8(output.stdout).expect("Failed to convert cargo output to UTF-8");
+    };
+    let mut sandbox = sandboxed("cargo", &args).unwrap_or_else(|e| panic!("{}", e));
+    sandbox
+        .command
+        .current_dir(crate_dir)
+        .env("CARGO_TARGET_DIR", target_dir);
+    let (status, stdout, _) = run_until(&mut sandbox.command, TEST_TIMEOUT).expect("Failed to execute cargo check command");
+    let stdout = String::from_utf8(stdout).expect("Failed to convert cargo output to UTF-8");
+    match status {
+        Some(_) => stdout,
+        None => killed_check(&stdout, checked),
+    }
+}
+
+/// The output of a `cargo check` of `checked` that was killed: its complete lines, and an error
+/// in each of the files (one in no file if there are none), so that the code does not count as
+/// compiling.
+fn killed_check(stdout: &str, checked: &[&str]) -> String {
+    let mut output = match stdout.rfind('\n') {
+        Some(end) => stdout[..=end].to_string(),
+        None => String::new(),
+    };
+    let text = format!("cargo check killed after {:?}", TEST_TIMEOUT);
+    let spans: Vec<Value> = checked
+        .iter()
+        .map(|file| serde_json::json!([{ "file_name": file, "is_primary": true }]))
+        .collect();
+    for spans in if spans.is_empty() { vec![serde_json::json!([])] } else { spans } {
+        let message = serde_json::json!({
+            "reason": "compiler-message",
+            "message": { "level": "error", "message": text, "rendered": format!("error: {}\n", text), "spans": spans },
+        });
+        output.push_str(&format!("{}\n", message));
+    }
+    output
+}
+
+/// Extracts the messages of the given level ("error" or "warning") relating to `source_file`
+/// from `cargo --message-format json` output.
+fn messages_for_file(stdout: &str, source_file: &str, level: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    let source_path = Path::new(source_file);
+
+    for line in stdout.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let json: Value = serde_json::from_str(line).expect("Failed to parse JSON output from cargo");
+        if json.get("reason").and_then(|r| r.as_str()) != Some("compiler-message") {
+            continue;
+        }
+        let Some(message) = json.get("message") else {
+            continue;
+        };
+        if message.get("level").and_then(|l| l.as_str()) != Some(level) {
+            continue;
+        }
+        let Some(spans) = message.get("spans").and_then(|s| s.as_array()) else {
+            continue;
+        };
+        let matches = spans.iter().any(|span| {
+            span.get("file_name")
+                .and_then(|f| f.as_str())
+                .map(|f| {
+                    let span_path = Path::new(f);
+                    span_path == source_path || span_path.file_name() == source_path.file_name()
+                })
+                .unwrap_or(false)
+        });
+        if !matches {
+            continue;
+        }
+        if let Some(rendered) = message.get("rendered").and_then(|r| r.as_str()) {
+            errors.push(rendered.to_string());
+        } else if let Some(msg_text) = message.get("message").and_then(|m| m.as_str()) {
+            errors.push(msg_text.to_string());
+        }
+    }
+    errors
+}
+
+/// Builds the binary `name` of the crate in the current directory and returns the path cargo
+/// reports for it.
+pub fn build_binary(name: &str) -> Result<PathBuf, String> {
+    let output = Command::new("cargo")
+        .args(["build", "--quiet", "--message-format", "json", "--bin", name])
+        .output()
+        .map_err(|e| format!("Failed to execute cargo build: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("failed to build {}", name));
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .find_map(|message| message.get("executable")?.as_str().map(PathBuf::from))
+        .ok_or_else(|| format!("cargo build did not report the binary of {}", name))
+}
+
+/// Returns `path` relative to the current directory (the crate root), as cargo reports it.
+pub fn crate_relative(path: &str) -> String {
+    let current = env::current_dir().expect("Failed to get current directory");
+    Path::new(path)
+        .strip_prefix(&current)
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.trim_start_matches("./").to_string())
+}
+
+/// A throwaway copy of the crate, used to compile candidates without touching the working tree.
+pub struct Scratch {
+    dir: TempDir,
+    target_dir: PathBuf,
+}
+
+impl Scratch {
+    /// Copies the crate in the current directory into a temporary directory below the configured
+    /// scratch directory. Build artifacts go to `target/llm-scratch` of the real crate, so
+    /// dependencies are only compiled once.
+    pub fn new() -> io::Result<Self> {
+        let root = env::current_dir()?;
+        let dir = tempfile::Builder::new()
+            .prefix("llm-scratch-")
+            .tempdir_in(&super::config::project().scratch_dir)?;
+        super::atomic::register_dir(dir.path());
+        let scratch = Scratch {
+            dir,
+            target_dir: root.join("target").join("llm-scratch"),
+        };
+        for entry in CRATE_ENTRIES {
+            let source = root.join(entry);
+            if source.exists() {
+                copy_recursive(&source, &scratch.dir.path().join(entry))?;
+            }
+        }
+        Ok(scratch)
+    }
+
+    /// Replaces the file at the crate-relative `source_file` with `content`. A new module is
+    /// declared in the generated mod.rs beside it, so that it is compiled.
+    pub fn write(&self, source_file: &str, content: &str) -> io::Result<()> {
+        let path = self.dir.path().join(crate_relative(source_file));
+        let Some(parent) = path.parent() else {
+            return fs::write(path, content);
+        };
+        fs::create_dir_all(parent)?;
+        fs::write(&path, content)?;
+        super::modules::refresh(parent).map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    /// Writes every (source file, content) pair of `files`.
+    pub fn write_all(&self, files: &[(String, String)]) -> io::Result<()> {
+        files.iter().try_for_each(|(source_file, content)| self.write(source_file, content))
+    }
+
+    /// `run_cargo_check_in` for the scratch crate.
+    pub fn check(&self, source_file: &str) -> Vec<String> {
+        run_cargo_check_in(self.dir.path(), Some(&self.target_dir), &crate_relative(source_file))
+    }
+
+    /// `run_cargo_check_dependents_in` for the scratch crate.
+    pub fn check_dependents(&self, source_files: &[&str], dependents: &[&str]) -> (Vec<String>, Vec<(String, String)>) {
+        let relative: Vec<String> = source_files.iter().map(|f| crate_relative(f)).collect();
+        let relative: Vec<&str> = relative.iter().map(String::as_str).collect();
+        run_cargo_check_dependents_in(self.dir.path(), Some(&self.target_dir), &relative, dependents)
+    }
+
+    /// `run_cargo_diagnostics_in` for the scratch crate.
+    pub fn diagnostics(&self, source_file: &str) -> Diagnostics {
+        run_cargo_diagnostics_in(self.dir.path(), Some(&self.target_dir), &crate_relative(source_file))
+    }
+
+    /// `run_cargo_test_in` for the scratch crate.
+    pub fn test(&self, source_file: &str) -> (bool, String) {
+        run_cargo_test_in(self.dir.path(), Some(&self.target_dir), source_file)
+    }
+
+    /// Runs the shell `command` in the scratch crate, with "{file}" replaced by the
+    /// crate-relative `source_file`, `sandboxed` and killed after `TEST_TIMEOUT`. Returns
+    /// whether it succeeded, and the tail of its output.
+    pub fn run(&self, command: &str, source_file: &str) -> (bool, String) {
+        let command = command.replace("{file}", &crate_relative(source_file));
+        let mut shell = executing("sh", &["-c", &command]).unwrap_or_else(|e| panic!("{}", e));
+        shell
+            .command
+            .current_dir(self.dir.path())
+            .env("CARGO_TARGET_DIR", &self.target_dir);
+        run_with_timeout(&mut shell.command, TEST_TIMEOUT)
+            .unwrap_or_else(|_| panic!("Failed to execute check command: {}", command))
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        super::atomic::forget_dir(self.dir.path());
+    }
+}
+
+/// The last 4000 bytes of `text`.
+fn tail(mut text: String) -> String {
+    if text.len() > 4000 {
+        let mut cut = text.len() - 4000;
+        while !text.is_char_boundary(cut) {
+            cut += 1;
+        }
+        text = text[cut..].to_string();
+    }
+    text
+}
+
+/// The `cargo test` arguments that run the tests of `source_file` in the crate at `crate_dir`:
+/// the binary itself for `src/bin/<name>.rs`, the module's tests inside the first binary
+/// that declares `mod lib;` for `src/bin/lib/...`, and the test, benchmark or example itself
+/// for `tests/<name>.rs`, `benches/<name>.rs` and `examples/<name>.rs`.
+fn test_args(crate_dir: &Path, source_file: &str) -> Vec<String> {
+    let relative = crate_relative(source_file);
+    for (dir, flag) in [("tests/", "--test"), ("benches/", "--bench"), ("examples/", "--example")] {
+        if let Some(name) = relative.strip_prefix(dir).and_then(|p| p.strip_suffix(".rs")) {
+            return vec!["test".to_string(), flag.to_string(), name.to_string()];
+        }
+    }
+    let Some(module_path) = relative.strip_prefix("src/bin/").and_then(|p| p.strip_suffix(".rs")) else {
+        return vec!["test".to_string()];
+    };
+    if !module_path.starts_with("lib/") {
+        return vec!["test".to_string(), "--bin".to_string(), module_path.to_string()];
+    }
+
+    let mut bins: Vec<PathBuf> = fs::read_dir(crate_dir.join("src/bin"))
+        .map(|entries| entries.filter_map(Result::ok).map(|e| e.path()).collect())
+        .unwrap_or_default();
+    bins.sort();
+    let host = bins.iter().find(|path| {
+        path.extension().and_then(|e| e.to_str()) == Some("rs")
+            && fs::read_to_string(path)
+                .map(|c| c.lines().any(|l| l.trim() == "mod lib;"))
+                .unwrap_or(false)
+    });
+    match host.and_then(|p| p.file_stem()).and_then(|s| s.to_str()) {
+        Some(bin) => vec![
+            "test".to_string(),
+            "--bin".to_string(),
+            bin.to_string(),
+            "--".to_string(),
+            format!("{}::", module_path.replace('/', "::")),
+        ],
+        None => vec!["test".to_string()],
+    }
+}
+
+/// Runs the tests of `source_file` in the crate at `crate_dir`, `sandboxed` and killed after
+/// `TEST_TIMEOUT`. Returns whether they passed, and the tail of the combined output.
+pub fn run_cargo_test_in(crate_dir: &Path, target_dir: Option<&Path>, source_file: &str) -> (bool, String) {
+    let args = test_args(crate_dir, source_file);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let mut sandbox = executing("cargo", &args).unwrap_or_else(|e| panic!("{}", e));
+    sandbox.command.current_dir(crate_dir);
+    if let Some(target_dir) = target_dir {
+        sandbox.command.env("CARGO_TARGET_DIR", target_dir);
+    }
+    run_with_timeout(&mut sandbox.command, TEST_TIMEOUT).expect("Failed to execute cargo test command")
+}
+
+/// How model-written code is kept away from the machine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Isolation {
+    /// In the namespaces of `UNSHARE`: no network, and `hidden_files` covered.
+    Namespaces,
+    /// The namespaces are not available: only the environment is cleaned, and the code has the
+    /// network and every file the user can read.
+    Environment,
+}
+
+/// The isolation this machine allows, tried once per process.
+fn isolation() -> Isolation {
+    static ISOLATION: OnceLock<Isolation> = OnceLock::new();
+    *ISOLATION.get_or_init(|| {
+        let works = Command::new("unshare")
+            .args(UNSHARE)
+            .args(["sh", "-c", HIDE_SCRIPT, "sh"])
+            .args(hidden_files())
+            .args(["--", "true"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if works { Isolation::Namespaces } else { Isolation::Environment }
+    })
+}
+
+/// The files that model-written code must not read: the API key files of the providers and
+/// the registry credentials of cargo, those that exist.
+fn hidden_files() -> Vec<PathBuf> {
+    let cargo_home = cargo_home();
+    let mut files: Vec<PathBuf> = super::config::all_providers()
+        .iter()
+        .filter_map(|provider| provider.api_key_path())
+        .chain([cargo_home.join("credentials.toml"), cargo_home.join("credentials")])
+        .filter(|path| path.is_file())
+        .filter_map(|path| path.canonicalize().ok())
+        .collect();
+    files.sort();
+    files.dedup();
+    files
+}
+
+/// Why model-written code can not be run on this machine, if it can not: without namespaces
+/// it could read the API key files of the providers. Compiling it, its build scripts and
+/// procedural macros included, goes on with only the environment cleaned.
+pub fn isolation_error() -> Option<String> {
+    if isolation() == Isolation::Namespaces {
+        return None;
+    }
+    let key_files: Vec<String> = super::config::all_providers()
+        .iter()
+        .filter_map(|provider| provider.api_key_file.clone())
+        .collect();
+    if key_files.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "Refusing to run model-written code: \"unshare\" can not isolate it here, and it could read the API key file {}",
+        key_files.join(", ")
+    ))
+}
+
+/// Whether model-written code runs without the network and without access to the API key files.
+pub fn is_isolated() -> bool {
+    isolation() == Isolation::Namespaces
+}
+
+fn home_dir(variable: &str, default: &str) -> PathBuf {
+    env::var_os(variable)
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(default)))
+        .unwrap_or_else(|| PathBuf::from(default))
+}
+
+fn cargo_home() -> PathBuf {
+    home_dir("CARGO_HOME", ".cargo")
+}
+
+/// A command prepared by `sandboxed`, with the scratch home directory it runs in.
+struct Sandboxed {
+    command: Command,
+    _home: TempDir,
+}
+
+impl Drop for Sandboxed {
+    fn drop(&mut self) {
+        super::atomic::forget_dir(self._home.path());
+    }
+}
+
+/// Prepares `program` with `args` for running model-written code, its tests, build scripts and
+/// check commands. It runs in the namespaces of `UNSHARE`, without the network and with
+/// `hidden_files` covered by /dev/null, when the machine allows it. Its HOME and TMPDIR are an
+/// empty scratch directory, CARGO_HOME and RUSTUP_HOME those of the user, set explicitly, and
+/// cargo works offline. The environment is cleared down to `KEPT_ENV`, without the
+/// `api_key_env` variable of any provider. It is put in a process group of its own, so that
+/// `run_with_timeout` can kill whatever it starts. Its home directory is removed if the
+/// process is interrupted (`super::atomic::register_dir`). It still runs with the permissions
+/// of the user on the rest of the file system.
+fn sandboxed(program: &str, args: &[&str]) -> io::Result<Sandboxed> {
+    sandboxed_with(program, args, env::vars())
+}
+
+/// `sandboxed` for running model-written code itself, its tests or a check command: fails with
+/// `isolation_error` when that says so.
+fn executing(program: &str, args: &[&str]) -> io::Result<Sandboxed> {
+    if let Some(error) = isolation_error() {
+        return Err(io::Error::other(error));
+    }
+    sandboxed(program, args)
+}
+
+/// `sandboxed` with `vars` in place of the environment of this process.
+fn sandboxed_with(
+    program: &str,
+    args: &[&str],
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> io::Result<Sandboxed> {
+    let home = tempfile::Builder::new()
+        .prefix("llm-home-")
+        .tempdir_in(&super::config::project().scratch_dir)?;
+    super::atomic::register_dir(home.path());
+    let mut command = match isolation() {
+        Isolation::Namespaces => {
+            let mut command = Command::new("unshare");
+            command
+                .args(UNSHARE)
+                .args(["sh", "-c", HIDE_SCRIPT, "sh"])
+                .args(hidden_files())
+                .arg("--")
+                .arg(program)
+                .args(args);
+            command
+        }
+        Isolation::Environment => {
+            let mut command = Command::new(program);
+            command.args(args);
+            command
+        }
+    };
+    command.env_clear();
+    for (name, value) in vars {
+        let toolchain = (name.starts_with("CARGO_") || name.starts_with("RUSTUP_")) && !name.contains("TOKEN");
+        if KEPT_ENV.contains(&name.as_str()) || toolchain {
+            command.env(name, value);
+        }
+    }
+    for provider in super::config::all_providers() {
+        if let Some(name) = &provider.api_key_env {
+            command.env_remove(name);
+        }
+    }
+    command
+        .env("HOME", home.path())
+        .env("TMPDIR", home.path())
+        .env("CARGO_HOME", cargo_home())
+        .env("RUSTUP_HOME", home_dir("RUSTUP_HOME", ".rustup"))
+        .env("CARGO_NET_OFFLINE", "true");
+    command.process_group(0);
+    Ok(Sandboxed { command, _home: home })
+}
+
+fn drain(mut pipe: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        let _ = pipe.read_to_end(&mut buffer);
+        buffer
+    })
+}
+
+/// Runs `command`, started by `sandboxed`, and kills its process group once it has run for
+/// `timeout`, or once it exits, or if the process is interrupted, so that nothing it started
+/// outlives it. Returns whether it
+/// succeeded in time and the tail of its stderr followed by its stdout.
+fn run_with_timeout(command: &mut Command, timeout: Duration) -> io::Result<(bool, String)> {
+    let (status, stdout, stderr) = run_until(command, timeout)?;
+    let mut text = String::from_utf8_lossy(&stderr).into_owned();
+    text.push_str(&String::from_utf8_lossy(&stdout));
+    if status.is_none() {
+        text.push_str(&format!("\nKilled after {:?}\n", timeout));
+    }
+    Ok((status.is_some_and(|s| s.success()), tail(text)))
+}
+
+/// `run_with_timeout` returning the exit status, none if it was killed, and the whole stdout
+/// and stderr.
+fn run_until(command: &mut Command, timeout: Duration) -> io::Result<(Option<ExitStatus>, Vec<u8>, Vec<u8>)> {
+    command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = super::atomic::spawn_group(command)?;
+    let stdout = child.stdout.take().map(drain);
+    let stderr = child.stderr.take().map(drain);
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Ok(Some(status)),
+            Ok(None) if started.elapsed() >= timeout => {
+                eprintln!("Killing process {} after {:?}", child.id(), timeout);
+                break Ok(None);
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(100)),
+            Err(e) => break Err(e),
+        }
+    };
+    super::atomic::kill_group(child.id());
+    super::atomic::forget_group(child.id());
+    let _ = child.wait();
+    let status = status?;
+
+    let collect = |handle: Option<JoinHandle<Vec<u8>>>| handle.and_then(|h| h.join().ok()).unwrap_or_default();
+    Ok((status, collect(stdout), collect(stderr)))
+}
+
+/// Runs the shell `command` with the candidate `files` in place, in a scratch copy of the crate,
+/// "{file}" standing for the first of them.
+pub fn run_candidate_set_check(files: &[(String, String)], command: &str) -> (bool, String) {
+    let scratch = Scratch::new().expect("Failed to create scratch crate");
+    scratch
+        .write_all(files)
+        .expect("Failed to write candidate into scratch crate");
+    scratch.run(command, &files[0].0)
+}
+
+/// `check_candidate` for a candidate of several files: the errors for all of them, with all
+/// of them in place, and the errors in `dependents`, the files using the candidate's, with the
+/// file each is in.
+pub fn check_candidate_set_dependents(
+    files: &[(String, String)],
+    dependents: &[&str],
+) -> (Vec<String>, Vec<(String, String)>) {
+    let scratch = Scratch::new().expect("Failed to create scratch crate");
+    scratch
+        .write_all(files)
+        .expect("Failed to write candidate into scratch crate");
+    let source_files: Vec<&str> = files.iter().map(|(source_file, _)| source_file.as_str()).collect();
+    scratch.check_dependents(&source_files, dependents)
+}
+
+/// `test_candidate` for a candidate of several files: the tests of each of them, with all of
+/// them in place. Stops at the first file whose tests fail.
+pub fn test_candidate_set(files: &[(String, String)]) -> (bool, String) {
+    let scratch = Scratch::new().expect("Failed to create scratch crate");
+    scratch
+        .write_all(files)
+        .expect("Failed to write candidate into scratch crate");
+    let mut output = String::new();
+    for (source_file, _) in files {
+        let (passed, tail) = scratch.test(source_file);
+        output = tail;
+        if !passed {
+            return (false, output);
+        }
+    }
+    (true, output)
+}
+
+/// Compiles `content` in place of `source_file` in a scratch copy of the crate and returns
+/// the errors for that file.
+pub fn check_candidate(source_file: &str, content: &str) -> Vec<String> {
+    let scratch = Scratch::new().expect("Failed to create scratch crate");
+    scratch
+        .write(source_file, content)
+        .expect("Failed to write candidate into scratch crate");
+    scratch.check(source_file)
+}
+
+/// Like `check_candidate`, but returns all the errors and warnings for the file.
+pub fn diagnose_candidate(source_file: &str, content: &str) -> Diagnostics {
+    let scratch = Scratch::new().expect("Failed to create scratch crate");
+    scratch
+        .write(source_file, content)
+        .expect("Failed to write candidate into scratch crate");
+    scratch.diagnostics(source_file)
+}
+
+/// Runs the tests of `source_file` with `content` in its place, in a scratch copy of the crate.
+pub fn test_candidate(source_file: &str, content: &str) -> (bool, String) {
+    let scratch = Scratch::new().expect("Failed to create scratch crate");
+    scratch
+        .write(source_file, content)
+        .expect("Failed to write candidate into scratch crate");
+    scratch.test(source_file)
+}
+
+fn copy_recursive(source: &Path, destination: &Path) -> io::Result<()> {
+    if source.is_dir() {
+        fs::create_dir_all(destination)?;
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &destination.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(source, destination).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sandboxed_command() {
+        let vars = env::vars().chain([("GROQ_API_KEY".to_string(), "secret".to_string())]);
+        let script = "echo \"${GROQ_API_KEY:-no key}\"; command -v cargo >/dev/null && echo cargo; echo \"$HOME\"";
+        let mut sandbox = sandboxed_with("sh", &["-c", script], vars).unwrap();
+        assert!(
+            sandbox
+                .command
+                .get_envs()
+                .all(|(name, value)| name != "GROQ_API_KEY" || value.is_none())
+        );
+        let home = sandbox._home.path().to_string_lossy().into_owned();
+        let (passed, output) = run_with_timeout(&mut sandbox.command, TEST_TIMEOUT).unwrap();
+        assert!(passed);
+        assert_eq!(output, format!("no key\ncargo\n{}\n", home));
+
+        // In the namespaces, there is no network and the hidden files read empty.
+        if is_isolated() {
+            let secret = tempfile::NamedTempFile::new().unwrap();
+            fs::write(secret.path(), "key").unwrap();
+            let mut command = Command::new("unshare");
+            command
+                .args(UNSHARE)
+                .args(["sh", "-c", HIDE_SCRIPT, "sh"])
+                .arg(secret.path())
+                .args(["--", "sh", "-c"])
+                .arg("cat \"$0\"; cat /proc/net/dev | grep -c :")
+                .arg(secret.path());
+            let (passed, output) = run_with_timeout(&mut command, TEST_TIMEOUT).unwrap();
+            assert!(passed);
+            assert_eq!(output, "1\n");
+        }
+
+        // The sleep started in the background is killed along with the shell.
+        let mut sandbox = sandboxed("sh", &["-c", "sleep 30 & echo started; sleep 30"]).unwrap();
+        let started = Instant::now();
+        let (passed, output) = run_with_timeout(&mut sandbox.command, Duration::from_millis(500)).unwrap();
+        assert!(!passed);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(output, "started\n\nKilled after 500ms\n");
+    }
+
+    #[test]
+    fn test_new_errors() {
+        let error = |file: &str, text: &str| (file.to_string(), text.to_string());
+        let before = vec![error("src/bin/a.rs", "error[E0425] at 3"), error("src/bin/b.rs", "error[E0308] at 7")];
+        let after = vec![
+            error("src/bin/a.rs", "error[E0425] at 5"),
+            error("src/bin/b.rs", "error[E0308] at 7"),
+            error("src/bin/b.rs", "error[E0061] at 9"),
+        ];
+        assert_eq!(
+            new_errors(&before, &after),
+            vec![error("src/bin/b.rs", "error[E0308] at 7"), error("src/bin/b.rs", "error[E0061] at 9")]
+        );
+        assert!(new_errors(&after, &before).is_empty());
+        assert_eq!(
+            test_args(Path::new("."), "tests/cli.rs"),
+            vec!["test".to_string(), "--test".to_string(), "cli".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_killed_check() {
+        let partial = "{\"reason\":\"build-script-executed\"}\n{\"reason\":\"compiler-mes";
+        let output = killed_check(partial, &["src/bin/a.rs"]);
+        assert!(output.starts_with("{\"reason\":\"build-script-executed\"}\n"));
+        assert_eq!(
+            messages_for_file(&output, "src/bin/a.rs", "error"),
+            vec![format!("error: cargo check killed after {:?}\n", TEST_TIMEOUT)]
+        );
+        assert!(messages_for_file(&output, "src/bin/b.rs", "error").is_empty());
+        assert_eq!(killed_check("", &[]).lines().count(), 1);
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use super::frontmatter::Settings;
+use super::mapping::Rule;
+
+/// The project configuration, in the crate root. A directory of specs may have its own, which
+/// overrides it, and the ones of the directories above, for the specs below that directory.
+pub const CONFIG_FILE: &str = "llm-templated.toml";
+
+/// The providers that `lib::provider::from_spec_in` knows how to talk to.
+pub const PROVIDERS: &[&str] = &["groq", "anthropic", "ollama"];
+
+/// Where and how to reach one provider.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderConfig {
+    pub url: String,
+    /// The environment variable holding the API key.
+    pub api_key_env: Option<String>,
+    /// A file holding the API key, read when the variable is not set; "~/" is the home directory.
+    pub api_key_file: Option<String>,
+    pub default_model: String,
+    pub max_tokens: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// The directory that the specs are discovered in.
+    pub instruct_dir: String,
+    /// Where the scratch crates and the request files of the older drivers go.
+    pub scratch_dir: PathBuf,
+    pub providers: BTreeMap<String, ProviderConfig>,
+    /// Spec settings for the specs that do not set them in their front matter.
+    pub defaults: Settings,
+    /// Tried in order; the first matching rule gives the output of a spec.
+    pub mapping: Vec<Rule>,
+    /// Named shell commands that specs can list in their "checks" setting. They run in the
+    /// scratch crate with the candidate in place, "{file}" standing for the output file.
+    pub checks: BTreeMap<String, String>,
+    /// The driver that llm-make regenerates a spec with when neither its "--driver" nor the
+    /// "driver" setting of the spec names one.
+    pub make_driver: String,
+}
+
+/// One configuration file, with everything optional.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Layer {
+    #[serde(default)]
+    paths: PathsLayer,
+    #[serde(default)]
+    providers: BTreeMap<String, ProviderLayer>,
+    #[serde(default)]
+    defaults: Settings,
+    #[serde(default)]
+    mapping: Vec<Rule>,
+    #[serde(default)]
+    checks: BTreeMap<String, String>,
+    #[serde(default)]
+    make: MakeLayer,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PathsLayer {
+    instruct: Option<String>,
+    scratch: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MakeLayer {
+    driver: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProviderLayer {
+    url: Option<String>,
+    api_key_env: Option<String>,
+    api_key_file: Option<String>,
+    default_model: Option<String>,
+    max_tokens: Option<u32>,
+}
+
+impl Layer {
+    fn parse(text: &str, origin: &str) -> Result<Self, String> {
+        let layer: Layer = toml::from_str(text).map_err(|e| format!("invalid configuration in {}: {}", origin, e))?;
+        for name in layer.providers.keys() {
+            if !PROVIDERS.contains(&name.as_str()) {
+                return Err(format!(
+                    "unknown provider \"{}\" in {}, expected one of: {}",
+                    name,
+                    origin,
+                    PROVIDERS.join(", ")
+                ));
+            }
+        }
+        Ok(layer)
+    }
+
+    /// This layer, with what it leaves unset taken from `outer`. Mapping rules of this layer
+    /// are tried before the ones of `outer`.
+    fn over(self, outer: Layer) -> Layer {
+        let mut providers = outer.providers;
+        for (name, provider) in self.providers {
+            let outer = providers.remove(&name).unwrap_or_default();
+            providers.insert(
+                name,
+                ProviderLayer {
+                    url: provider.url.or(outer.url),
+                    api_key_env: provider.api_key_env.or(outer.api_key_env),
+                    api_key_file: provider.api_key_file.or(outer.api_key_file),
+                    default_model: provider.default_model.or(outer.default_model),
+                    max_tokens: provider.max_tokens.or(outer.max_tokens),
+                },
+            );
+        }
+        let mut checks = outer.checks;
+        checks.extend(self.checks);
+        let mut mapping = self.mapping;
+        mapping.extend(outer.mapping);
+        Layer {
+            paths: PathsLayer {
+                instruct: self.paths.instruct.or(outer.paths.instruct),
+                scratch: self.paths.scratch.or(outer.paths.scratch),
+            },
+            providers,
+            defaults: self.defaults.or(outer.defaults),
+            mapping,
+            checks,
+            make: MakeLayer {
+                driver: self.make.driver.or(outer.make.driver),
+            },
+        }
+    }
+}
+
+/// The built-in configuration, which the configuration files override.
+fn builtin() -> Layer {
+    let provider = |url: &str, api_key_env: Option<&str>, default_model: &str, max_tokens: u32| ProviderLayer {
+        url: Some(url.to_string()),
+        api_key_env: api_key_env.map(str::to_string),
+        api_key_file: None,
+        default_model: Some(default_model.to_string()),
+        max_tokens: Some(max_tokens),
+    };
+    Layer {
+        paths: PathsLayer {
+            instruct: Some("instruct".to_string()),
+            scratch: None,
+        },
+        providers: BTreeMap::from([
+            (
+                "groq".to_string(),
+                provider("https://api.groq.com/openai/v1/chat/completions", Some("GROQ_API_KEY"), "moonshotai/kimi-k2-instruct", 16384),
+            ),
+            (
+                "anthropic".to_string(),
+                provider("https://api.anthropic.com/v1/messages", Some("ANTHROPIC_API_KEY"), "claude-sonnet-4-20250514", 8192),
+            ),
+            ("ollama".to_string(), provider("http://localhost:11434", None, "qwen2.5-coder:14b", 16384)),
+        ]),
+        defaults: Settings::default(),
+        mapping: vec![Rule {
+            spec: "instruct/%.md".to_string(),
+            output: "src/%.rs".to_string(),
+        }],
+        checks: BTreeMap::new(),
+        make: MakeLayer {
+            driver: Some("llm-groq-4".to_string()),
+        },
+    }
+}
+
+impl Config {
+    fn from_layer(layer: Layer) -> Config {
+        let providers = layer
+            .providers
+            .into_iter()
+            .map(|(name, p)| {
+                let provider = ProviderConfig {
+                    url: p.url.unwrap_or_default(),
+                    api_key_env: p.api_key_env,
+                    api_key_file: p.api_key_file,
+                    default_model: p.default_model.unwrap_or_default(),
+                    max_tokens: p.max_tokens.unwrap_or(8192),
+                };
+                (name, provider)
+            })
+            .collect();
+        Config {
+            instruct_dir: layer.paths.instruct.unwrap_or_default(),
+            scratch_dir: layer.paths.scratch.map(PathBuf::from).unwrap_or_else(env::temp_dir),
+            providers,
+            defaults: layer.defaults,
+            mapping: layer.mapping,
+            checks: layer.checks,
+            make_driver: layer.make.driver.unwrap_or_default(),
+        }
+    }
+
+    pub fn provider(&self, name: &str) -> &ProviderConfig {
+        self.providers
+            .get(name)
+            .unwrap_or_else(|| panic!("Provider {} is not configured", name))
+    }
+
+    /// The output file of `spec` by the first matching mapping rule.
+    pub fn output_for(&self, spec: &str) -> Option<String> {
+        let spec = spec.trim_start_matches("./");
+        self.mapping.iter().find_map(|rule| rule.apply(spec))
+    }
+}
+
+impl ProviderConfig {
+    /// The API key from `api_key_env`, or else from `api_key_file`. Panics if there is none.
+    pub fn api_key(&self) -> String {
+        self.try_api_key().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// The path of `api_key_file`, "~/" expanded.
+    pub fn api_key_path(&self) -> Option<PathBuf> {
+        let file = self.api_key_file.as_ref()?;
+        Some(match (file.strip_prefix("~/"), env::var_os("HOME")) {
+            (Some(rest), Some(home)) => Path::new(&home).join(rest),
+            _ => PathBuf::from(file),
+        })
+    }
+
+    /// Like `api_key`, but says why there is no key instead of panicking.
+    pub fn try_api_key(&self) -> Result<String, String> {
+        if let Some(value) = self.api_key_env.as_ref().and_then(|name| env::var(name).ok()) {
+            return Ok(value);
+        }
+        if let Some(path) = self.api_key_path()
+            && let Ok(key) = fs::read_to_string(&path)
+        {
+            return Ok(key.trim().to_string());
+        }
+        Err(match (&self.api_key_env, &self.api_key_file) {
+            (Some(name), None) => format!("{} must be set", name),
+            (Some(name), Some(file)) => format!("{} must be set, or {} must hold the API key", name, file),
+            (None, Some(file)) => format!("{} must hold the API key", file),
+            (None, None) => format!("No API key source is configured for {}", self.url),
+        })
+    }
+}
+
+/// The configuration files that apply to `spec`, outermost first: the one in the crate root,
+/// then those of the directories on the way to the spec.
+pub fn files_for(spec: &str) -> Vec<String> {
+    let spec = spec.trim_start_matches("./");
+    let mut files: Vec<String> = Path::new(spec)
+        .ancestors()
+        .skip(1)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(|dir| dir.join(CONFIG_FILE).to_string_lossy().into_owned())
+        .filter(|file| Path::new(file).exists())
+        .collect();
+    if Path::new(CONFIG_FILE).exists() {
+        files.push(CONFIG_FILE.to_string());
+    }
+    files.reverse();
+    files
+}
+
+fn load_files(files: &[String]) -> Result<Config, String> {
+    let mut layer = builtin();
+    for file in files {
+        let text = fs::read_to_string(file).map_err(|e| format!("Failed to read {}: {}", file, e))?;
+        layer = Layer::parse(&text, file)?.over(layer);
+    }
+    Ok(Config::from_layer(layer))
+}
+
+/// The configuration of the crate root, read once. Panics if it is invalid.
+pub fn project() -> &'static Config {
+    static PROJECT: OnceLock<Config> = OnceLock::new();
+    PROJECT.get_or_init(|| {
+        let files: Vec<String> = [CONFIG_FILE.to_string()]
+            .into_iter()
+            .filter(|file| Path::new(file).exists())
+            .collect();
+        load_files(&files).unwrap_or_else(|e| panic!("{}", e))
+    })
+}
+
+/// The configuration for `spec`: the project configuration with the overrides of the
+/// directories on the way to it.
+pub fn for_spec(spec: &str) -> Result<Config, String> {
+    load_files(&files_for(spec))
+}
+
+/// The providers of the project configuration and of every directory of specs with a
+/// configuration file of its own: all the API key sources that the drivers may use.
+pub fn all_providers() -> Vec<ProviderConfig> {
+    let mut providers: Vec<ProviderConfig> = project().providers.values().cloned().collect();
+    let mut dirs = vec![PathBuf::from(&project().instruct_dir)];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        dirs.extend(entries.filter_map(Result::ok).map(|e| e.path()).filter(|p| p.is_dir()));
+        if dir.join(CONFIG_FILE).exists()
+            && let Ok(config) = for_spec(&dir.join("spec.md").to_string_lossy())
+        {
+            for provider in config.providers.into_values() {
+                if !providers.contains(&provider) {
+                    providers.push(provider);
+                }
+            }
+        }
+    }
+    providers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_layers() {
+        let dir = tempdir().unwrap();
+        let outer = dir.path().join(CONFIG_FILE);
+        fs::write(
+            &outer,
+            "[providers.groq]\nmax_tokens = 4096\n[make]\ndriver = \"llm-groq-5\"\n[[mapping]]\nspec = \"instruct/%.md\"\noutput = \"src/%.rs\"\n[checks]\nclippy = \"cargo clippy\"\n",
+        )
+        .unwrap();
+        let inner = dir.path().join("tests").join(CONFIG_FILE);
+        fs::create_dir(inner.parent().unwrap()).unwrap();
+        fs::write(
+            &inner,
+            "[defaults]\nmodel = \"m\"\n[[mapping]]\nspec = \"instruct/tests/%.md\"\noutput = \"tests/%.rs\"\n",
+        )
+        .unwrap();
+
+        let files = [outer.to_string_lossy().into_owned(), inner.to_string_lossy().into_owned()];
+        let config = load_files(&files).unwrap();
+        let groq = config.provider("groq");
+        assert_eq!(groq.max_tokens, 4096);
+        assert_eq!(groq.api_key_env.as_deref(), Some("GROQ_API_KEY"));
+        assert_eq!(config.defaults.model.as_deref(), Some("m"));
+        assert_eq!(config.checks["clippy"], "cargo clippy");
+        assert_eq!(config.make_driver, "llm-groq-5");
+        assert_eq!(load_files(&[]).unwrap().make_driver, "llm-groq-4");
+        assert_eq!(config.output_for("instruct/tests/cli.md").as_deref(), Some("tests/cli.rs"));
+        assert_eq!(config.output_for("./instruct/bin/wcr.md").as_deref(), Some("src/bin/wcr.rs"));
+        assert_eq!(config.output_for("include/common.md"), None);
+
+        assert!(Layer::parse("[providers.openai]\nurl = \"x\"\n", "x").unwrap_err().contains("unknown provider"));
+        assert!(Layer::parse("[paths]\nsource = \"x\"\n", "x").is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+/// One concrete mismatch between the description and the specimen.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Finding {
+    pub requirement: String,
+    pub problem: String,
+}
+
+pub fn prompt(description: &str, specimen: &str, compiler_errors: &[String]) -> String {
+    format!(
+        "Please CAREFULLY review the specimen (enclosed into <result-specimen></result-specimen>) against the description (enclosed into <result-description></result-description>), taking into account the possible presence of compiler errors (enclosed into <compiler-errors></compiler-errors>). List every concrete mismatch: a requirement of the description that the specimen does not fulfil, fulfils incorrectly, or contradicts. Do not list matters of taste or style that the description does not ask for. Output only a JSON array, where each element is an object with the string fields \"requirement\" (the requirement, quoted or paraphrased from the description) and \"problem\" (what exactly is wrong in the specimen). If there are no mismatches, output an empty array: []. Output nothing else.\n\n<result-description>\n{}\n</result-description>\n\n<result-specimen>\n{}\n</result-specimen>\n\n<compiler-errors>\n{}\n</compiler-errors>",
+        description,
+        specimen,
+        compiler_errors.join("\n")
+    )
+}
+
+/// Parses the critic response, tolerating text or code fences around the JSON array.
+/// Returns `None` if no array of findings can be found.
+pub fn parse_findings(response: &str) -> Option<Vec<Finding>> {
+    let start = response.find('[')?;
+    let end = response.rfind(']')?;
+    if end < start {
+        return None;
+    }
+    serde_json::from_str(&response[start..=end]).ok()
+}
+
+/// The findings in the form they are appended to the generation request.
+pub fn findings_section(findings: &[Finding]) -> String {
+    let list: String = findings
+        .iter()
+        .map(|f| format!("- {}: {}\n", f.requirement, f.problem))
+        .collect();
+    format!(
+        "\n\nA reviewer has found the following mismatches between the description and the specimen (enclosed into <review-findings></review-findings>). Make sure your result addresses every one of them.\n\n<review-findings>\n{}</review-findings>",
+        list
+    )
+}
+
+/// A human-readable review report.
+pub fn report(input_file: &str, output_file: &str, findings: &[Finding]) -> String {
+    let mut report = format!("# Review of {} against {}\n\n", output_file, input_file);
+    if findings.is_empty() {
+        report.push_str("No mismatches found.\n");
+    }
+    for (i, finding) in findings.iter().enumerate() {
+        report.push_str(&format!(
+            "{}. Requirement: {}\n   Problem: {}\n",
+            i + 1,
+            finding.requirement,
+            finding.problem
+        ));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(parse_findings("[]"), Some(Vec::new()));
+        assert_eq!(parse_findings("```json\n[ ]\n```"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_parse_findings() {
+        let response = "Here you go:\n[{\"requirement\": \"max-tokens 16384\", \"problem\": \"uses 8192\"}]";
+        let findings = parse_findings(response).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].problem, "uses 8192");
+        assert!(findings_section(&findings).contains("- max-tokens 16384: uses 8192\n"));
+    }
+
+    #[test]
+    fn test_parse_garbage() {
+        assert_eq!(parse_findings("Everything looks fine."), None);
+        assert_eq!(parse_findings("[not json]"), None);
+    }
+}
@@ -0,0 +1,255 @@
+use super::judge::Verdict;
+
+/// Who decides which of two results is better.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JudgeKind {
+    /// A model call, as described by `lib::judge`.
+    Llm,
+    /// Compiler, tests and interface only; no model call.
+    Deterministic,
+    /// The deterministic rules reject what is clearly worse, the model decides the rest.
+    Filter,
+}
+
+impl JudgeKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "llm" => Some(JudgeKind::Llm),
+            "deterministic" => Some(JudgeKind::Deterministic),
+            "filter" => Some(JudgeKind::Filter),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            JudgeKind::Llm => "llm",
+            JudgeKind::Deterministic => "deterministic",
+            JudgeKind::Filter => "filter",
+        }
+    }
+}
+
+/// What can be measured about one result without asking a model.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Evidence {
+    /// False for an empty or missing file.
+    pub exists: bool,
+    pub errors: usize,
+    pub warnings: usize,
+    /// `None` if the tests were not run.
+    pub tests_passed: Option<bool>,
+    /// Items of the declared interface that the result does not define.
+    pub missing_interface: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    /// `None` if the rules can not tell the results apart.
+    pub verdict: Option<Verdict>,
+    pub reason: String,
+}
+
+fn decided(verdict: Verdict, reason: String) -> Decision {
+    Decision {
+        verdict: Some(verdict),
+        reason,
+    }
+}
+
+/// The evidence for `content`, a result whose compile errors are `errors`, those it causes in
+/// the files using it included, with `warnings` compiler warnings and `tests_passed` if its
+/// tests were run. If `interface` is not empty, checks that it defines all of it.
+pub fn evidence(
+    content: &str,
+    errors: &[String],
+    warnings: usize,
+    tests_passed: Option<bool>,
+    interface: &[String],
+) -> Evidence {
+    if content.trim().is_empty() {
+        return Evidence::default();
+    }
+    Evidence {
+        exists: true,
+        errors: errors.len(),
+        warnings,
+        tests_passed,
+        missing_interface: missing_interface(interface, content),
+    }
+}
+
+/// The hard rules: a second result that does not compile (or compiles worse), fails tests the
+/// first one passes, or lacks declared interface the first one has, loses; a compiling second
+/// result beats a missing or non-compiling first one. Anything else is left undecided.
+pub fn screen(first: &Evidence, second: &Evidence) -> Decision {
+    if !second.exists {
+        return decided(Verdict::First, "second result is empty".to_string());
+    }
+    if second.errors > 0 && (first.exists && second.errors >= first.errors) {
+        return decided(
+            Verdict::First,
+            format!("second result has {} compile errors, first has {}", second.errors, first.errors),
+        );
+    }
+    if second.errors == 0 && (!first.exists || first.errors > 0) {
+        return decided(Verdict::Second, "only the second result compiles".to_string());
+    }
+    if first.tests_passed == Some(true) && second.tests_passed == Some(false) {
+        return decided(Verdict::First, "second result fails tests the first one passes".to_string());
+    }
+    if second.missing_interface.len() > first.missing_interface.len() {
+        return decided(
+            Verdict::First,
+            format!("second result lacks declared interface: {}", second.missing_interface.join(", ")),
+        );
+    }
+    Decision {
+        verdict: None,
+        reason: "no hard rule applies".to_string(),
+    }
+}
+
+/// `screen`, then: passing tests the first one fails wins, then fewer diagnostics win, and on a
+/// tie the first result is kept.
+pub fn decide(first: &Evidence, second: &Evidence) -> Decision {
+    let screened = screen(first, second);
+    if screened.verdict.is_some() {
+        return screened;
+    }
+    if first.tests_passed == Some(false) && second.tests_passed == Some(true) {
+        return decided(Verdict::Second, "second result passes tests the first one fails".to_string());
+    }
+    let first_count = first.errors + first.warnings;
+    let second_count = second.errors + second.warnings;
+    if second_count < first_count {
+        return decided(
+            Verdict::Second,
+            format!("second result has fewer diagnostics ({} vs {})", second_count, first_count),
+        );
+    }
+    if second_count > first_count {
+        return decided(
+            Verdict::First,
+            format!("second result has more diagnostics ({} vs {})", second_count, first_count),
+        );
+    }
+    decided(Verdict::First, "no measurable improvement, keeping the first result".to_string())
+}
+
+const ITEM_KINDS: &[&str] = &["fn", "struct", "enum", "trait"];
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The items ("fn new", "struct Groq", ...) mentioned in the sections of the spec whose heading
+/// contains "interface".
+pub fn declared_interface(description: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut in_interface = false;
+    for line in description.lines() {
+        if line.starts_with('#') {
+            in_interface = line.to_lowercase().contains("interface");
+            continue;
+        }
+        if !in_interface {
+            continue;
+        }
+        let words: Vec<&str> = line
+            .split(|c: char| !is_ident_char(c))
+            .filter(|w| !w.is_empty())
+            .collect();
+        for pair in words.windows(2) {
+            if ITEM_KINDS.contains(&pair[0]) {
+                let item = format!("{} {}", pair[0], pair[1]);
+                if !items.contains(&item) {
+                    items.push(item);
+                }
+            }
+        }
+    }
+    items
+}
+
+/// The items of `interface` that `source` does not define.
+pub fn missing_interface(interface: &[String], source: &str) -> Vec<String> {
+    interface
+        .iter()
+        .filter(|item| {
+            !source.match_indices(item.as_str()).any(|(index, _)| {
+                let before = source[..index].chars().next_back();
+                let after = source[index + item.len()..].chars().next();
+                !before.is_some_and(is_ident_char) && !after.is_some_and(is_ident_char)
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compiling(warnings: usize, tests_passed: Option<bool>) -> Evidence {
+        Evidence {
+            exists: true,
+            warnings,
+            tests_passed,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hard_rules() {
+        let broken = Evidence {
+            exists: true,
+            errors: 2,
+            ..Default::default()
+        };
+        let good = compiling(0, Some(true));
+        assert_eq!(screen(&good, &broken).verdict, Some(Verdict::First));
+        assert_eq!(screen(&broken, &good).verdict, Some(Verdict::Second));
+        assert_eq!(screen(&Evidence::default(), &good).verdict, Some(Verdict::Second));
+        assert_eq!(screen(&good, &compiling(0, Some(false))).verdict, Some(Verdict::First));
+        assert_eq!(screen(&good, &compiling(0, Some(true))).verdict, None);
+
+        let incomplete = Evidence {
+            missing_interface: vec!["fn new".to_string()],
+            ..compiling(0, Some(true))
+        };
+        assert_eq!(screen(&good, &incomplete).verdict, Some(Verdict::First));
+    }
+
+    #[test]
+    fn test_downstream_errors_count() {
+        // A library module that compiles by itself but breaks a file using it.
+        let errors = vec!["error[E0061]: this function takes 2 arguments but 1 argument was supplied".to_string()];
+        let breaking = evidence("pub fn run(a: u32, b: u32) {}\n", &errors, 0, None, &[]);
+        let original = evidence("pub fn run(a: u32) {}\n", &[], 1, Some(true), &[]);
+        assert_eq!(breaking.errors, 1);
+        assert_eq!(screen(&original, &breaking).verdict, Some(Verdict::First));
+        assert_eq!(decide(&original, &breaking).verdict, Some(Verdict::First));
+        assert_eq!(evidence(" \n", &errors, 0, None, &[]), Evidence::default());
+    }
+
+    #[test]
+    fn test_decide_prefers_fewer_diagnostics() {
+        assert_eq!(decide(&compiling(3, None), &compiling(1, None)).verdict, Some(Verdict::Second));
+        assert_eq!(decide(&compiling(1, None), &compiling(3, None)).verdict, Some(Verdict::First));
+        assert_eq!(decide(&compiling(1, None), &compiling(1, None)).verdict, Some(Verdict::First));
+        assert_eq!(
+            decide(&compiling(0, Some(false)), &compiling(2, Some(true))).verdict,
+            Some(Verdict::Second)
+        );
+    }
+
+    #[test]
+    fn test_interface() {
+        let spec = "# Target\n\nA struct Ignored here.\n\n# Library Interface\n\nCode would define \"struct Groq\".\n\n- fn new()\n- fn with_model(model: &str)\n\n# Details\n\n- fn helper()\n";
+        let interface = declared_interface(spec);
+        assert_eq!(interface, vec!["struct Groq", "fn new", "fn with_model"]);
+        let source = "pub struct Groq;\nimpl Groq {\n    pub fn new() -> Self { Groq }\n    pub fn with_model_name() {}\n}\n";
+        assert_eq!(missing_interface(&interface, source), vec!["fn with_model"]);
+    }
+}
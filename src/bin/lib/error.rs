@@ -0,0 +1,98 @@
+use std::fmt;
+
+/// Why a call to a model failed, as far as deciding whether to retry or fall back goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Missing or rejected credentials (401/403): retrying will not help, another provider may.
+    Auth,
+    /// Rate limits and overloaded or failing servers (429, 5xx).
+    Overload,
+    /// The request did not get an HTTP response at all.
+    Network,
+    /// Anything else, e.g. a malformed request or reply.
+    Other,
+}
+
+impl ErrorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::Auth => "auth",
+            ErrorKind::Overload => "overload",
+            ErrorKind::Network => "network",
+            ErrorKind::Other => "other",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "auth" => Some(ErrorKind::Auth),
+            "overload" => Some(ErrorKind::Overload),
+            "network" => Some(ErrorKind::Network),
+            "other" => Some(ErrorKind::Other),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LlmError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl LlmError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        LlmError {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// Classifies a non-success HTTP reply.
+    pub fn from_status(status: u16, body: &str) -> Self {
+        let kind = match status {
+            401 | 403 => ErrorKind::Auth,
+            408 | 429 | 500..=599 => ErrorKind::Overload,
+            _ => ErrorKind::Other,
+        };
+        LlmError::new(kind, format!("HTTP {}: {}", status, body))
+    }
+
+    pub fn network(error: impl fmt::Display) -> Self {
+        LlmError::new(ErrorKind::Network, error.to_string())
+    }
+
+    /// Worth trying the same request again after a pause.
+    pub fn is_transient(&self) -> bool {
+        matches!(self.kind, ErrorKind::Overload | ErrorKind::Network)
+    }
+
+    /// Worth trying the request with another provider.
+    pub fn allows_fallback(&self) -> bool {
+        self.kind != ErrorKind::Other
+    }
+}
+
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} error: {}", self.kind.name(), self.message)
+    }
+}
+
+impl std::error::Error for LlmError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_status() {
+        assert_eq!(LlmError::from_status(401, "").kind, ErrorKind::Auth);
+        assert_eq!(LlmError::from_status(429, "").kind, ErrorKind::Overload);
+        assert_eq!(LlmError::from_status(529, "").kind, ErrorKind::Overload);
+        assert_eq!(LlmError::from_status(400, "").kind, ErrorKind::Other);
+        assert!(!LlmError::from_status(401, "").is_transient());
+        assert!(LlmError::from_status(401, "").allows_fallback());
+        assert!(!LlmError::from_status(400, "").allows_fallback());
+    }
+}
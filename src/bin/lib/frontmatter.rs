@@ -0,0 +1,131 @@
+use std::fs;
+
+use serde::Deserialize;
+
+use super::config;
+use super::preprocess::split_front_matter;
+
+/// The built-in checks a candidate must pass before it is accepted, besides compiling; the
+/// project configuration can name more.
+pub const CHECKS: &[&str] = &["interface", "tests"];
+
+/// Generation settings of a spec, from the TOML front matter between "+++" lines at its top
+/// and the "[defaults]" of the configuration files. Unset settings use the driver's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// The driver binary that regenerates the spec.
+    pub driver: Option<String>,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub judge_model: Option<String>,
+    /// Names from `CHECKS` or the "[checks]" of the configuration.
+    pub checks: Option<Vec<String>>,
+    /// Overrides the output file that the spec path maps to.
+    pub output: Option<String>,
+    /// Further files the spec generates together with its output, in one response.
+    pub outputs: Option<Vec<String>>,
+}
+
+impl Settings {
+    pub fn parse(text: &str, origin: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| format!("invalid settings in {}: {}", origin, e))
+    }
+
+    /// These settings, with the ones they leave unset taken from `defaults`.
+    pub fn or(self, defaults: Settings) -> Settings {
+        Settings {
+            driver: self.driver.or(defaults.driver),
+            provider: self.provider.or(defaults.provider),
+            model: self.model.or(defaults.model),
+            temperature: self.temperature.or(defaults.temperature),
+            judge_model: self.judge_model.or(defaults.judge_model),
+            checks: self.checks.or(defaults.checks),
+            output: self.output.or(defaults.output),
+            outputs: self.outputs.or(defaults.outputs),
+        }
+    }
+
+    /// The model as a provider spec, "<provider>:<model>", or the bare model name if no
+    /// provider is set. A provider without a model is ignored.
+    pub fn model_spec(&self) -> Option<String> {
+        let model = self.model.as_ref()?;
+        match &self.provider {
+            Some(provider) => Some(format!("{}:{}", provider, model)),
+            None => Some(model.clone()),
+        }
+    }
+
+    pub fn has_check(&self, check: &str) -> bool {
+        self.checks.iter().flatten().any(|c| c == check)
+    }
+
+    /// The checks that are neither built in nor configured commands.
+    pub fn unknown_checks(&self, commands: &[&str]) -> Vec<String> {
+        self.checks
+            .iter()
+            .flatten()
+            .filter(|c| !CHECKS.contains(&c.as_str()) && !commands.contains(&c.as_str()))
+            .cloned()
+            .collect()
+    }
+}
+
+/// The front matter of `spec`, if any, over the "[defaults]" of the configuration for it.
+pub fn settings_for(spec: &str) -> Result<Settings, String> {
+    let text = fs::read_to_string(spec).map_err(|e| format!("Failed to read {}: {}", spec, e))?;
+    let front_matter = match split_front_matter(&text).0 {
+        Some(front_matter) => Settings::parse(front_matter, spec)?,
+        None => Settings::default(),
+    };
+    let config = config::for_spec(spec)?;
+    let settings = front_matter.or(config.defaults.clone());
+    let commands: Vec<&str> = config.checks.keys().map(String::as_str).collect();
+    let unknown = settings.unknown_checks(&commands);
+    if !unknown.is_empty() {
+        return Err(format!(
+            "unknown checks for {}: {}; expected {} or one of the configured [checks]",
+            spec,
+            unknown.join(", "),
+            CHECKS.join(", ")
+        ));
+    }
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::CONFIG_FILE;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_settings_inheritance() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("instruct");
+        fs::create_dir_all(root.join("bin/lib")).unwrap();
+        fs::write(root.join(CONFIG_FILE), "[defaults]\nprovider = \"groq\"\nmodel = \"a\"\ntemperature = 0.2\n").unwrap();
+        fs::write(
+            root.join("bin/lib").join(CONFIG_FILE),
+            "[defaults]\nmodel = \"b\"\nchecks = [\"tests\", \"clippy\"]\n[checks]\nclippy = \"cargo clippy\"\n",
+        )
+        .unwrap();
+        let spec = root.join("bin/lib/groq.md");
+        fs::write(&spec, "+++\nprovider = \"anthropic\"\ndriver = \"llm-groq-5\"\n+++\n# Groq\n").unwrap();
+
+        let settings = settings_for(spec.to_str().unwrap()).unwrap();
+        assert_eq!(settings.model_spec().as_deref(), Some("anthropic:b"));
+        assert_eq!(settings.temperature, Some(0.2));
+        assert_eq!(settings.driver.as_deref(), Some("llm-groq-5"));
+        assert!(settings.has_check("tests"));
+        assert!(!settings.has_check("interface"));
+        assert_eq!(settings.output, None);
+        assert_eq!(settings.outputs, None);
+
+        let other = root.join("bin/wcr.md");
+        fs::write(&other, "+++\nchecks = [\"clippy\"]\n+++\n").unwrap();
+        assert!(settings_for(other.to_str().unwrap()).unwrap_err().contains("unknown checks"));
+        assert!(Settings::parse("modle = \"typo\"", "x").is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use sha2::{Digest, Sha256};
+
+/// Runs git with `args` and returns its stdout, or its stderr as the error.
+fn git(args: &[&str]) -> Result<String, String> {
+    run(Command::new("git"), args)
+}
+
+/// `git` on the index file `index` instead of the one of the repository.
+fn git_on(index: &Path, args: &[&str]) -> Result<String, String> {
+    let mut command = Command::new("git");
+    command.env("GIT_INDEX_FILE", index);
+    run(command, args)
+}
+
+fn run(mut command: Command, args: &[&str]) -> Result<String, String> {
+    let output = command
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to execute git {}: {}", args.join(" "), e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+pub fn add(paths: &[&str]) -> Result<(), String> {
+    let mut args = vec!["add", "--"];
+    args.extend(paths);
+    git(&args).map(|_| ())
+}
+
+/// Commits `paths`, and only them: anything else that is staged stays staged.
+/// Returns the hash of the new commit.
+pub fn commit(paths: &[&str], message: &str) -> Result<String, String> {
+    add(paths)?;
+    let mut args = vec!["commit", "--quiet", "-m", message, "--"];
+    args.extend(paths);
+    git(&args)?;
+    git(&["rev-parse", "HEAD"]).map(|hash| hash.trim().to_string())
+}
+
+/// Like `commit`, but also commits `staged`, the content to commit for each of some other
+/// paths, whose files stay as they are: e.g. a shared file of which only some changes belong
+/// in the commit. Goes through an index of its own, so that the index of the repository only
+/// gets the committed paths updated. Returns the hash of the new commit.
+pub fn commit_with(paths: &[&str], staged: &[(&str, Vec<u8>)], message: &str) -> Result<String, String> {
+    let dir = tempfile::tempdir().map_err(|e| format!("Failed to create a temporary index: {}", e))?;
+    let index = dir.path().join("index");
+    git_on(&index, &["read-tree", "HEAD"])?;
+    let mut args = vec!["add", "--"];
+    args.extend(paths);
+    git_on(&index, &args)?;
+    let blob_file = dir.path().join("blob");
+    for (path, content) in staged {
+        fs::write(&blob_file, content).map_err(|e| format!("Failed to write {}: {}", blob_file.display(), e))?;
+        let blob = git(&["hash-object", "-w", "--", &blob_file.to_string_lossy()])?;
+        let cacheinfo = format!("100644,{},{}", blob.trim(), path);
+        git_on(&index, &["update-index", "--add", "--cacheinfo", &cacheinfo])?;
+    }
+    git_on(&index, &["commit", "--quiet", "-m", message])?;
+    let mut args = vec!["reset", "--quiet", "--"];
+    args.extend(paths);
+    args.extend(staged.iter().map(|(path, _)| *path));
+    git(&args)?;
+    head()
+}
+
+/// The content of `path` as committed at HEAD; `None` if it is not there.
+pub fn committed(path: &str) -> Option<Vec<u8>> {
+    let output = Command::new("git")
+        .args(["show", &format!("HEAD:{}", path)])
+        .output()
+        .ok()?;
+    output.status.success().then_some(output.stdout)
+}
+
+/// A commit message: the subject, a blank line and one "Key: value" trailer per line,
+/// so that `git log --format=%(trailers)` can read them back.
+pub fn message(subject: &str, trailers: &[(&str, String)]) -> String {
+    let mut message = format!("{}\n\n", subject);
+    for (key, value) in trailers {
+        // A trailer value must stay on one line.
+        let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+        message.push_str(&format!("{}: {}\n", key, value));
+    }
+    message
+}
+
+/// The entries of `git status --porcelain` for `paths`, e.g. " M src/bin/wcr.rs";
+/// untracked files count as changed.
+pub fn changes(paths: &[&str]) -> Result<Vec<String>, String> {
+    let mut args = vec!["status", "--porcelain", "--"];
+    args.extend(paths);
+    Ok(git(&args)?.lines().map(str::to_string).collect())
+}
+
+/// The operation the repository is in the middle of, if any: "rebase", "merge",
+/// "cherry-pick" or "revert".
+pub fn operation_in_progress() -> Result<Option<&'static str>, String> {
+    let git_dir = git(&["rev-parse", "--git-dir"])?;
+    let git_dir = Path::new(git_dir.trim());
+    let markers = [
+        ("rebase-merge", "rebase"),
+        ("rebase-apply", "rebase"),
+        ("MERGE_HEAD", "merge"),
+        ("CHERRY_PICK_HEAD", "cherry-pick"),
+        ("REVERT_HEAD", "revert"),
+    ];
+    Ok(markers
+        .iter()
+        .find(|(marker, _)| git_dir.join(marker).exists())
+        .map(|(_, operation)| *operation))
+}
+
+pub fn is_repository() -> bool {
+    git(&["rev-parse", "--git-dir"]).is_ok()
+}
+
+/// The hash of the commit checked out.
+pub fn head() -> Result<String, String> {
+    git(&["rev-parse", "HEAD"]).map(|hash| hash.trim().to_string())
+}
+
+/// Checks out a new branch `branch`, starting at HEAD, into a new worktree at `path`.
+pub fn add_worktree(path: &str, branch: &str) -> Result<(), String> {
+    git(&["worktree", "add", "--quiet", "-b", branch, path, "HEAD"]).map(|_| ())
+}
+
+/// Removes the worktree at `path` with whatever is left in it; its branch stays.
+pub fn remove_worktree(path: &str) -> Result<(), String> {
+    git(&["worktree", "remove", "--force", path]).map(|_| ())
+}
+
+/// `git log --oneline` of `range`, e.g. "<base>..<branch>".
+pub fn log_oneline(range: &str) -> Result<String, String> {
+    git(&["log", "--oneline", "--reverse", range])
+}
+
+/// `git diff --stat` of `range`.
+pub fn diff_stat(range: &str) -> Result<String, String> {
+    git(&["diff", "--stat", range])
+}
+
+/// The pre-flight checks every driver makes before it may overwrite its output file: the
+/// output file has no uncommitted changes, the repository is not in the middle of a rebase or
+/// merge and, with "--clean-spec", neither the spec nor the files it includes have uncommitted
+/// changes. "--allow-dirty" turns the failures into warnings.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Safety {
+    pub allow_dirty: bool,
+    pub clean_spec: bool,
+}
+
+impl Safety {
+    /// Takes "--allow-dirty" and "--clean-spec" out of `args`, wherever they are.
+    pub fn from_args(args: &mut Vec<String>) -> Self {
+        let mut safety = Safety::default();
+        args.retain(|arg| match arg.as_str() {
+            "--allow-dirty" => {
+                safety.allow_dirty = true;
+                false
+            }
+            "--clean-spec" => {
+                safety.clean_spec = true;
+                false
+            }
+            _ => true,
+        });
+        safety
+    }
+
+    /// Lists what is wrong with the working tree for regenerating `output_file` from `input_file`.
+    pub fn problems(&self, input_file: &str, output_file: &str) -> Result<Vec<String>, String> {
+        let mut problems = Vec::new();
+        if let Some(operation) = operation_in_progress()? {
+            problems.push(format!("a {} is in progress", operation));
+        }
+        let mut paths = Vec::new();
+        if Path::new(output_file).exists() {
+            paths.push(output_file.to_string());
+        }
+        if self.clean_spec {
+            paths.push(input_file.to_string());
+            paths.extend(
+                super::preprocess::includes(input_file)
+                    .into_iter()
+                    .filter(|include| Path::new(include).exists()),
+            );
+        }
+        if !paths.is_empty() {
+            let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+            problems.extend(changes(&paths)?.into_iter().map(|change| format!("uncommitted change: {}", change)));
+        }
+        Ok(problems)
+    }
+
+    /// Runs the checks, reporting each step on stderr. Outside a git repository there is
+    /// nothing to protect the files with, so the checks are skipped with a warning.
+    pub fn check(&self, input_file: &str, output_file: &str) -> Result<(), String> {
+        eprintln!("Checking output file status with git");
+        if !is_repository() {
+            eprintln!("Warning: not in a git repository, skipping the safety checks");
+            return Ok(());
+        }
+        let problems = self.problems(input_file, output_file)?;
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let list: String = problems.iter().map(|p| format!("\n  {}", p)).collect();
+        if self.allow_dirty {
+            eprintln!("Warning: continuing because of --allow-dirty:{}", list);
+            return Ok(());
+        }
+        Err(format!(
+            "refusing to overwrite {}:{}\nCommit or stash the changes, or pass --allow-dirty",
+            output_file, list
+        ))
+    }
+}
+
+/// "sha256:<hex>" of `content`, e.g. to record which version of a spec produced a file.
+pub fn content_hash(content: impl AsRef<[u8]>) -> String {
+    let digest = Sha256::digest(content.as_ref());
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256:{}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safety_flags() {
+        let mut args: Vec<String> = ["llm-groq-4", "--allow-dirty", "in.md", "out.rs"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let safety = Safety::from_args(&mut args);
+        assert!(safety.allow_dirty && !safety.clean_spec);
+        assert_eq!(args, vec!["llm-groq-4", "in.md", "out.rs"]);
+    }
+
+    #[test]
+    fn test_message_and_hash() {
+        let trailers = [("Spec", "instruct/bin/wcr.md".to_string()), ("Verdict", "Second:\n  only the second compiles".to_string())];
+        assert_eq!(
+            message("Regenerate src/bin/wcr.rs", &trailers),
+            "Regenerate src/bin/wcr.rs\n\nSpec: instruct/bin/wcr.md\nVerdict: Second: only the second compiles\n"
+        );
+        assert_eq!(
+            content_hash(""),
+            "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
@@ -1,27 +1,78 @@
-use std::env;
 use reqwest::blocking::Client;
 use serde::{Deserialize, Serialize};
 
+use std::cell::OnceCell;
+
+use super::config::{self, ProviderConfig};
+use super::error::{ErrorKind, LlmError};
+
 pub struct Groq {
     client: Client,
-    api_key: String,
+    key_source: ProviderConfig,
+    /// Looked up on the first request, see `api_key`.
+    api_key: OnceCell<Result<String, LlmError>>,
+    model: String,
+    url: String,
+    max_tokens: u32,
 }
 
 impl Groq {
-    pub fn new() -> Self {
-        let api_key = env::var("GROQ_API_KEY").expect("GROQ_API_KEY must be set");
+    /// The default model at the "groq" provider of the configuration of `spec`, which the
+    /// configuration files of its directories can override. Panics if that is invalid.
+    pub fn for_spec(spec: &str) -> Self {
+        let config = config::for_spec(spec).unwrap_or_else(|e| panic!("{}", e));
+        let provider = config.provider("groq");
+        Self::with_provider(provider, &provider.default_model)
+    }
+
+    /// `model` at the provider `provider` describes.
+    pub fn with_provider(provider: &ProviderConfig, model: &str) -> Self {
         Groq {
             client: Client::new(),
-            api_key,
+            key_source: provider.clone(),
+            api_key: OnceCell::new(),
+            model: model.to_string(),
+            url: provider.url.clone(),
+            max_tokens: provider.max_tokens,
         }
     }
 
+    /// The API key, looked up on the first request. A missing or unreadable key is an auth
+    /// error, so that a fallback chain moves on to the next provider.
+    fn api_key(&self) -> Result<String, LlmError> {
+        self.api_key
+            .get_or_init(|| self.key_source.try_api_key().map_err(|e| LlmError::new(ErrorKind::Auth, e)))
+            .clone()
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn max_tokens(&self) -> u32 {
+        self.max_tokens
+    }
+
     pub fn evaluate(&self, prompt: &str) -> String {
+        self.evaluate_with(prompt, None, None)
+    }
+
+    pub fn evaluate_with(&self, prompt: &str, temperature: Option<f32>, seed: Option<u64>) -> String {
+        self.try_evaluate_with(prompt, temperature, seed)
+            .unwrap_or_else(|e| panic!("Groq request failed: {}", e))
+    }
+
+    /// Same as evaluate_with, but returns API, HTTP and network failures instead of panicking.
+    pub fn try_evaluate_with(&self, prompt: &str, temperature: Option<f32>, seed: Option<u64>) -> Result<String, LlmError> {
         #[derive(Serialize)]
         struct RequestBody<'a> {
             model: &'a str,
             messages: Vec<Message<'a>>,
             max_tokens: u32,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            temperature: Option<f32>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            seed: Option<u64>,
         }
 
         #[derive(Serialize)]
@@ -46,37 +97,61 @@ impl Groq {
         }
 
         let body = RequestBody {
-            model: "moonshotai/kimi-k2-instruct",
+            model: &self.model,
             messages: vec![Message {
                 role: "user",
                 content: prompt,
             }],
-            max_tokens: 16384,
+            max_tokens: self.max_tokens,
+            temperature,
+            seed,
         };
 
-        let response = self
-            .client
-            .post("https://api.groq.com/openai/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&body)
-            .send()
-            .expect("Failed to send request");
-
-        let text = response.text().expect("Failed to get response text");
+        let text = self.post(&body)?;
         match serde_json::from_str::<Response>(&text) {
-            Ok(parsed) => {
-                parsed
-                    .choices
-                    .into_iter()
-                    .next()
-                    .expect("No choices returned")
-                    .message
-                    .content
-            }
+            Ok(parsed) => Ok(parsed
+                .choices
+                .into_iter()
+                .next()
+                .expect("No choices returned")
+                .message
+                .content),
             Err(_) => {
                 eprintln!("{}", text);
                 panic!("Failed to parse JSON response");
             }
         }
     }
-}
\ No newline at end of file
+
+    /// Posts a raw chat completion request and returns the parsed JSON reply, or the HTTP and
+    /// network failures. If the reply is not JSON or is an API error, outputs it to stderr and panics.
+    pub fn try_complete_json(&self, body: &serde_json::Value) -> Result<serde_json::Value, LlmError> {
+        let text = self.post(body)?;
+        match serde_json::from_str::<serde_json::Value>(&text) {
+            Ok(parsed) if parsed.get("error").is_none() => Ok(parsed),
+            _ => {
+                eprintln!("{}", text);
+                panic!("Failed to parse JSON response");
+            }
+        }
+    }
+
+    /// Sends the request and returns the reply text of a successful HTTP response.
+    fn post<T: Serialize>(&self, body: &T) -> Result<String, LlmError> {
+        let api_key = self.api_key()?;
+        let response = self
+            .client
+            .post(&self.url)
+            .header("Authorization", format!("Bearer {}", api_key))
+            .json(body)
+            .send()
+            .map_err(LlmError::network)?;
+
+        let status = response.status();
+        let text = response.text().map_err(LlmError::network)?;
+        if !status.is_success() {
+            return Err(LlmError::from_status(status.as_u16(), &text));
+        }
+        Ok(text)
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::provider::{FallbackRecord, canonical};
+
+/// The run log: one JSON line per driver run, in the crate root.
+pub const HISTORY_FILE: &str = ".llm-history.jsonl";
+
+/// What happened to one generated candidate.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CandidateRecord {
+    pub tier: usize,
+    pub model: String,
+    pub label: String,
+    pub compile_errors: usize,
+    /// Size of the request and of the reply, a stand-in for the cost of the candidate.
+    #[serde(default)]
+    pub prompt_bytes: usize,
+    #[serde(default)]
+    pub response_bytes: usize,
+    /// "accepted", "lost", "no-compile" or "tests-failed".
+    pub outcome: String,
+}
+
+/// How one tier of the escalation ladder ended.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TierRecord {
+    pub tier: usize,
+    pub models: String,
+    pub outcome: String,
+}
+
+/// How long one step of a run took.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Timing {
+    pub step: String,
+    pub seconds: f64,
+}
+
+/// One driver run: what the run log holds a line of, and the manifest of the run directory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// The name of the run directory below `lib::runs::RUNS_DIR`.
+    #[serde(default)]
+    pub run_id: String,
+    pub started: u64,
+    pub finished: u64,
+    pub input_file: String,
+    pub output_file: String,
+    /// The content hash of every output file when the run started, empty for a missing one,
+    /// so that a resume can tell whether they have changed since.
+    #[serde(default)]
+    pub output_hashes: BTreeMap<String, String>,
+    /// The command line options and files the driver was started with.
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub tiers: Vec<TierRecord>,
+    #[serde(default)]
+    pub candidates: Vec<CandidateRecord>,
+    /// Provider switches made because of outages.
+    #[serde(default)]
+    pub fallbacks: Vec<FallbackRecord>,
+    pub accepted_tier: Option<usize>,
+    pub accepted_model: Option<String>,
+    /// The verdict of the final round and the reason for it, e.g. "Second (deterministic: ...)".
+    #[serde(default)]
+    pub verdict: String,
+    /// The hash of the commit of the accepted result, with "--commit".
+    #[serde(default)]
+    pub commit: Option<String>,
+    /// "accepted", "kept", "rejected" or "failed"; empty while the run is in progress.
+    pub result: String,
+    #[serde(default)]
+    pub timings: Vec<Timing>,
+    /// The stages whose responses are saved in the run directory, e.g. "tier2/generate-1:<model>".
+    #[serde(default)]
+    pub completed: Vec<String>,
+    /// How many times the run was resumed.
+    #[serde(default)]
+    pub resumed: usize,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl RunRecord {
+    pub fn new(input_file: &str, output_file: &str) -> Self {
+        RunRecord {
+            started: now(),
+            input_file: input_file.to_string(),
+            output_file: output_file.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Records how long `step` took since `started`.
+    pub fn time(&mut self, step: &str, started: Instant) {
+        self.timings.push(Timing {
+            step: step.to_string(),
+            seconds: started.elapsed().as_secs_f64(),
+        });
+    }
+
+    /// Prepares the record of an interrupted run for resuming it: keeps the completed stages
+    /// and forgets what the interrupted attempt found out about the candidates.
+    pub fn restart(&mut self) {
+        self.resumed += 1;
+        self.tiers.clear();
+        self.candidates.clear();
+        self.fallbacks.clear();
+        self.accepted_tier = None;
+        self.accepted_model = None;
+        self.verdict.clear();
+        self.commit = None;
+        self.result.clear();
+    }
+
+    pub fn finish(&mut self, result: &str) {
+        self.finished = now();
+        self.result = result.to_string();
+    }
+}
+
+/// Appends the record to the run log.
+pub fn append(record: &RunRecord) {
+    let line = serde_json::to_string(record).expect("Failed to serialize run record");
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(HISTORY_FILE)
+        .unwrap_or_else(|_| panic!("Failed to open run log: {}", HISTORY_FILE));
+    writeln!(file, "{}", line).unwrap_or_else(|_| panic!("Failed to write run log: {}", HISTORY_FILE));
+}
+
+/// Reads all records of the run log, skipping lines that do not parse.
+pub fn load() -> Vec<RunRecord> {
+    fs::read_to_string(HISTORY_FILE)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// How one model has done on one output file, or on all of them if `output_file` is empty.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelStats {
+    pub output_file: String,
+    pub model: String,
+    pub candidates: usize,
+    pub compiled: usize,
+    pub accepted: usize,
+    pub bytes: usize,
+}
+
+impl ModelStats {
+    pub fn acceptance_rate(&self) -> f64 {
+        ratio(self.accepted, self.candidates)
+    }
+
+    pub fn compile_rate(&self) -> f64 {
+        ratio(self.compiled, self.candidates)
+    }
+
+    pub fn bytes_per_candidate(&self) -> usize {
+        self.bytes.checked_div(self.candidates).unwrap_or(0)
+    }
+
+    /// The acceptance rate with one success and one failure added, so that a model tried once
+    /// does not beat one with a long record.
+    fn score(&self) -> f64 {
+        (self.accepted as f64 + 1.0) / (self.candidates as f64 + 2.0)
+    }
+}
+
+fn ratio(part: usize, whole: usize) -> f64 {
+    if whole == 0 { 0.0 } else { part as f64 / whole as f64 }
+}
+
+/// Aggregates the candidates of the records per output file and model, or per model only
+/// if `per_file` is false.
+pub fn model_stats(records: &[RunRecord], per_file: bool) -> Vec<ModelStats> {
+    let mut stats: BTreeMap<(String, String), ModelStats> = BTreeMap::new();
+    for record in records {
+        let output_file = if per_file { record.output_file.clone() } else { String::new() };
+        for candidate in &record.candidates {
+            let entry = stats
+                .entry((output_file.clone(), candidate.model.clone()))
+                .or_insert_with(|| ModelStats {
+                    output_file: output_file.clone(),
+                    model: candidate.model.clone(),
+                    ..Default::default()
+                });
+            entry.candidates += 1;
+            if candidate.compile_errors == 0 {
+                entry.compiled += 1;
+            }
+            if candidate.outcome == "accepted" {
+                entry.accepted += 1;
+            }
+            entry.bytes += candidate.prompt_bytes + candidate.response_bytes;
+        }
+    }
+    stats.into_values().collect()
+}
+
+/// Picks the model among `models` with the best acceptance record for `output_file`, using the
+/// records of all files when this one has none. Ties go to the better compile rate, then to the
+/// cheaper model, then to the earlier one in `models`. A model that no run has tried yet comes
+/// first, so that every listed model gets a record. Models are compared as provider specs, so
+/// a bare model name matches its "groq:" record. Returns `None` without any history.
+pub fn route(records: &[RunRecord], output_file: &str, models: &[String]) -> Option<String> {
+    let overall = model_stats(records, false);
+    let tried = |model: &String| overall.iter().any(|s| canonical(&s.model) == canonical(model));
+    if models.iter().any(tried)
+        && let Some(untried) = models.iter().find(|model| !tried(model))
+    {
+        return Some(untried.clone());
+    }
+    let for_file: Vec<RunRecord> = records
+        .iter()
+        .filter(|r| r.output_file == output_file)
+        .cloned()
+        .collect();
+    let pick = |stats: Vec<ModelStats>| {
+        let mut best: Option<(&String, &ModelStats)> = None;
+        for model in models {
+            let Some(candidate) = stats.iter().find(|s| canonical(&s.model) == canonical(model)) else {
+                continue;
+            };
+            let better = match best {
+                None => true,
+                Some((_, best)) => {
+                    (candidate.score(), candidate.compile_rate(), -(candidate.bytes_per_candidate() as f64))
+                        > (best.score(), best.compile_rate(), -(best.bytes_per_candidate() as f64))
+                }
+            };
+            if better {
+                best = Some((model, candidate));
+            }
+        }
+        best.map(|(model, _)| model.clone())
+    };
+    pick(model_stats(&for_file, true)).or_else(|| pick(overall))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(output_file: &str, candidates: &[(&str, usize, &str)]) -> RunRecord {
+        RunRecord {
+            output_file: output_file.to_string(),
+            candidates: candidates
+                .iter()
+                .map(|(model, errors, outcome)| CandidateRecord {
+                    model: model.to_string(),
+                    compile_errors: *errors,
+                    outcome: outcome.to_string(),
+                    prompt_bytes: 100,
+                    response_bytes: 50,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_model_stats() {
+        let records = vec![
+            run("src/a.rs", &[("groq:x", 0, "accepted"), ("groq:y", 2, "no-compile")]),
+            run("src/b.rs", &[("groq:x", 0, "lost")]),
+        ];
+        let stats = model_stats(&records, false);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].model, "groq:x");
+        assert_eq!(stats[0].candidates, 2);
+        assert_eq!(stats[0].accepted, 1);
+        assert_eq!(stats[0].bytes_per_candidate(), 150);
+        assert_eq!(stats[1].compile_rate(), 0.0);
+        assert_eq!(model_stats(&records, true).len(), 3);
+    }
+
+    #[test]
+    fn test_route() {
+        let models = vec!["groq:x".to_string(), "groq:y".to_string()];
+        let records = vec![
+            run("src/a.rs", &[("groq:x", 0, "lost"), ("groq:y", 0, "accepted")]),
+            run("src/a.rs", &[("groq:x", 0, "lost"), ("groq:y", 0, "accepted")]),
+            run("src/b.rs", &[("groq:x", 0, "accepted")]),
+        ];
+        assert_eq!(route(&records, "src/a.rs", &models).as_deref(), Some("groq:y"));
+        assert_eq!(route(&records, "src/b.rs", &models).as_deref(), Some("groq:x"));
+        // No history for this file: the overall record decides.
+        assert_eq!(route(&records, "src/c.rs", &models).as_deref(), Some("groq:y"));
+        assert_eq!(route(&[], "src/a.rs", &models), None);
+    }
+
+    #[test]
+    fn test_route_bare_names() {
+        // The records name the models as providers do; "--route" may list bare Groq names.
+        let records = vec![
+            run("src/a.rs", &[("groq:x", 0, "lost"), ("groq:y", 0, "accepted")]),
+            run("src/b.rs", &[("groq:x", 0, "accepted")]),
+        ];
+        let bare = vec!["x".to_string(), "y".to_string()];
+        assert_eq!(route(&records, "src/a.rs", &bare).as_deref(), Some("y"));
+        assert_eq!(route(&records, "src/b.rs", &bare).as_deref(), Some("x"));
+        // A listed model without any record is tried before the ones with a record.
+        let with_new = vec!["y".to_string(), "anthropic:z".to_string()];
+        assert_eq!(route(&records, "src/a.rs", &with_new).as_deref(), Some("anthropic:z"));
+    }
+}
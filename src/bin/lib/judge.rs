@@ -0,0 +1,243 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use similar::TextDiff;
+
+pub const FIRST_BETTER: &str = "First result is better.";
+pub const SECOND_BETTER: &str = "The second implementation is better.";
+
+/// How the candidate is presented to the judge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JudgeMode {
+    /// Original and candidate are both embedded in full.
+    Full,
+    /// Original is embedded in full, the candidate as a unified diff against it.
+    Diff,
+}
+
+impl JudgeMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "full" => Some(JudgeMode::Full),
+            "diff" => Some(JudgeMode::Diff),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            JudgeMode::Full => "full",
+            JudgeMode::Diff => "diff",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    First,
+    Second,
+}
+
+/// Maps the judge reply onto a verdict, `None` if it is neither of the two phrases.
+pub fn parse_verdict(response: &str) -> Option<Verdict> {
+    match response.trim() {
+        FIRST_BETTER => Some(Verdict::First),
+        SECOND_BETTER => Some(Verdict::Second),
+        _ => None,
+    }
+}
+
+/// Unified diff of `candidate` against `original`, with `context` lines around each hunk.
+pub fn unified_diff(original: &str, candidate: &str, context: usize) -> String {
+    TextDiff::from_lines(original, candidate)
+        .unified_diff()
+        .context_radius(context)
+        .header("first-result", "second-result")
+        .to_string()
+}
+
+pub fn full_prompt(
+    description: &str,
+    original: &str,
+    candidate: &str,
+    first_errors: &[String],
+    second_errors: &[String],
+) -> String {
+    format!(
+        "Please CAREFULLY evaluate the below description (enclosed into <result-description></result-description>), and two outputs corresponding to this description, first one enclosed into \"<first-result></first-result>\" and the second enclosed into \"<second-result></second-result>\", with compile errors of first result included into \"<first-compile-errors></first-compile-errors>\" and second compile errors as \"<second-compile-errors></second-compile-errors>\", and evaluate which of the two is more precise and correct in implementing the description - and also which of them compiles! Then, if the first result is better, output the phrase '{}', if the second result is better, output the phrase '{}'. Output only one of the two phrases, and nothing else\n\n<result-description>\n{}\n</result-description>\n\n<first-result>\n{}</first-result>\n\n<second-result>\n{}</second-result>\n\n<first-compile-errors>\n{}</first-compile-errors>\n\n<second-compile-errors>\n{}</second-compile-errors>",
+        FIRST_BETTER, SECOND_BETTER, description, original, candidate, first_errors.join("\n"), second_errors.join("\n")
+    )
+}
+
+pub fn diff_prompt(
+    description: &str,
+    original: &str,
+    diff: &str,
+    first_errors: &[String],
+    second_errors: &[String],
+) -> String {
+    format!(
+        "Please CAREFULLY evaluate the below description (enclosed into <result-description></result-description>), and two outputs corresponding to this description: the first one is enclosed into \"<first-result></first-result>\", the second one is given as a unified diff against the first one, enclosed into \"<second-result-diff></second-result-diff>\" - applying that diff to the first result yields the second result. Compile errors of first result are included into \"<first-compile-errors></first-compile-errors>\" and second compile errors as \"<second-compile-errors></second-compile-errors>\". Evaluate which of the two is more precise and correct in implementing the description - and also which of them compiles! Then, if the first result is better, output the phrase '{}', if the second result is better, output the phrase '{}'. Output only one of the two phrases, and nothing else\n\n<result-description>\n{}\n</result-description>\n\n<first-result>\n{}</first-result>\n\n<second-result-diff>\n{}</second-result-diff>\n\n<first-compile-errors>\n{}</first-compile-errors>\n\n<second-compile-errors>\n{}</second-compile-errors>",
+        FIRST_BETTER, SECOND_BETTER, description, original, diff, first_errors.join("\n"), second_errors.join("\n")
+    )
+}
+
+/// Builds the evaluation prompt for the given mode.
+pub fn prompt(
+    mode: JudgeMode,
+    description: &str,
+    original: &str,
+    candidate: &str,
+    first_errors: &[String],
+    second_errors: &[String],
+    diff_context: usize,
+) -> String {
+    match mode {
+        JudgeMode::Full => full_prompt(description, original, candidate, first_errors, second_errors),
+        JudgeMode::Diff => {
+            let diff = unified_diff(original, candidate, diff_context);
+            diff_prompt(description, original, &diff, first_errors, second_errors)
+        }
+    }
+}
+
+/// What a judge was asked to compare, kept so that the comparison can be replayed exactly.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Inputs {
+    pub description: String,
+    pub first: String,
+    pub second: String,
+    pub first_errors: Vec<String>,
+    pub second_errors: Vec<String>,
+}
+
+/// The directory beside the evaluation request `req_path` that `save_inputs` writes to.
+pub fn inputs_dir(req_path: &str) -> String {
+    format!("{}-inputs", req_path.trim_end_matches(".txt"))
+}
+
+/// Writes `inputs` into `dir`, one file each: "description.txt", "first.txt", "second.txt",
+/// and the error lists as JSON arrays in "first-errors.json" and "second-errors.json".
+pub fn save_inputs(dir: &str, inputs: &Inputs) -> io::Result<()> {
+    let dir = Path::new(dir);
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("description.txt"), &inputs.description)?;
+    fs::write(dir.join("first.txt"), &inputs.first)?;
+    fs::write(dir.join("second.txt"), &inputs.second)?;
+    for (name, errors) in [("first-errors.json", &inputs.first_errors), ("second-errors.json", &inputs.second_errors)] {
+        fs::write(dir.join(name), serde_json::to_string_pretty(errors).map_err(io::Error::other)?)?;
+    }
+    Ok(())
+}
+
+/// Reads back what `save_inputs` wrote into `dir`; `None` if any of it is missing.
+pub fn load_inputs(dir: &str) -> Option<Inputs> {
+    let dir = Path::new(dir);
+    let read = |name: &str| fs::read_to_string(dir.join(name)).ok();
+    let errors = |name: &str| serde_json::from_str(&read(name)?).ok();
+    Some(Inputs {
+        description: read("description.txt")?,
+        first: read("first.txt")?,
+        second: read("second.txt")?,
+        first_errors: errors("first-errors.json")?,
+        second_errors: errors("second-errors.json")?,
+    })
+}
+
+/// Splits an error list joined by `full_prompt` back into its messages, each starting at a
+/// line that starts with "error", as rendered compiler errors do. Joining them again gives the
+/// same text.
+fn split_errors(text: &str) -> Vec<String> {
+    let mut errors: Vec<String> = Vec::new();
+    for line in text.split_inclusive('\n') {
+        match errors.last_mut() {
+            Some(last) if !line.starts_with("error") => last.push_str(line),
+            Some(last) => {
+                last.pop();
+                errors.push(line.to_string());
+            }
+            None => errors.push(line.to_string()),
+        }
+    }
+    errors
+}
+
+/// Splits `text`, which starts with `open`, at the first `close` after that: the text in
+/// between, and the text after `close`.
+fn section<'a>(text: &'a str, open: &str, close: &str) -> Option<(&'a str, &'a str)> {
+    let body = text.strip_prefix(open)?;
+    let end = body.find(close)?;
+    Some((&body[..end], &body[end + close.len()..]))
+}
+
+/// Takes the inputs back out of a request built by `full_prompt`, for runs recorded before
+/// `save_inputs` existed. The sections are read in the order of the prompt, each ending where
+/// its closing tag is followed by the opening tag of the next one, so that a result mentioning
+/// the tags is not cut apart unless it holds exactly that sequence.
+pub fn parse_full_prompt(text: &str) -> Option<Inputs> {
+    let rest = &text[text.find("<result-description>\n")?..];
+    let (description, rest) = section(rest, "<result-description>\n", "\n</result-description>\n\n<first-result>\n")?;
+    let (first, rest) = section(rest, "", "</first-result>\n\n<second-result>\n")?;
+    let (second, rest) = section(rest, "", "</second-result>\n\n<first-compile-errors>\n")?;
+    let (first_errors, rest) = section(rest, "", "</first-compile-errors>\n\n<second-compile-errors>\n")?;
+    let second_errors = rest.strip_suffix("</second-compile-errors>")?;
+    Some(Inputs {
+        description: description.to_string(),
+        first: first.to_string(),
+        second: second.to_string(),
+        first_errors: split_errors(first_errors),
+        second_errors: split_errors(second_errors),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_verdict() {
+        assert_eq!(parse_verdict("First result is better.\n"), Some(Verdict::First));
+        assert_eq!(parse_verdict(" The second implementation is better."), Some(Verdict::Second));
+        assert_eq!(parse_verdict("Both are fine."), None);
+    }
+
+    #[test]
+    fn test_diff_prompt_is_smaller_than_full() {
+        let original: String = (0..1000).map(|i| format!("line {}\n", i)).collect();
+        let candidate = original.replace("line 100\n", "line one hundred\n");
+        let full = prompt(JudgeMode::Full, "desc", &original, &candidate, &[], &[], 3);
+        let diff = prompt(JudgeMode::Diff, "desc", &original, &candidate, &[], &[], 3);
+        assert!(diff.len() < full.len() * 3 / 5);
+        assert!(diff.contains("-line 100\n+line one hundred\n"));
+    }
+
+    #[test]
+    fn test_inputs_roundtrip() {
+        // Results that mention the tags themselves, as this module does.
+        let inputs = Inputs {
+            description: "the spec\n".to_string(),
+            first: "let open = \"<first-result>\";\nlet close = \"</second-result>\";\n".to_string(),
+            second: "// <second-compile-errors></second-compile-errors>\nnew\n".to_string(),
+            first_errors: vec!["error: boom\n --> a.rs:1:1\n".to_string(), "error[E0425]: x\n".to_string()],
+            second_errors: Vec::new(),
+        };
+        let text = full_prompt(
+            &inputs.description,
+            &inputs.first,
+            &inputs.second,
+            &inputs.first_errors,
+            &inputs.second_errors,
+        );
+        assert_eq!(parse_full_prompt(&text), Some(inputs.clone()));
+        assert_eq!(parse_full_prompt("<first-result>\nold\n</first-result>"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let req_path = dir.path().join("eval.txt").to_string_lossy().into_owned();
+        let inputs_dir = inputs_dir(&req_path);
+        assert!(inputs_dir.ends_with("eval-inputs"));
+        save_inputs(&inputs_dir, &inputs).unwrap();
+        assert_eq!(load_inputs(&inputs_dir), Some(inputs));
+        assert_eq!(load_inputs(&dir.path().to_string_lossy()), None);
+    }
+}
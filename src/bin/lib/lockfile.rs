@@ -0,0 +1,223 @@
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::atomic::{self, OutputLock};
+use super::git::{self, content_hash};
+use super::preprocess;
+
+/// Records, per spec and output pair, which version of the spec the output was last generated
+/// or checked against. Lives in the crate root and is committed with the sources.
+pub const LOCK_FILE: &str = "instruct.lock";
+
+const HEADER: &str = "# Written by the llm drivers: the spec and output each result was accepted or kept with.\n# Checked by \"llm-make --verify\".\n\n";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub spec: String,
+    pub output: String,
+    /// `content_hash` of the fully preprocessed spec.
+    pub spec_hash: String,
+    pub output_hash: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub verdict: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Lockfile {
+    #[serde(default, rename = "target")]
+    pub entries: Vec<Entry>,
+}
+
+/// How an output relates to its lock entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Status {
+    UpToDate,
+    /// The pair has no entry yet.
+    Untracked,
+    /// The output file does not exist.
+    MissingOutput,
+    /// The spec, or a file it includes, changed since the output was accepted or kept.
+    SpecChanged,
+    /// The output was edited since; the spec did not change.
+    OutputEdited,
+}
+
+impl Status {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::UpToDate => "up-to-date",
+            Status::Untracked => "untracked",
+            Status::MissingOutput => "missing-output",
+            Status::SpecChanged => "spec-changed",
+            Status::OutputEdited => "output-edited",
+        }
+    }
+}
+
+/// The hash of the fully preprocessed spec; `None` if it, or a file it includes, is missing.
+pub fn spec_hash(spec: &str) -> Option<String> {
+    if !Path::new(spec).exists() || preprocess::includes(spec).iter().any(|i| !Path::new(i).exists()) {
+        return None;
+    }
+    Some(content_hash(preprocess::preprocess(spec)))
+}
+
+pub fn output_hash(output: &str) -> Option<String> {
+    fs::read(output).ok().map(content_hash)
+}
+
+impl Lockfile {
+    /// Reads `LOCK_FILE`; an empty lock if there is none.
+    pub fn load() -> Self {
+        let Ok(text) = fs::read_to_string(LOCK_FILE) else {
+            return Lockfile::default();
+        };
+        toml::from_str(&text).unwrap_or_else(|e| panic!("Failed to parse {}: {}", LOCK_FILE, e))
+    }
+
+    /// Writes `LOCK_FILE` atomically, with the entries sorted by output file.
+    pub fn save(&mut self) {
+        atomic::write(LOCK_FILE, self.text()).unwrap_or_else(|e| panic!("Failed to write {}: {}", LOCK_FILE, e));
+    }
+
+    /// The content of `LOCK_FILE` for these entries, sorted by output file.
+    fn text(&mut self) -> String {
+        self.entries.sort_by(|a, b| a.output.cmp(&b.output));
+        let text = toml::to_string(self).expect("Failed to serialize lock file");
+        format!("{}{}", HEADER, text)
+    }
+
+    pub fn get(&self, output: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.output == output)
+    }
+
+    /// Adds `entry`, replacing the one for the same output.
+    pub fn insert(&mut self, entry: Entry) {
+        self.entries.retain(|e| e.output != entry.output);
+        self.entries.push(entry);
+    }
+
+    pub fn status(&self, spec: &str, output: &str) -> Status {
+        let Some(current_output) = output_hash(output) else {
+            return Status::MissingOutput;
+        };
+        let Some(entry) = self.get(output) else {
+            return Status::Untracked;
+        };
+        if spec_hash(spec).as_ref() != Some(&entry.spec_hash) {
+            return Status::SpecChanged;
+        }
+        if current_output != entry.output_hash {
+            return Status::OutputEdited;
+        }
+        Status::UpToDate
+    }
+}
+
+/// The lock on `LOCK_FILE`, waiting up to a minute for it.
+fn lock() -> OutputLock {
+    let mut waited = 0;
+    loop {
+        match OutputLock::acquire(LOCK_FILE) {
+            Ok(lock) => return lock,
+            Err(e) if waited >= 600 => panic!("Failed to take the lock on {}: {}", LOCK_FILE, e),
+            Err(_) => {
+                thread::sleep(Duration::from_millis(100));
+                waited += 1;
+            }
+        }
+    }
+}
+
+/// Reads, changes and writes `LOCK_FILE` while holding its lock, so that drivers running in
+/// parallel do not lose each other's entries. Waits up to a minute for the lock.
+pub fn update(change: impl FnOnce(&mut Lockfile)) {
+    let lock = lock();
+    let mut lockfile = Lockfile::load();
+    change(&mut lockfile);
+    lockfile.save();
+    lock.release();
+}
+
+/// Calls `commit` with the content of `LOCK_FILE` to commit along with `outputs`: the one at
+/// HEAD with the current entries of `outputs`, and not the entries other drivers running in
+/// parallel recorded meanwhile. Holds the lock meanwhile, so that neither the lock file nor
+/// HEAD move under another driver doing the same.
+pub fn commit_entries<T>(outputs: &[String], commit: impl FnOnce(Vec<u8>) -> T) -> T {
+    let lock = lock();
+    let mut committed: Lockfile = git::committed(LOCK_FILE)
+        .map(|text| {
+            toml::from_str(&String::from_utf8_lossy(&text))
+                .unwrap_or_else(|e| panic!("Failed to parse {} at HEAD: {}", LOCK_FILE, e))
+        })
+        .unwrap_or_default();
+    let current = Lockfile::load();
+    for output in outputs {
+        match current.get(output) {
+            Some(entry) => committed.insert(entry.clone()),
+            None => committed.entries.retain(|e| &e.output != output),
+        }
+    }
+    let result = commit(committed.text().into_bytes());
+    lock.release();
+    result
+}
+
+/// Records that `output`, as it is now, was accepted or kept for `spec` as it is now. An empty
+/// `model` keeps the model already recorded, as a kept output was generated earlier.
+/// Skipped with a warning if the spec can not be preprocessed.
+pub fn record(spec: &str, output: &str, model: &str, verdict: &str) {
+    let (Some(spec_hash), Some(output_hash)) = (spec_hash(spec), output_hash(output)) else {
+        eprintln!("Warning: can not hash {} and {}, not recording them in {}", spec, output, LOCK_FILE);
+        return;
+    };
+    update(|lockfile| {
+        let model = match (model, lockfile.get(output)) {
+            ("", Some(previous)) => previous.model.clone(),
+            _ => model.to_string(),
+        };
+        lockfile.insert(Entry {
+            spec: spec.to_string(),
+            output: output.to_string(),
+            spec_hash,
+            output_hash,
+            model,
+            verdict: verdict.to_string(),
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut lockfile = Lockfile::default();
+        let entry = Entry {
+            spec: "instruct/bin/wcr.md".to_string(),
+            output: "src/bin/wcr.rs".to_string(),
+            spec_hash: content_hash("spec"),
+            output_hash: content_hash("output"),
+            model: "groq:moonshotai/kimi-k2-instruct".to_string(),
+            verdict: "Second (llm judge: SECOND IS BETTER)".to_string(),
+        };
+        lockfile.insert(entry.clone());
+        lockfile.insert(Entry {
+            model: "anthropic:claude-sonnet-4-20250514".to_string(),
+            ..entry.clone()
+        });
+        assert_eq!(lockfile.entries.len(), 1);
+
+        let text = toml::to_string(&lockfile).unwrap();
+        let parsed: Lockfile = toml::from_str(&format!("{}{}", HEADER, text)).unwrap();
+        assert_eq!(parsed, lockfile);
+        assert_eq!(parsed.get("src/bin/wcr.rs").unwrap().model, "anthropic:claude-sonnet-4-20250514");
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::config;
+use super::frontmatter;
+
+/// A rule mapping spec paths to output paths, with one "%" standing for the same text in both,
+/// as in the Makefile rule "src/%.rs: instruct/%.md". Outputs need not mirror the specs or be
+/// Rust: "instruct/tests/%.md" can map to "tests/%.rs", "instruct/manifest/%.md" to "%.toml".
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub spec: String,
+    pub output: String,
+}
+
+/// `path` with the text matching the "%" of `from` put in place of the "%" of `to`.
+fn substitute(from: &str, to: &str, path: &str) -> Option<String> {
+    let (prefix, suffix) = from.split_once('%')?;
+    let stem = path.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    if stem.is_empty() {
+        return None;
+    }
+    Some(to.replacen('%', stem, 1))
+}
+
+impl Rule {
+    /// The rule pairing every "<spec_dir>/<path>.md" with "<output_dir>/<path>.rs".
+    pub fn mirror(spec_dir: &str, output_dir: &str) -> Rule {
+        Rule {
+            spec: format!("{}/%.md", spec_dir.trim_end_matches('/')),
+            output: format!("{}/%.rs", output_dir.trim_end_matches('/')),
+        }
+    }
+
+    /// The output of `spec` if it matches this rule.
+    pub fn apply(&self, spec: &str) -> Option<String> {
+        substitute(&self.spec, &self.output, spec)
+    }
+
+    /// The directory the outputs of this rule are in, "" for the crate root.
+    pub fn output_dir(&self) -> &str {
+        let prefix = self.output.split('%').next().unwrap_or_default();
+        prefix.rfind('/').map_or("", |end| &prefix[..end])
+    }
+
+    /// What the outputs of this rule end with, e.g. ".rs".
+    pub fn output_suffix(&self) -> &str {
+        self.output.split_once('%').map_or("", |(_, suffix)| suffix)
+    }
+}
+
+/// The output of `spec` by the mapping rules of its configuration; `None` if no rule matches.
+pub fn rule_output_for(spec: &str) -> Option<String> {
+    match config::for_spec(spec) {
+        Ok(config) => config.output_for(spec),
+        Err(_) => config::project().output_for(spec),
+    }
+}
+
+/// The output of `spec`: its "output" setting, or else by the mapping rules. Invalid settings
+/// are warned about and ignored.
+pub fn output_for(spec: &str) -> Option<String> {
+    if !Path::new(spec).exists() {
+        return rule_output_for(spec);
+    }
+    match frontmatter::settings_for(spec) {
+        Ok(settings) => settings.output.or_else(|| rule_output_for(spec)),
+        Err(e) => {
+            eprintln!("Warning: {}, ignoring the settings of {}", e, spec);
+            rule_output_for(spec)
+        }
+    }
+}
+
+/// The further files `spec` generates together with its output, from its "outputs" setting.
+pub fn extra_outputs(spec: &str) -> Vec<String> {
+    frontmatter::settings_for(spec)
+        .ok()
+        .and_then(|settings| settings.outputs)
+        .unwrap_or_default()
+}
+
+/// The outputs to move along when `spec` is renamed to `new_spec`: its output and the one the
+/// mapping rules give for the new name. `None` if the output stays, as when the spec names it
+/// with an "output" setting, or if either name has no output.
+pub fn renamed_output(spec: &str, new_spec: &str) -> Option<(String, String)> {
+    let output = rule_output_for(spec)?;
+    if output_for(spec).as_ref() != Some(&output) {
+        return None;
+    }
+    let new_output = rule_output_for(new_spec)?;
+    (new_output != output).then_some((output, new_output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules() {
+        let tests = Rule {
+            spec: "instruct/tests/%.md".to_string(),
+            output: "tests/%.rs".to_string(),
+        };
+        assert_eq!(tests.apply("instruct/tests/cli.md").as_deref(), Some("tests/cli.rs"));
+        assert_eq!(tests.apply("instruct/bin/wcr.md"), None);
+        assert_eq!(tests.output_dir(), "tests");
+        assert_eq!(tests.output_suffix(), ".rs");
+
+        let manifest = Rule {
+            spec: "instruct/manifest/%.md".to_string(),
+            output: "%.toml".to_string(),
+        };
+        assert_eq!(manifest.apply("instruct/manifest/Cargo.md").as_deref(), Some("Cargo.toml"));
+        assert_eq!(manifest.output_dir(), "");
+
+        let mirror = Rule::mirror("instruct/", "src");
+        assert_eq!(mirror.apply("instruct/bin/lib/groq.md").as_deref(), Some("src/bin/lib/groq.rs"));
+        assert_eq!(mirror.apply("instruct/bin/lib/groq.toml"), None);
+    }
+
+    #[test]
+    fn test_output_for() {
+        assert_eq!(rule_output_for("instruct/bin/wcr.md").as_deref(), Some("src/bin/wcr.rs"));
+        assert_eq!(rule_output_for("./instruct/bin/lib/groq.md").as_deref(), Some("src/bin/lib/groq.rs"));
+        assert_eq!(rule_output_for("include/common.md"), None);
+        assert_eq!(rule_output_for("instruct/bin/notes.txt"), None);
+        assert_eq!(
+            renamed_output("instruct/bin/wcr.md", "instruct/bin/wc.md"),
+            Some(("src/bin/wcr.rs".to_string(), "src/bin/wc.rs".to_string()))
+        );
+        assert_eq!(renamed_output("instruct/bin/wcr.md", "include/wcr.md"), None);
+    }
+}
@@ -0,0 +1,109 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs;
+
+use serde::Deserialize;
+
+use super::error::{ErrorKind, LlmError};
+use super::provider::{AssistantTurn, Message, Provider, ToolSpec};
+
+/// A scripted provider for offline runs and tests: every call consumes the next scripted reply.
+pub struct MockProvider {
+    name: String,
+    replies: RefCell<VecDeque<Result<AssistantTurn, LlmError>>>,
+    requests: RefCell<Vec<String>>,
+}
+
+/// A script entry is a plain text reply, a failure such as `{"error": "overload"}`,
+/// or a full assistant turn with tool calls.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScriptEntry {
+    Text(String),
+    Error {
+        error: String,
+        #[serde(default)]
+        message: String,
+    },
+    Turn(AssistantTurn),
+}
+
+impl MockProvider {
+    #[cfg(test)]
+    pub fn new(replies: Vec<AssistantTurn>) -> Self {
+        Self::from_results("mock:inline", replies.into_iter().map(Ok).collect())
+    }
+
+    /// A provider whose calls may also fail, for exercising retries and fallbacks.
+    pub fn from_results(name: &str, replies: Vec<Result<AssistantTurn, LlmError>>) -> Self {
+        MockProvider {
+            name: name.to_string(),
+            replies: RefCell::new(replies.into()),
+            requests: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Loads the replies from a JSON array of strings, `{"text": .., "tool_calls": [..]}` objects
+    /// and `{"error": "auth|overload|network|other", "message": ..}` failures.
+    pub fn from_script(path: &str) -> Self {
+        let script = fs::read_to_string(path).unwrap_or_else(|_| panic!("Unable to read mock script {}", path));
+        let entries: Vec<ScriptEntry> =
+            serde_json::from_str(&script).unwrap_or_else(|e| panic!("Invalid mock script {}: {}", path, e));
+        let replies = entries
+            .into_iter()
+            .map(|e| match e {
+                ScriptEntry::Text(text) => Ok(AssistantTurn { text, tool_calls: Vec::new() }),
+                ScriptEntry::Error { error, message } => {
+                    let kind = ErrorKind::parse(&error)
+                        .unwrap_or_else(|| panic!("Invalid error kind {} in mock script {}", error, path));
+                    Err(LlmError::new(kind, message))
+                }
+                ScriptEntry::Turn(turn) => Ok(turn),
+            })
+            .collect();
+        Self::from_results(&format!("mock:{}", path), replies)
+    }
+
+    /// The requests seen so far: prompts, or the last message of each chat step.
+    #[cfg(test)]
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.borrow().clone()
+    }
+
+    fn next_reply(&self) -> Result<AssistantTurn, LlmError> {
+        let mut turn = self
+            .replies
+            .borrow_mut()
+            .pop_front()
+            .expect("Mock provider ran out of scripted replies")?;
+        let step = self.requests.borrow().len();
+        for (i, call) in turn.tool_calls.iter_mut().enumerate() {
+            if call.id.is_empty() {
+                call.id = format!("call-{}-{}", step, i);
+            }
+        }
+        Ok(turn)
+    }
+}
+
+impl Provider for MockProvider {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn try_evaluate_with(&self, prompt: &str, _temperature: Option<f32>, _seed: Option<u64>) -> Result<String, LlmError> {
+        let reply = self.next_reply();
+        self.requests.borrow_mut().push(prompt.to_string());
+        reply.map(|turn| turn.text)
+    }
+
+    fn try_chat(&self, messages: &[Message], _tools: &[ToolSpec]) -> Result<AssistantTurn, LlmError> {
+        let reply = self.next_reply();
+        let last = messages
+            .last()
+            .map(|m| serde_json::to_string(m).unwrap_or_default())
+            .unwrap_or_default();
+        self.requests.borrow_mut().push(last);
+        reply
+    }
+}
@@ -1,4 +1,26 @@
-pub mod ollama;
-pub mod groq;
+// Generated by `llm-make --modules` from the instruct tree; do not edit.
+// To maintain it by hand instead, add the line "// llm-templated: manual".
 
+pub mod agent;
+pub mod anthropic;
+pub mod atomic;
+pub mod cargo;
+pub mod config;
+pub mod critic;
+pub mod deterministic;
+pub mod error;
+pub mod frontmatter;
+pub mod git;
+pub mod groq;
+pub mod history;
+pub mod judge;
+pub mod lockfile;
+pub mod mapping;
+pub mod mock;
+pub mod modules;
+pub mod multifile;
+pub mod ollama;
 pub mod preprocess;
+pub mod provider;
+pub mod runs;
+pub mod targets;
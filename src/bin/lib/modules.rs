@@ -0,0 +1,164 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::targets;
+
+/// The first line of a generated module declaration file.
+pub const GENERATED_HEADER: &str = "// Generated by `llm-make --modules` from the instruct tree; do not edit.";
+
+/// A mod.rs with a comment line of just this is maintained by hand and never regenerated.
+pub const MANUAL_MARKER: &str = "llm-templated: manual";
+
+/// Whether `content` has the manual marker on a comment line of its own.
+fn is_manual(content: &str) -> bool {
+    content
+        .lines()
+        .any(|line| line.trim().strip_prefix("//").is_some_and(|comment| comment.trim() == MANUAL_MARKER))
+}
+
+/// The name of the module `path` declares: the stem of a ".rs" file other than mod.rs, or a
+/// directory with a mod.rs. `None` for anything else, or a name that is not an identifier.
+fn module_name(path: &Path) -> Option<String> {
+    let name = if path.is_dir() {
+        if !path.join("mod.rs").exists() {
+            return None;
+        }
+        path.file_name()?.to_str()?
+    } else {
+        if path.extension().is_none_or(|e| e != "rs") {
+            return None;
+        }
+        path.file_stem()?.to_str()?
+    };
+    let identifier = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && name.chars().next().is_some_and(|c| !c.is_ascii_digit());
+    (identifier && name != "mod").then(|| name.to_string())
+}
+
+/// The modules in `dir`, sorted.
+pub fn modules_in(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let modules: BTreeSet<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| module_name(&entry.path()))
+        .collect();
+    modules.into_iter().collect()
+}
+
+/// The generated mod.rs declaring `modules`.
+pub fn render(modules: &[String]) -> String {
+    let mut content = format!(
+        "{}\n// To maintain it by hand instead, add the line \"// {}\".\n\n",
+        GENERATED_HEADER, MANUAL_MARKER
+    );
+    for module in modules {
+        content.push_str(&format!("pub mod {};\n", module));
+    }
+    content
+}
+
+/// Whether `path` is a generated mod.rs, which is rewritten as modules come and go.
+pub fn is_generated(path: &Path) -> bool {
+    fs::read_to_string(path).is_ok_and(|content| {
+        content.lines().next() == Some(GENERATED_HEADER) && !is_manual(&content)
+    })
+}
+
+/// Writes the mod.rs of `dir` declaring the modules in it, unless it carries the manual marker.
+/// Returns whether the file changed.
+pub fn sync(dir: &Path) -> Result<bool, String> {
+    let path = dir.join("mod.rs");
+    let existing = fs::read_to_string(&path).ok();
+    if existing.as_deref().is_some_and(is_manual) {
+        return Ok(false);
+    }
+    let content = render(&modules_in(dir));
+    if existing.as_ref() == Some(&content) {
+        return Ok(false);
+    }
+    fs::write(&path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(true)
+}
+
+/// `sync` for a directory whose mod.rs is already generated, e.g. after adding a file to it;
+/// a hand-written or missing mod.rs is left alone.
+pub fn refresh(dir: &Path) -> Result<bool, String> {
+    if !is_generated(&dir.join("mod.rs")) {
+        return Ok(false);
+    }
+    sync(dir)
+}
+
+/// The directories that get a generated mod.rs: those that the specs below the instruct
+/// directory put their outputs in, and the directories between them and "src/bin" or "src",
+/// deepest first so that a directory's modules are declared before it is.
+pub fn module_dirs() -> Vec<PathBuf> {
+    let roots = [Path::new("src"), Path::new("src/bin")];
+    let mut dirs = BTreeSet::new();
+    for target in targets::discover() {
+        let mut dir = Path::new(&target.output).parent();
+        while let Some(current) = dir {
+            if !current.starts_with("src") || roots.contains(&current) {
+                break;
+            }
+            dirs.insert(current.to_path_buf());
+            dir = current.parent();
+        }
+    }
+    let mut dirs: Vec<PathBuf> = dirs.into_iter().collect();
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    dirs
+}
+
+/// Syncs the mod.rs of every module directory, returning the paths that changed.
+pub fn sync_all() -> Result<Vec<String>, String> {
+    let mut written = Vec::new();
+    for dir in module_dirs() {
+        if sync(&dir)? {
+            written.push(dir.join("mod.rs").to_string_lossy().into_owned());
+        }
+    }
+    Ok(written)
+}
+
+/// The generated mod.rs beside `output`, if there is one.
+pub fn generated_beside(output: &str) -> Option<String> {
+    let path = Path::new(output).parent()?.join("mod.rs");
+    is_generated(&path).then(|| path.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib = dir.path().join("lib");
+        fs::create_dir_all(lib.join("providers")).unwrap();
+        for file in ["groq.rs", "anthropic.rs", "notes.txt", "llm-groq.rs", "providers/mod.rs"] {
+            fs::write(lib.join(file), "").unwrap();
+        }
+        assert_eq!(modules_in(&lib), vec!["anthropic", "groq", "providers"]);
+
+        assert!(sync(&lib).unwrap());
+        let content = fs::read_to_string(lib.join("mod.rs")).unwrap();
+        assert!(content.starts_with(GENERATED_HEADER));
+        assert!(content.ends_with("\".\n\npub mod anthropic;\npub mod groq;\npub mod providers;\n"));
+        assert!(is_generated(&lib.join("mod.rs")));
+        assert!(!sync(&lib).unwrap());
+
+        fs::write(lib.join("ollama.rs"), "").unwrap();
+        assert!(refresh(&lib).unwrap());
+        assert!(fs::read_to_string(lib.join("mod.rs")).unwrap().contains("pub mod ollama;\n"));
+
+        let manual = format!("// {}\npub mod groq;\n", MANUAL_MARKER);
+        fs::write(lib.join("mod.rs"), &manual).unwrap();
+        assert!(!sync(&lib).unwrap());
+        assert!(!refresh(&lib).unwrap());
+        assert_eq!(fs::read_to_string(lib.join("mod.rs")).unwrap(), manual);
+    }
+}
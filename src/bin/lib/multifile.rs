@@ -0,0 +1,114 @@
+/// Starts every file of a multi-file result: "=== FILE: <path> ===" on a line of its own.
+const MARKER_START: &str = "=== FILE: ";
+const MARKER_END: &str = " ===";
+
+/// The instructions appended to the prompts of a spec with several outputs, asking for all of
+/// them in one response.
+pub fn instructions(outputs: &[String]) -> String {
+    let mut text = String::from(
+        "\n\nThe result consists of several files. Output every one of them in full, each starting with a line of its own naming it, exactly like this:\n",
+    );
+    for output in outputs {
+        text.push_str(&format!("{}{}{}\n", MARKER_START, output, MARKER_END));
+    }
+    text.push_str("Output each of the files listed above exactly once, and nothing else: no other files, no text before the first file line, no markdown separators.");
+    text
+}
+
+/// The files as a single multi-file result, the form in which they are judged and saved as drafts.
+/// A file not ending with a newline gets one, so that the next file line starts a line.
+pub fn render(files: &[(String, String)]) -> String {
+    let mut text = String::new();
+    for (path, content) in files {
+        text.push_str(&format!("{}{}{}\n", MARKER_START, path, MARKER_END));
+        text.push_str(content);
+        if !content.is_empty() && !content.ends_with('\n') {
+            text.push('\n');
+        }
+    }
+    text
+}
+
+/// The current contents of `outputs` as a multi-file result, files that do not exist as empty.
+pub fn read(outputs: &[String]) -> String {
+    let files: Vec<(String, String)> = outputs
+        .iter()
+        .map(|output| (output.clone(), std::fs::read_to_string(output).unwrap_or_default()))
+        .collect();
+    render(&files)
+}
+
+/// Splits a multi-file result into its files, in the order of `outputs`. Text before the first
+/// file line is ignored. The content of a file is everything between its file line and the
+/// next one, verbatim: trailing whitespace and an empty file are kept as they are. Fails if a
+/// file is missing, repeated or not one of `outputs`.
+pub fn parse(text: &str, outputs: &[String]) -> Result<Vec<(String, String)>, String> {
+    let mut files: Vec<(String, String)> = Vec::new();
+    for line in text.split_inclusive('\n') {
+        let marker = line
+            .trim()
+            .strip_prefix(MARKER_START)
+            .and_then(|rest| rest.strip_suffix(MARKER_END));
+        match (marker, files.last_mut()) {
+            (Some(path), _) => {
+                let path = path.trim();
+                if !outputs.iter().any(|o| o == path) {
+                    return Err(format!("unexpected file {} in the result", path));
+                }
+                if files.iter().any(|(p, _)| p == path) {
+                    return Err(format!("file {} appears twice in the result", path));
+                }
+                files.push((path.to_string(), String::new()));
+            }
+            (None, Some((_, content))) => content.push_str(line),
+            (None, None) => {}
+        }
+    }
+    let mut ordered = Vec::new();
+    for output in outputs {
+        let Some(index) = files.iter().position(|(path, _)| path == output) else {
+            return Err(format!("file {} is missing from the result", output));
+        };
+        ordered.push(files.swap_remove(index));
+    }
+    Ok(ordered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let outputs = vec!["src/bin/lib/shapes.rs".to_string(), "tests/shapes.rs".to_string()];
+        let files = vec![
+            (outputs[0].clone(), "pub fn area() -> u32 {\n    4\n}\n".to_string()),
+            (outputs[1].clone(), "#[test]\nfn area() {}\n".to_string()),
+        ];
+        let text = render(&files);
+        assert_eq!(parse(&text, &outputs).unwrap(), files);
+
+        let reordered = format!("Here you go:\n{}", render(&[files[1].clone(), files[0].clone()]));
+        assert_eq!(parse(&reordered, &outputs).unwrap(), files);
+
+        // Contents are kept verbatim, an empty file and trailing blank lines included.
+        let verbatim = vec![
+            (outputs[0].clone(), String::new()),
+            (outputs[1].clone(), "fn main() {}\n\n\n".to_string()),
+        ];
+        assert_eq!(parse(&render(&verbatim), &outputs).unwrap(), verbatim);
+        let crlf = "=== FILE: src/bin/lib/shapes.rs ===\r\n=== FILE: tests/shapes.rs ===\r\nx \r\n";
+        assert_eq!(parse(crlf, &outputs).unwrap()[1].1, "x \r\n");
+        assert_eq!(parse(crlf, &outputs).unwrap()[0].1, "");
+        assert!(instructions(&outputs).contains("=== FILE: tests/shapes.rs ===\n"));
+    }
+
+    #[test]
+    fn test_invalid_results() {
+        let outputs = vec!["a.rs".to_string(), "b.rs".to_string()];
+        assert!(parse("=== FILE: a.rs ===\nx\n", &outputs).unwrap_err().contains("b.rs is missing"));
+        assert!(parse("=== FILE: a.rs ===\n=== FILE: c.rs ===\n", &outputs).unwrap_err().contains("unexpected"));
+        assert!(parse("=== FILE: a.rs ===\n=== FILE: a.rs ===\n", &outputs).unwrap_err().contains("twice"));
+        assert!(parse("fn main() {}\n", &outputs).is_err());
+    }
+}
@@ -1,17 +1,44 @@
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 pub fn preprocess(file_name: &str) -> String {
-    preprocess_with_depth(file_name, 0)
+    preprocess_tracked(file_name).0
 }
 
-fn preprocess_with_depth(file_name: &str, depth: usize) -> String {
+/// Like `preprocess`, but also returns the files read during the expansion: the file itself
+/// first, then every included file once, in the order they are first read.
+pub fn preprocess_tracked(file_name: &str) -> (String, Vec<String>) {
+    let mut touched = Vec::new();
+    let mut unreadable = Vec::new();
+    let output = preprocess_with_depth(file_name, 0, &mut touched, &mut unreadable);
+    if let Some(file) = unreadable.first() {
+        panic!("Unable to read file {}", file);
+    }
+    (output, touched)
+}
+
+/// Expands `file_name`, recording in `touched` every file it meets, and in `unreadable` those
+/// that can not be read, which expand to nothing.
+fn preprocess_with_depth(
+    file_name: &str,
+    depth: usize,
+    touched: &mut Vec<String>,
+    unreadable: &mut Vec<String>,
+) -> String {
     if depth >= 32 {
         return "TOO MUCH NESTED INCLUDES".to_string();
     }
 
     let path = Path::new(file_name);
-    let contents = fs::read_to_string(&path).expect(&format!("Unable to read file {}", &file_name));
+    let normalized = normalize(file_name);
+    if !touched.contains(&normalized) {
+        touched.push(normalized);
+    }
+    let Ok(text) = fs::read_to_string(path) else {
+        unreadable.push(file_name.to_string());
+        return String::new();
+    };
+    let (_, contents) = split_front_matter(&text);
     let mut output = String::new();
     let mut cursor = 0;
 
@@ -36,7 +63,7 @@ fn preprocess_with_depth(file_name: &str, depth: usize) -> String {
                 .into_owned()
         };
 
-        let included = preprocess_with_depth(&resolved_path, depth + 1);
+        let included = preprocess_with_depth(&resolved_path, depth + 1, touched, unreadable);
         output.push_str(&contents[cursor..start]);
         output.push_str(&included);
         cursor = end + 2;
@@ -49,6 +76,64 @@ fn preprocess_with_depth(file_name: &str, depth: usize) -> String {
     output
 }
 
+/// Splits off the front matter: the lines between a first line "+++" and the next line "+++".
+/// Returns the front matter, if there is one, and the rest of the text after its closing line.
+pub fn split_front_matter(contents: &str) -> (Option<&str>, &str) {
+    let Some(rest) = contents.strip_prefix("+++\n").or_else(|| contents.strip_prefix("+++\r\n")) else {
+        return (None, contents);
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "+++" {
+            return (Some(&rest[..offset]), &rest[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+    (None, contents)
+}
+
+/// Removes "." and resolves ".." components without looking at the file system, so that
+/// "instruct/bin/../../include/common.md" becomes "include/common.md".
+fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "." => {}
+            "" if !parts.is_empty() => {}
+            ".." if parts.last().is_some_and(|p| *p != ".." && !p.is_empty()) => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    if parts.is_empty() { ".".to_string() } else { parts.join("/") }
+}
+
+fn escape_make(path: &str) -> String {
+    path.replace('$', "$$").replace(' ', "\\ ").replace('#', "\\#")
+}
+
+/// A Make dependency file stating that `targets` depend on `prerequisites`, with an empty rule
+/// for each prerequisite other than the first, so that make does not fail when an included
+/// file is removed.
+pub fn depfile(targets: &[&str], prerequisites: &[String]) -> String {
+    let targets: Vec<String> = targets.iter().map(|t| escape_make(t)).collect();
+    let prerequisites: Vec<String> = prerequisites.iter().map(|p| escape_make(p)).collect();
+    let mut depfile = format!("{}: {}\n", targets.join(" "), prerequisites.join(" "));
+    for prerequisite in prerequisites.iter().skip(1) {
+        depfile.push_str(&format!("\n{}:\n", prerequisite));
+    }
+    depfile
+}
+
+/// The files that `preprocess(file_name)` would include, directly or through other includes,
+/// each listed once in the order they are first met. Missing files are listed too.
+pub fn includes(file_name: &str) -> Vec<String> {
+    let mut touched = Vec::new();
+    preprocess_with_depth(file_name, 0, &mut touched, &mut Vec::new());
+    touched.into_iter().skip(1).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -174,6 +259,62 @@ mod tests {
         assert!(result.contains("TOO MUCH NESTED INCLUDES"));
     }
 
+    #[test]
+    fn test_includes() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("inner.txt"), "inner\n").unwrap();
+        fs::write(dir.path().join("middle.txt"), "{!inner.txt!}{!missing.txt!}").unwrap();
+        let main_path = dir.path().join("main.txt");
+        fs::write(&main_path, "{!middle.txt!} {!inner.txt!}").unwrap();
+
+        let found = includes(main_path.to_str().unwrap());
+        let names: Vec<String> = found
+            .iter()
+            .map(|p| Path::new(p).file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["middle.txt", "inner.txt", "missing.txt"]);
+    }
+
+    #[test]
+    fn test_tracked_and_depfile() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("specs")).unwrap();
+        fs::write(dir.path().join("common.md"), "common\n").unwrap();
+        let main_path = dir.path().join("specs").join("main.md");
+        fs::write(&main_path, "{!../common.md!}main\n{!../common.md!}").unwrap();
+
+        let (output, touched) = preprocess_tracked(main_path.to_str().unwrap());
+        assert_eq!(output, "common\nmain\ncommon\n");
+        let root = dir.path().to_str().unwrap();
+        assert_eq!(
+            touched,
+            vec![format!("{}/specs/main.md", root), format!("{}/common.md", root)]
+        );
+
+        assert_eq!(normalize("./instruct/bin/../../include/common.md"), "include/common.md");
+        assert_eq!(
+            depfile(&["src/a.rs", "deps/a.d"], &["instruct/a.md".to_string(), "my include.md".to_string()]),
+            "src/a.rs deps/a.d: instruct/a.md my\\ include.md\n\nmy\\ include.md:\n"
+        );
+    }
+
+    #[test]
+    fn test_front_matter() {
+        assert_eq!(
+            split_front_matter("+++\nmodel = \"m\"\n+++\n# Spec\n"),
+            (Some("model = \"m\"\n"), "# Spec\n")
+        );
+        assert_eq!(split_front_matter("+++\n+++"), (Some(""), ""));
+        assert_eq!(split_front_matter("+++\nno closing line\n"), (None, "+++\nno closing line\n"));
+        assert_eq!(split_front_matter("text\n+++\n"), (None, "text\n+++\n"));
+
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("part.md"), "+++\ndriver = \"x\"\n+++\npart\n").unwrap();
+        let main_path = dir.path().join("main.md");
+        fs::write(&main_path, "+++\nmodel = \"m\"\n+++\nmain {!part.md!}").unwrap();
+        assert_eq!(preprocess(main_path.to_str().unwrap()), "main part\n");
+    }
+
     #[test]
     fn test_circular_include() {
         let dir = tempdir().unwrap();
@@ -0,0 +1,489 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use super::anthropic::Anthropic;
+use super::config::{Config, ProviderConfig};
+use super::error::{ErrorKind, LlmError};
+use super::groq::Groq;
+use super::mock::MockProvider;
+use super::ollama::OllamaClient;
+
+/// How many times a transient failure is retried before giving up on a provider.
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// The pause before the first retry; it doubles with every further retry.
+pub const DEFAULT_BACKOFF: Duration = Duration::from_secs(2);
+
+/// A tool the model may call, described by a JSON schema of its arguments.
+#[derive(Debug, Clone)]
+pub struct ToolSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub parameters: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+/// What the model said in one step: some text and possibly tool calls.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AssistantTurn {
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCall>,
+}
+
+/// A provider-neutral conversation entry.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "role", rename_all = "snake_case")]
+pub enum Message {
+    User { content: String },
+    Assistant { turn: AssistantTurn },
+    Tool { call_id: String, name: String, content: String },
+}
+
+/// A model behind some API.
+pub trait Provider {
+    /// "<provider>:<model>", the same form `from_spec_in` accepts.
+    fn name(&self) -> String;
+
+    fn try_evaluate_with(&self, prompt: &str, temperature: Option<f32>, seed: Option<u64>) -> Result<String, LlmError>;
+
+    /// One step of a tool-calling conversation.
+    fn try_chat(&self, messages: &[Message], tools: &[ToolSpec]) -> Result<AssistantTurn, LlmError>;
+
+    fn evaluate_with(&self, prompt: &str, temperature: Option<f32>, seed: Option<u64>) -> String {
+        self.try_evaluate_with(prompt, temperature, seed)
+            .unwrap_or_else(|e| panic!("Request to {} failed: {}", self.name(), e))
+    }
+
+    fn evaluate(&self, prompt: &str) -> String {
+        self.evaluate_with(prompt, None, None)
+    }
+
+    fn chat(&self, messages: &[Message], tools: &[ToolSpec]) -> AssistantTurn {
+        self.try_chat(messages, tools)
+            .unwrap_or_else(|e| panic!("Request to {} failed: {}", self.name(), e))
+    }
+}
+
+/// A model served by a local Ollama, at `OLLAMA_HOST` or the configured address.
+pub struct Ollama {
+    client: OllamaClient,
+    model: String,
+}
+
+impl Ollama {
+    pub fn with_provider(provider: &ProviderConfig, model: &str) -> Self {
+        let base_url = std::env::var("OLLAMA_HOST").unwrap_or_else(|_| provider.url.clone());
+        Ollama {
+            client: OllamaClient::new(base_url),
+            model: model.to_string(),
+        }
+    }
+}
+
+/// `spec` with the provider prefix that `from_spec_in` assumes when there is none, which is how
+/// the provider names itself, e.g. "groq:<model>" for a bare model name.
+pub fn canonical(spec: &str) -> String {
+    match spec.split_once(':') {
+        Some(("groq" | "anthropic" | "ollama" | "mock", _)) => spec.to_string(),
+        _ => format!("groq:{}", spec),
+    }
+}
+
+/// Creates a provider from "groq:<model>", "anthropic:<model>", "ollama:<model>" or "mock:<script.json>",
+/// at the providers of `config`, e.g. the configuration of a spec file. A spec without a known
+/// provider prefix is a Groq model name.
+pub fn from_spec_in(config: &Config, spec: &str) -> Box<dyn Provider> {
+    match spec.split_once(':') {
+        Some(("groq", model)) => Box::new(Groq::with_provider(config.provider("groq"), model)),
+        Some(("anthropic", model)) => Box::new(Anthropic::with_provider(config.provider("anthropic"), model)),
+        Some(("ollama", model)) => Box::new(Ollama::with_provider(config.provider("ollama"), model)),
+        Some(("mock", script)) => Box::new(MockProvider::from_script(script)),
+        _ => Box::new(Groq::with_provider(config.provider("groq"), spec)),
+    }
+}
+
+impl Provider for Groq {
+    fn name(&self) -> String {
+        format!("groq:{}", self.model())
+    }
+
+    fn try_evaluate_with(&self, prompt: &str, temperature: Option<f32>, seed: Option<u64>) -> Result<String, LlmError> {
+        Groq::try_evaluate_with(self, prompt, temperature, seed)
+    }
+
+    fn try_chat(&self, messages: &[Message], tools: &[ToolSpec]) -> Result<AssistantTurn, LlmError> {
+        let messages: Vec<Value> = messages
+            .iter()
+            .map(|m| match m {
+                Message::User { content } => json!({"role": "user", "content": content}),
+                Message::Assistant { turn } => {
+                    let mut message = json!({"role": "assistant", "content": turn.text});
+                    if !turn.tool_calls.is_empty() {
+                        message["tool_calls"] = turn
+                            .tool_calls
+                            .iter()
+                            .map(|c| {
+                                json!({
+                                    "id": c.id,
+                                    "type": "function",
+                                    "function": {"name": c.name, "arguments": c.arguments.to_string()},
+                                })
+                            })
+                            .collect();
+                    }
+                    message
+                }
+                Message::Tool { call_id, content, .. } => {
+                    json!({"role": "tool", "tool_call_id": call_id, "content": content})
+                }
+            })
+            .collect();
+        let tools: Vec<Value> = tools
+            .iter()
+            .map(|t| {
+                json!({
+                    "type": "function",
+                    "function": {"name": t.name, "description": t.description, "parameters": t.parameters},
+                })
+            })
+            .collect();
+
+        let body = json!({
+            "model": self.model(),
+            "messages": messages,
+            "tools": tools,
+            "max_tokens": self.max_tokens(),
+        });
+        let response = self.try_complete_json(&body)?;
+        let message = &response["choices"][0]["message"];
+        let tool_calls = message["tool_calls"]
+            .as_array()
+            .map(|calls| {
+                calls
+                    .iter()
+                    .map(|c| ToolCall {
+                        id: c["id"].as_str().unwrap_or_default().to_string(),
+                        name: c["function"]["name"].as_str().unwrap_or_default().to_string(),
+                        arguments: c["function"]["arguments"]
+                            .as_str()
+                            .and_then(|a| serde_json::from_str(a).ok())
+                            .unwrap_or(Value::Null),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(AssistantTurn {
+            text: message["content"].as_str().unwrap_or_default().to_string(),
+            tool_calls,
+        })
+    }
+}
+
+impl Provider for Anthropic {
+    fn name(&self) -> String {
+        format!("anthropic:{}", self.model())
+    }
+
+    fn try_evaluate_with(&self, prompt: &str, temperature: Option<f32>, _seed: Option<u64>) -> Result<String, LlmError> {
+        Anthropic::try_evaluate_with(self, prompt, temperature)
+    }
+
+    fn try_chat(&self, messages: &[Message], tools: &[ToolSpec]) -> Result<AssistantTurn, LlmError> {
+        // Anthropic wants the results of all tool calls of one step in a single user message.
+        let mut converted: Vec<Value> = Vec::new();
+        for m in messages {
+            match m {
+                Message::User { content } => converted.push(json!({"role": "user", "content": content})),
+                Message::Assistant { turn } => {
+                    let mut blocks = Vec::new();
+                    if !turn.text.is_empty() {
+                        blocks.push(json!({"type": "text", "text": turn.text}));
+                    }
+                    for c in &turn.tool_calls {
+                        blocks.push(json!({"type": "tool_use", "id": c.id, "name": c.name, "input": c.arguments}));
+                    }
+                    converted.push(json!({"role": "assistant", "content": blocks}));
+                }
+                Message::Tool { call_id, content, .. } => {
+                    let block = json!({"type": "tool_result", "tool_use_id": call_id, "content": content});
+                    match converted.last_mut() {
+                        Some(last) if last["role"] == "user" && last["content"].is_array() => {
+                            last["content"].as_array_mut().unwrap().push(block);
+                        }
+                        _ => converted.push(json!({"role": "user", "content": [block]})),
+                    }
+                }
+            }
+        }
+        let tools: Vec<Value> = tools
+            .iter()
+            .map(|t| json!({"name": t.name, "description": t.description, "input_schema": t.parameters}))
+            .collect();
+
+        let body = json!({
+            "model": self.model(),
+            "max_tokens": self.max_tokens(),
+            "messages": converted,
+            "tools": tools,
+        });
+        let response = self.try_complete_json(&body)?;
+        let mut turn = AssistantTurn::default();
+        for block in response["content"].as_array().into_iter().flatten() {
+            match block["type"].as_str() {
+                Some("text") => turn.text.push_str(block["text"].as_str().unwrap_or_default()),
+                Some("tool_use") => turn.tool_calls.push(ToolCall {
+                    id: block["id"].as_str().unwrap_or_default().to_string(),
+                    name: block["name"].as_str().unwrap_or_default().to_string(),
+                    arguments: block["input"].clone(),
+                }),
+                _ => {}
+            }
+        }
+        Ok(turn)
+    }
+}
+
+impl Provider for Ollama {
+    fn name(&self) -> String {
+        format!("ollama:{}", self.model)
+    }
+
+    fn try_evaluate_with(&self, prompt: &str, _temperature: Option<f32>, _seed: Option<u64>) -> Result<String, LlmError> {
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to start tokio runtime");
+        runtime
+            .block_on(self.client.generate(&self.model, prompt))
+            .map_err(|e| match e.status() {
+                Some(status) => LlmError::from_status(status.as_u16(), &e.to_string()),
+                None => LlmError::network(e),
+            })
+    }
+
+    fn try_chat(&self, _messages: &[Message], _tools: &[ToolSpec]) -> Result<AssistantTurn, LlmError> {
+        Err(LlmError::new(ErrorKind::Other, "the ollama provider does not support tool calling"))
+    }
+}
+
+/// A switch to the next provider of a fallback chain, made because the previous one kept failing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FallbackRecord {
+    pub from: String,
+    pub to: String,
+    pub error: String,
+}
+
+/// Calls the first provider of the chain, retrying transient failures with an exponential
+/// backoff; when a provider still fails with an auth, overload or network error, moves on to
+/// the next one and records the switch in the shared log. A provider the log already records
+/// a switch away from, in this chain or another one sharing the log, is skipped right away.
+pub struct WithFallback {
+    chain: Vec<Box<dyn Provider>>,
+    retries: u32,
+    backoff: Duration,
+    active: Cell<usize>,
+    log: Rc<RefCell<Vec<FallbackRecord>>>,
+}
+
+impl WithFallback {
+    pub fn new(chain: Vec<Box<dyn Provider>>, retries: u32, log: Rc<RefCell<Vec<FallbackRecord>>>) -> Self {
+        assert!(!chain.is_empty(), "A fallback chain needs at least one provider");
+        WithFallback {
+            chain,
+            retries,
+            backoff: DEFAULT_BACKOFF,
+            active: Cell::new(0),
+            log,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    fn with_retries<T>(&self, provider: &dyn Provider, call: &dyn Fn(&dyn Provider) -> Result<T, LlmError>) -> Result<T, LlmError> {
+        let mut pause = self.backoff;
+        let mut attempt = 0;
+        loop {
+            match call(provider) {
+                Err(e) if e.is_transient() && attempt < self.retries => {
+                    attempt += 1;
+                    eprintln!(
+                        "{} failed ({}), retry {}/{} in {:?}",
+                        provider.name(),
+                        e,
+                        attempt,
+                        self.retries,
+                        pause
+                    );
+                    thread::sleep(pause);
+                    pause *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Whether another call already gave up on `provider`.
+    fn is_dead(&self, provider: &dyn Provider) -> bool {
+        let name = provider.name();
+        self.log.borrow().iter().any(|record| record.from == name)
+    }
+
+    fn call<T>(&self, call: &dyn Fn(&dyn Provider) -> Result<T, LlmError>) -> Result<T, LlmError> {
+        loop {
+            let index = self.active.get();
+            let provider = self.chain[index].as_ref();
+            if index + 1 < self.chain.len() && self.is_dead(provider) {
+                eprintln!("{} is known to be unavailable, using {}", provider.name(), self.chain[index + 1].name());
+                self.active.set(index + 1);
+                continue;
+            }
+            match self.with_retries(provider, call) {
+                Err(e) if e.allows_fallback() && index + 1 < self.chain.len() => {
+                    let next = self.chain[index + 1].name();
+                    eprintln!("{} is unavailable ({}), falling back to {}", provider.name(), e, next);
+                    self.log.borrow_mut().push(FallbackRecord {
+                        from: provider.name(),
+                        to: next,
+                        error: e.to_string(),
+                    });
+                    self.active.set(index + 1);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Provider for WithFallback {
+    /// The name of the provider currently in use.
+    fn name(&self) -> String {
+        self.chain[self.active.get()].name()
+    }
+
+    fn try_evaluate_with(&self, prompt: &str, temperature: Option<f32>, seed: Option<u64>) -> Result<String, LlmError> {
+        self.call(&|p| p.try_evaluate_with(prompt, temperature, seed))
+    }
+
+    fn try_chat(&self, messages: &[Message], tools: &[ToolSpec]) -> Result<AssistantTurn, LlmError> {
+        self.call(&|p| p.try_chat(messages, tools))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::config;
+
+    fn text(reply: &str) -> Result<AssistantTurn, LlmError> {
+        Ok(AssistantTurn {
+            text: reply.to_string(),
+            tool_calls: Vec::new(),
+        })
+    }
+
+    fn chain(log: &Rc<RefCell<Vec<FallbackRecord>>>, primary: Vec<Result<AssistantTurn, LlmError>>) -> WithFallback {
+        let chain: Vec<Box<dyn Provider>> = vec![
+            Box::new(MockProvider::from_results("mock:primary", primary)),
+            Box::new(MockProvider::from_results("mock:fallback", vec![text("from fallback")])),
+        ];
+        WithFallback::new(chain, 2, log.clone()).with_backoff(Duration::ZERO)
+    }
+
+    #[test]
+    fn test_transient_error_is_retried() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let provider = chain(
+            &log,
+            vec![Err(LlmError::from_status(503, "busy")), text("from primary")],
+        );
+        assert_eq!(provider.evaluate("hi"), "from primary");
+        assert!(log.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_falls_back_after_retries() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let overloaded = || Err(LlmError::from_status(429, "slow down"));
+        let provider = chain(&log, vec![overloaded(), overloaded(), overloaded()]);
+        assert_eq!(provider.evaluate("hi"), "from fallback");
+        assert_eq!(provider.name(), "mock:fallback");
+        let log = log.borrow();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].from, "mock:primary");
+        assert_eq!(log[0].to, "mock:fallback");
+    }
+
+    #[test]
+    fn test_auth_error_falls_back_without_retry() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let provider = chain(&log, vec![Err(LlmError::from_status(401, "bad key"))]);
+        assert_eq!(provider.evaluate("hi"), "from fallback");
+        assert!(log.borrow()[0].error.starts_with("auth"));
+    }
+
+    #[test]
+    fn test_missing_key_falls_back() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut groq = config::project().provider("groq").clone();
+        groq.api_key_env = Some("LLM_TEMPLATED_TEST_NO_SUCH_KEY".to_string());
+        groq.api_key_file = None;
+        let chain: Vec<Box<dyn Provider>> = vec![
+            Box::new(Groq::with_provider(&groq, "some-model")),
+            Box::new(MockProvider::from_results("mock:fallback", vec![text("from fallback")])),
+        ];
+        let provider = WithFallback::new(chain, 2, log.clone()).with_backoff(Duration::ZERO);
+        assert_eq!(provider.evaluate("hi"), "from fallback");
+        assert!(log.borrow()[0].error.contains("LLM_TEMPLATED_TEST_NO_SUCH_KEY must be set"));
+    }
+
+    #[test]
+    fn test_dead_provider_is_skipped() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let first = chain(&log, vec![Err(LlmError::from_status(401, "bad key"))]);
+        assert_eq!(first.evaluate("hi"), "from fallback");
+        // A second chain sharing the log does not ask the dead primary again.
+        let second = chain(&log, vec![text("from primary")]);
+        assert_eq!(second.evaluate("hi"), "from fallback");
+        assert_eq!(log.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_spec_config_overrides_providers() {
+        let dir = tempfile::tempdir().unwrap();
+        let sub = dir.path().join("local");
+        std::fs::create_dir(&sub).unwrap();
+        std::fs::write(dir.path().join(config::CONFIG_FILE), "[providers.groq]\nmax_tokens = 1024\n").unwrap();
+        std::fs::write(
+            sub.join(config::CONFIG_FILE),
+            "[providers.groq]\nurl = \"http://localhost:1/v1\"\ndefault_model = \"local-model\"\n",
+        )
+        .unwrap();
+        let spec = sub.join("spec.md").to_string_lossy().into_owned();
+        let config = config::for_spec(&spec).unwrap();
+        let groq = config.provider("groq");
+        assert_eq!(groq.url, "http://localhost:1/v1");
+        assert_eq!(groq.max_tokens, 1024);
+        assert_eq!(Groq::for_spec(&spec).model(), "local-model");
+        assert_eq!(from_spec_in(&config, "anthropic:a").name(), "anthropic:a");
+        // The directory above the override keeps the project's default model.
+        let above = dir.path().join("spec.md").to_string_lossy().into_owned();
+        assert_eq!(Groq::for_spec(&above).model(), config::project().provider("groq").default_model);
+    }
+}
@@ -0,0 +1,237 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::history::{self, RunRecord};
+use super::lockfile;
+
+/// Every driver run gets its own directory below this one, in the crate root.
+pub const RUNS_DIR: &str = ".llm-runs";
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Formats seconds since the epoch as "YYYYMMDD-HHMMSS" (UTC).
+pub fn timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rest = secs % 86400;
+    // Civil date from days since 1970-01-01, after Howard Hinnant's algorithm.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+/// The output file as a single path component: "src/bin/wcr.rs" becomes "src-bin-wcr.rs".
+pub fn slug(output_file: &str) -> String {
+    output_file
+        .trim_start_matches("./")
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '.' || c == '_' || c == '-' { c } else { '-' })
+        .collect()
+}
+
+/// The directory of one run: "<RUNS_DIR>/<timestamp>-<slug>".
+pub struct RunDir {
+    path: PathBuf,
+}
+
+impl RunDir {
+    /// The directory of the existing run `id`.
+    pub fn open(id: &str) -> Self {
+        RunDir {
+            path: Path::new(RUNS_DIR).join(id),
+        }
+    }
+
+    /// Creates a new, empty run directory for `output_file`.
+    pub fn create(output_file: &str) -> Self {
+        let base = format!("{}-{}", timestamp(history::now()), slug(output_file));
+        let mut path = Path::new(RUNS_DIR).join(&base);
+        let mut n = 1;
+        while path.exists() {
+            n += 1;
+            path = Path::new(RUNS_DIR).join(format!("{}-{}", base, n));
+        }
+        fs::create_dir_all(&path).unwrap_or_else(|_| panic!("Failed to create run directory: {}", path.display()));
+        RunDir { path }
+    }
+
+    pub fn id(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The path of `name` inside the run directory, creating any subdirectory it names.
+    pub fn file(&self, name: &str) -> String {
+        let path = self.path.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .unwrap_or_else(|_| panic!("Failed to create directory: {}", parent.display()));
+        }
+        path.to_string_lossy().into_owned()
+    }
+
+    pub fn write_manifest(&self, record: &RunRecord) {
+        let path = self.path.join(MANIFEST_FILE);
+        let json = serde_json::to_string_pretty(record).expect("Failed to serialize run manifest");
+        fs::write(&path, json).unwrap_or_else(|_| panic!("Failed to write run manifest: {}", path.display()));
+    }
+}
+
+/// The ids of all recorded runs, oldest first.
+pub fn list() -> Vec<String> {
+    list_in(Path::new(RUNS_DIR))
+}
+
+fn list_in(runs_dir: &Path) -> Vec<String> {
+    let mut ids: Vec<String> = fs::read_dir(runs_dir)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter(|e| e.path().is_dir())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    ids.sort();
+    ids
+}
+
+/// Reads the manifest of the run `id`; `None` if it is missing or does not parse, e.g. while
+/// the run is still in progress.
+pub fn load_manifest(id: &str) -> Option<RunRecord> {
+    load_manifest_in(Path::new(RUNS_DIR), id)
+}
+
+fn load_manifest_in(runs_dir: &Path, id: &str) -> Option<RunRecord> {
+    let text = fs::read_to_string(runs_dir.join(id).join(MANIFEST_FILE)).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+/// The id of the latest run of `output_file`.
+pub fn latest_for(output_file: &str) -> Option<String> {
+    latest_in(Path::new(RUNS_DIR), output_file).map(|(id, _)| id)
+}
+
+fn latest_in(runs_dir: &Path, output_file: &str) -> Option<(String, RunRecord)> {
+    list_in(runs_dir).into_iter().rev().find_map(|id| {
+        let record = load_manifest_in(runs_dir, &id)?;
+        (record.output_file == output_file).then_some((id, record))
+    })
+}
+
+/// The latest run of `output_file`, together with its manifest, if that run did not finish or
+/// failed and none of its output files has changed since it started.
+pub fn find_interrupted(output_file: &str) -> Option<(RunDir, RunRecord)> {
+    interrupted_in(Path::new(RUNS_DIR), output_file).map(|(id, record)| (RunDir::open(&id), record))
+}
+
+fn interrupted_in(runs_dir: &Path, output_file: &str) -> Option<(String, RunRecord)> {
+    let (id, record) = latest_in(runs_dir, output_file)?;
+    if !record.result.is_empty() && record.result != "failed" {
+        eprintln!("The latest run {} of {} finished ({})", id, output_file, record.result);
+        return None;
+    }
+    // A draft of a run that started from other outputs would overwrite what changed since.
+    let changed = if record.output_hashes.contains_key(output_file) {
+        record
+            .output_hashes
+            .iter()
+            .find(|(output, hash)| output_hash(output) != **hash)
+            .map(|(output, _)| output.as_str())
+    } else {
+        Some(output_file)
+    };
+    if let Some(output) = changed {
+        eprintln!("{} has changed since run {} started", output, id);
+        return None;
+    }
+    Some((id, record))
+}
+
+/// The content hash of `output` the way the manifest records it: empty if it is missing.
+pub fn output_hash(output: &str) -> String {
+    lockfile::output_hash(output).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(0), "19700101-000000");
+        assert_eq!(timestamp(951_782_400 + 3661), "20000229-010101");
+        assert_eq!(timestamp(1_792_281_600), "20261018-000000");
+    }
+
+    #[test]
+    fn test_slug() {
+        assert_eq!(slug("./src/bin/lib/groq.rs"), "src-bin-lib-groq.rs");
+    }
+
+    fn record_run(runs_dir: &Path, id: &str, output_file: &str, result: &str) {
+        let mut record = RunRecord::new("spec.md", output_file);
+        record.run_id = id.to_string();
+        record.result = result.to_string();
+        record.output_hashes.insert(output_file.to_string(), output_hash(output_file));
+        fs::create_dir_all(runs_dir.join(id)).unwrap();
+        fs::write(
+            runs_dir.join(id).join(MANIFEST_FILE),
+            serde_json::to_string(&record).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_interrupted_only_if_latest() {
+        let dir = tempdir().unwrap();
+        let runs_dir = dir.path().join(RUNS_DIR);
+        let output = dir.path().join("out.rs").to_string_lossy().into_owned();
+        fs::write(&output, "fn main() {}\n").unwrap();
+
+        record_run(&runs_dir, "20261018-000000-out.rs", &output, "failed");
+        let (id, _) = interrupted_in(&runs_dir, &output).unwrap();
+        assert_eq!(id, "20261018-000000-out.rs");
+
+        // Failed, then succeeded: the failed run is stale.
+        record_run(&runs_dir, "20261018-000100-out.rs", &output, "accepted");
+        assert!(interrupted_in(&runs_dir, &output).is_none());
+
+        record_run(&runs_dir, "20261018-000200-out.rs", &output, "");
+        let (id, _) = interrupted_in(&runs_dir, &output).unwrap();
+        assert_eq!(id, "20261018-000200-out.rs");
+    }
+
+    #[test]
+    fn test_not_interrupted_if_output_changed() {
+        let dir = tempdir().unwrap();
+        let runs_dir = dir.path().join(RUNS_DIR);
+        let output = dir.path().join("out.rs").to_string_lossy().into_owned();
+        fs::write(&output, "fn main() {}\n").unwrap();
+        record_run(&runs_dir, "20261018-000000-out.rs", &output, "");
+
+        fs::write(&output, "fn main() { println!(); }\n").unwrap();
+        assert!(interrupted_in(&runs_dir, &output).is_none());
+    }
+}
@@ -0,0 +1,235 @@
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+
+use super::config;
+use super::frontmatter;
+use super::lockfile::{Lockfile, Status};
+use super::mapping;
+use super::preprocess;
+
+/// Make dependency files: "instruct/<path>.md" gets "<DEPS_DIR>/<path>.d".
+pub const DEPS_DIR: &str = ".llm-deps";
+
+/// A spec and the source file generated from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub spec: String,
+    pub output: String,
+}
+
+/// The path of the Make dependency file of `spec`.
+pub fn depfile_path(spec: &str) -> Option<String> {
+    let relative = spec
+        .trim_start_matches("./")
+        .strip_prefix(config::project().instruct_dir.as_str())?
+        .strip_prefix('/')?
+        .strip_suffix(".md")?;
+    Some(format!("{}/{}.d", DEPS_DIR, relative))
+}
+
+/// Adds the specs below `dir` to `specs`.
+pub fn collect_specs(dir: &Path, specs: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_specs(&path, specs);
+        } else if path.extension().is_some_and(|e| e == "md") {
+            specs.push(path.to_string_lossy().into_owned());
+        }
+    }
+}
+
+/// All the specs below the instruct directory with their output files, sorted by spec path. The
+/// "output" setting of a spec overrides the output file its path maps to.
+pub fn discover() -> Vec<Target> {
+    let mut specs = Vec::new();
+    collect_specs(Path::new(&config::project().instruct_dir), &mut specs);
+    specs.sort();
+    specs
+        .into_iter()
+        .filter_map(|spec| {
+            let output = mapping::output_for(&spec)?;
+            Some(Target { spec, output })
+        })
+        .collect()
+}
+
+/// The library modules that the source `output` uses, as paths of their source files: the
+/// modules named by "lib::<name>" in a binary, which pulls the library in with "mod lib;",
+/// or by "super::<name>" in a module of the library itself.
+pub fn used_modules(output: &str, source: &str) -> Vec<String> {
+    let path = Path::new(output);
+    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+    let (lib_dir, pattern) = if parent.file_name().is_some_and(|n| n == "lib") {
+        (parent.to_path_buf(), r"\bsuper::(\w+)")
+    } else {
+        (parent.join("lib"), r"\blib::(\w+)")
+    };
+    let re = Regex::new(pattern).expect("Invalid module pattern");
+    let mut modules = Vec::new();
+    for captures in re.captures_iter(source) {
+        let module = lib_dir.join(format!("{}.rs", &captures[1])).to_string_lossy().into_owned();
+        if module != output && !modules.contains(&module) {
+            modules.push(module);
+        }
+    }
+    modules
+}
+
+/// Adds the Rust sources below `dir` to `sources`.
+fn collect_sources(dir: &Path, sources: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_sources(&path, sources);
+        } else if path.extension().is_some_and(|e| e == "rs") {
+            sources.push(path.to_string_lossy().into_owned());
+        }
+    }
+}
+
+/// The sources that use the library module `module`, directly or through other modules of
+/// the library: the binaries next to its "lib" directory and the modules inside it, sorted.
+/// Empty if `module` is not a library module.
+pub fn dependents(module: &str) -> Vec<String> {
+    let path = Path::new(module);
+    let Some(lib_dir) = path.parent().filter(|dir| dir.file_name().is_some_and(|n| n == "lib")) else {
+        return Vec::new();
+    };
+    let mut sources = Vec::new();
+    collect_sources(lib_dir.parent().unwrap_or_else(|| Path::new("")), &mut sources);
+    sources.sort();
+    let uses: Vec<(String, Vec<String>)> = sources
+        .into_iter()
+        .map(|source| {
+            let content = fs::read_to_string(&source).unwrap_or_default();
+            let modules = used_modules(&source, &content);
+            (source, modules)
+        })
+        .collect();
+
+    let mut found = vec![module.to_string()];
+    let mut index = 0;
+    while index < found.len() {
+        for (source, modules) in &uses {
+            if modules.contains(&found[index]) && !found.contains(source) {
+                found.push(source.clone());
+            }
+        }
+        index += 1;
+    }
+    found.remove(0);
+    found.sort();
+    found
+}
+
+/// For each target, the indices of the targets generating the library modules it uses, so
+/// that those are regenerated first. Modules without a spec are not targets and are left out.
+pub fn dependency_graph(targets: &[Target]) -> Vec<Vec<usize>> {
+    targets
+        .iter()
+        .map(|target| {
+            let source = fs::read_to_string(&target.output).unwrap_or_default();
+            used_modules(&target.output, &source)
+                .iter()
+                .filter_map(|module| targets.iter().position(|t| &t.output == module))
+                .collect()
+        })
+        .collect()
+}
+
+/// The spec and every file it includes: what the output is generated from.
+pub fn inputs(target: &Target) -> Vec<String> {
+    let mut inputs = vec![target.spec.clone()];
+    inputs.extend(preprocess::includes(&target.spec));
+    inputs
+}
+
+/// Writes the Make dependency file of `target`: the output and the dependency file itself
+/// depend on the spec and everything it includes. Includes that do not exist are left out,
+/// as make would otherwise remake the dependency file, and restart, forever. The output is
+/// added to TARGETS with its rule, calling the Makefile's "regenerate", as the mapping rules
+/// can put it anywhere. A "driver" setting becomes the output's BINARY. The mapping rules and
+/// the driver can come from the configuration files, so the dependency file also depends on
+/// them. Returns the path written.
+pub fn write_depfile(target: &Target) -> Result<String, String> {
+    let path = depfile_path(&target.spec).ok_or_else(|| format!("{} is not a spec", target.spec))?;
+    if let Some(parent) = Path::new(&path).parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let inputs: Vec<String> = inputs(target).into_iter().filter(|i| Path::new(i).exists()).collect();
+    let mut content = preprocess::depfile(&[&target.output, &path], &inputs);
+    content.push('\n');
+    // The spec comes first, as the first prerequisite gets no empty rule.
+    let mut configuration = vec![target.spec.clone()];
+    configuration.extend(config::files_for(&target.spec));
+    content.push_str(&preprocess::depfile(&[&path], &configuration));
+    content.push_str(&format!(
+        "\nTARGETS += {output}\n{output}:\n\t$(call regenerate,{},{output})\n",
+        target.spec,
+        output = target.output
+    ));
+    if let Some(driver) = frontmatter::settings_for(&target.spec)?.driver {
+        content.push_str(&format!("\n{}: BINARY = {}\n", target.output, driver));
+    }
+    fs::write(&path, content).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    Ok(path)
+}
+
+/// Whether the output, or one of the further outputs of the spec, is missing or was generated
+/// from another version of the spec, going by the hashes in the lock file. Outputs edited by
+/// hand since are not stale. Targets without a lock entry are stale too: file times say
+/// nothing after a fresh clone, so "llm-make --relock" records outputs known to be current.
+pub fn is_stale(target: &Target, lock: &Lockfile) -> bool {
+    let mut outputs = vec![target.output.clone()];
+    outputs.extend(mapping::extra_outputs(&target.spec));
+    outputs.iter().any(|output| match lock.status(&target.spec, output) {
+        Status::UpToDate | Status::OutputEdited => false,
+        Status::MissingOutput | Status::SpecChanged | Status::Untracked => true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_depfile_path() {
+        assert_eq!(depfile_path("instruct/bin/lib/groq.md").as_deref(), Some(".llm-deps/bin/lib/groq.d"));
+        assert_eq!(depfile_path("./instruct/bin/wcr.md").as_deref(), Some(".llm-deps/bin/wcr.d"));
+        assert_eq!(depfile_path("include/common.md"), None);
+    }
+
+    #[test]
+    fn test_used_modules() {
+        let bin = "mod lib;\nuse lib::groq::Groq;\nfn main() { lib::preprocess::preprocess(\"x\"); lib::groq::Groq::for_spec(\"x\"); }\n";
+        assert_eq!(
+            used_modules("src/bin/llm-groq-4.rs", bin),
+            vec!["src/bin/lib/groq.rs", "src/bin/lib/preprocess.rs"]
+        );
+        let module = "use super::error::LlmError;\nuse super::groq;\n";
+        assert_eq!(
+            used_modules("src/bin/lib/provider.rs", module),
+            vec!["src/bin/lib/error.rs", "src/bin/lib/groq.rs"]
+        );
+    }
+
+    #[test]
+    fn test_dependents() {
+        let groq = dependents("src/bin/lib/groq.rs");
+        assert!(groq.contains(&"src/bin/llm-groq-5.rs".to_string()));
+        // Through lib::provider, which uses super::groq.
+        assert!(groq.contains(&"src/bin/lib/provider.rs".to_string()));
+        assert!(!groq.contains(&"src/bin/lib/groq.rs".to_string()));
+        assert!(!groq.contains(&"src/bin/check.rs".to_string()));
+        assert!(dependents("src/bin/wcr.rs").is_empty());
+    }
+}
@@ -6,15 +6,24 @@ use std::process::{Command, exit};
 use filetime::{FileTime, set_file_mtime};
 use serde_json::{json, Value};
 
+#[allow(dead_code)]
+mod lib;
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let safety = lib::git::Safety::from_args(&mut args);
     if args.len() != 3 {
-        eprintln!("Usage: {} <input_file> <output_file>", args[0]);
+        eprintln!("Usage: {} [--allow-dirty] [--clean-spec] <input_file> <output_file>", args[0]);
         exit(1);
     }
 
     let input_file = &args[1];
     let output_file = &args[2];
+
+    if let Err(e) = safety.check(input_file, output_file) {
+        eprintln!("Error: {}", e);
+        exit(1);
+    }
     let draft_file = format!("{}.draft", output_file);
     let reject_file = format!("{}.rej", output_file);
 
@@ -2,30 +2,49 @@ use std::env;
 use std::fs;
 use std::io::{self, Write};
 use std::process;
-use std::time::SystemTime;
 use reqwest;
 use serde_json::{json, Value};
 use tokio;
 use std::path::Path;
 
+#[allow(dead_code)]
+mod lib;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let safety = lib::git::Safety::from_args(&mut args);
     
     if args.len() != 3 {
-        eprintln!("Usage: {} <input_file> <output_file>", args[0]);
+        eprintln!("Usage: {} [--allow-dirty] [--clean-spec] <input_file> <output_file>", args[0]);
         process::exit(1);
     }
     
     let input_file = &args[1];
     let output_file = &args[2];
     let draft_file = format!("{}.draft", output_file);
-    let api_key = env::var("ANTHROPIC_API_KEY")
-        .expect("ANTHROPIC_API_KEY environment variable must be set");
-    
-    // Read the input file
-    let input_content = fs::read_to_string(input_file)
+
+    if let Err(e) = safety.check(input_file, output_file) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+    // Only one process may work on an output file at a time
+    eprintln!("Taking the lock on {}", output_file);
+    let lock = lib::atomic::OutputLock::acquire(output_file).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    lib::atomic::install_signal_cleanup();
+    // The API key, URL, model and max_tokens come from the configuration of the spec
+    let config = lib::config::for_spec(input_file)?;
+    let provider = config.provider("anthropic");
+    let api_key = provider.api_key();
+    let model = &provider.default_model;
+    
+    // Read the input file, without its front matter
+    let input_text = fs::read_to_string(input_file)
         .map_err(|e| format!("Failed to read input file {}: {}", input_file, e))?;
+    let input_content = lib::preprocess::split_front_matter(&input_text).1.to_string();
     
     // Check if output file exists and is non-empty
     let output_exists_and_not_empty = Path::new(output_file).exists() && {
@@ -52,11 +71,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
     
     // Prepare the API request for Claude
-    let url = "https://api.anthropic.com/v1/messages";
+    let url = &provider.url;
     
     let payload = json!({
-        "model": "claude-sonnet-4-20250514",
-        "max_tokens": 8192,
+        "model": model,
+        "max_tokens": provider.max_tokens,
         "temperature": 0.7,
         "messages": [{
             "role": "user",
@@ -78,6 +97,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     if !response.status().is_success() {
         let error_text = response.text().await?;
         eprintln!("API request failed: {}", error_text);
+        lock.release();
         process::exit(1);
     }
     
@@ -101,6 +121,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     
     // Save the response to draft file
+    lib::atomic::register(&draft_file);
     fs::write(&draft_file, &generated_text)
         .map_err(|e| format!("Failed to write draft file {}: {}", draft_file, e))?;
     eprintln!("Draft saved to: {}", draft_file);
@@ -120,7 +141,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         
         // Make evaluation API call
         let eval_payload = json!({
-            "model": "claude-sonnet-4-20250514",
+            "model": model,
             "max_tokens": 100,
             "temperature": 0.1,
             "messages": [{
@@ -141,6 +162,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         if !eval_response.status().is_success() {
             let error_text = eval_response.text().await?;
             eprintln!("Evaluation API request failed: {}", error_text);
+            lock.release();
             process::exit(1);
         }
         
@@ -165,20 +187,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         match evaluation_result {
             "First result is better." => {
                 eprintln!("Keeping existing output file unchanged, updating mtime.");
-                // Update mtime on the output file
-                let now = SystemTime::now();
-                let since_epoch = now.duration_since(SystemTime::UNIX_EPOCH)?;
-                fs::OpenOptions::new()
-                    .write(true)
-                    .open(output_file)?
-                    .set_len(0)?;
-                fs::write(output_file, &existing_output)?;
+                // Update only the mtime: the content of the output file stays byte-identical
+                lib::atomic::touch(output_file)
+                    .map_err(|e| format!("Failed to update mtime of {}: {}", output_file, e))?;
+                lib::lockfile::record(input_file, output_file, "", evaluation_result);
                 
                 // Rename draft file to .rej since it wasn't accepted
                 let reject_file = format!("{}.rej", output_file);
                 fs::rename(&draft_file, &reject_file)
                     .map_err(|e| format!("Failed to rename draft file to reject: {}", e))?;
                 eprintln!("Draft file renamed to: {}", reject_file);
+                lib::atomic::forget(&draft_file);
                 
                 // Output diff between original and rejected
                 let diff_output = std::process::Command::new("diff")
@@ -204,19 +223,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 eprintln!("Expected either 'First result is better.' or 'The second implementation is better.'");
                 // Keep draft file for diagnostic purposes
                 eprintln!("Draft file kept at: {} for diagnostic purposes", draft_file);
+                lock.release();
                 process::exit(1);
             }
         }
     }
     
-    // Write the result to the output file
-    fs::write(output_file, generated_text)
+    // Write the result to the output file, atomically via a temporary file and a rename
+    lib::atomic::write(output_file, &generated_text)
         .map_err(|e| format!("Failed to write to {}: {}", output_file, e))?;
     
     eprintln!("Output written to: {}", output_file);
+    lib::lockfile::record(input_file, output_file, &format!("anthropic:{}", model), "accepted");
     
     // Remove draft file since its content was accepted
     fs::remove_file(&draft_file).ok();
+    lib::atomic::forget(&draft_file);
     
     Ok(())
 }
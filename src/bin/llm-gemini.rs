@@ -5,17 +5,26 @@ use reqwest;
 use serde_json::{json, Value};
 use tokio;
 
+#[allow(dead_code)]
+mod lib;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 1. Argument Parsing
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let safety = lib::git::Safety::from_args(&mut args);
     if args.len() != 3 {
-        eprintln!("Usage: {} <input_file> <output_file>", args[0]);
+        eprintln!("Usage: {} [--allow-dirty] [--clean-spec] <input_file> <output_file>", args[0]);
         process::exit(1);
     }
     let input_file = &args[1];
     let output_file = &args[2];
 
+    if let Err(e) = safety.check(input_file, output_file) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+
     // 2. Get API Key from environment variable
     let api_key = match env::var("GEMINI_API_KEY") {
         Ok(key) => key,
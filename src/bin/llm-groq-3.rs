@@ -5,30 +5,23 @@ use std::process::Command;
 use std::time::SystemTime;
 use filetime::FileTime;
 
+#[allow(dead_code)]
 mod lib;
 
 fn main() {
     eprintln!("Starting program");
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let safety = lib::git::Safety::from_args(&mut args);
     if args.len() != 3 {
-        eprintln!("Usage: {} <input_file> <output_file>", args[0]);
+        eprintln!("Usage: {} [--allow-dirty] [--clean-spec] <input_file> <output_file>", args[0]);
         std::process::exit(1);
     }
     let input_file = &args[1];
     let output_file = &args[2];
 
-    eprintln!("Checking output file status with git");
-    if Path::new(output_file).exists() {
-        let git_status = Command::new("git")
-            .args(&["status", "--porcelain", output_file])
-            .output()
-            .expect("Failed to execute git status");
-        
-        let output = String::from_utf8_lossy(&git_status.stdout);
-        if !output.trim().is_empty() {
-            eprintln!("Error: Output file has uncommitted changes");
-            std::process::exit(1);
-        }
+    if let Err(e) = safety.check(input_file, output_file) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
     }
 
     eprintln!("Reading input file: {}", input_file);
@@ -40,10 +33,11 @@ fn main() {
     let rej_path = format!("{}.rej", output_file);
 
     let pid = std::process::id();
-    let req_path_gen = format!("/tmp/llm-req-{}-gen.txt", pid);
-    let resp_path_gen = format!("/tmp/llm-req-{}-gen-resp.txt", pid);
-    let req_path_eval = format!("/tmp/llm-req-{}-eval.txt", pid);
-    let resp_path_eval = format!("/tmp/llm-req-{}-eval-resp.txt", pid);
+    let scratch = lib::config::project().scratch_dir.display().to_string();
+    let req_path_gen = format!("{}/llm-req-{}-gen.txt", scratch, pid);
+    let resp_path_gen = format!("{}/llm-req-{}-gen-resp.txt", scratch, pid);
+    let req_path_eval = format!("{}/llm-req-{}-eval.txt", scratch, pid);
+    let resp_path_eval = format!("{}/llm-req-{}-eval-resp.txt", scratch, pid);
 
     let prompt = if !output_path.exists()
         || fs::metadata(output_path)
@@ -69,7 +63,7 @@ fn main() {
         .unwrap_or_else(|_| panic!("Failed to write request file: {}", req_path_gen));
 
     eprintln!("Calling Groq API");
-    let groq = lib::groq::Groq::new();
+    let groq = lib::groq::Groq::for_spec(input_file);
     let response = groq.evaluate(&prompt);
 
     eprintln!("Saving response to: {}", resp_path_gen);
@@ -105,7 +99,7 @@ fn main() {
         .unwrap_or_else(|_| panic!("Failed to write evaluation request file"));
 
     eprintln!("Calling Groq API for evaluation");
-    let groq_eval = lib::groq::Groq::new();
+    let groq_eval = lib::groq::Groq::for_spec(input_file);
     let eval_response = groq_eval.evaluate(&eval_prompt);
     let trimmed = eval_response.trim();
 
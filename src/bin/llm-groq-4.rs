@@ -2,35 +2,33 @@ use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::time::SystemTime;
-use filetime::FileTime;
 
+#[allow(dead_code)]
 mod lib;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let safety = lib::git::Safety::from_args(&mut args);
     if args.len() != 3 {
-        eprintln!("Usage: {} <input_file> <output_file>", args[0]);
+        eprintln!("Usage: {} [--allow-dirty] [--clean-spec] <input_file> <output_file>", args[0]);
         std::process::exit(1);
     }
     eprintln!("Starting program {}", args[0]);
     let input_file = &args[1];
     let output_file = &args[2];
 
-    eprintln!("Checking output file status with git");
-    if Path::new(output_file).exists() {
-        let git_status = Command::new("git")
-            .args(&["status", "--porcelain", output_file])
-            .output()
-            .expect("Failed to execute git status");
-        
-        let output = String::from_utf8_lossy(&git_status.stdout);
-        if !output.trim().is_empty() {
-            eprintln!("Error: Output file has uncommitted changes");
-            std::process::exit(1);
-        }
+    if let Err(e) = safety.check(input_file, output_file) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
     }
 
+    eprintln!("Taking the lock on {}", output_file);
+    let lock = lib::atomic::OutputLock::acquire(output_file).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    lib::atomic::install_signal_cleanup();
+
     eprintln!("Reading input file: {}", input_file);
     let description = lib::preprocess::preprocess(input_file);
 
@@ -39,10 +37,11 @@ fn main() {
     let rej_path = format!("{}.rej", output_file);
 
     let pid = std::process::id();
-    let req_path_gen = format!("/tmp/llm-req-{}-gen.txt", pid);
-    let resp_path_gen = format!("/tmp/llm-req-{}-gen-resp.txt", pid);
-    let req_path_eval = format!("/tmp/llm-req-{}-eval.txt", pid);
-    let resp_path_eval = format!("/tmp/llm-req-{}-eval-resp.txt", pid);
+    let scratch = lib::config::project().scratch_dir.display().to_string();
+    let req_path_gen = format!("{}/llm-req-{}-gen.txt", scratch, pid);
+    let resp_path_gen = format!("{}/llm-req-{}-gen-resp.txt", scratch, pid);
+    let req_path_eval = format!("{}/llm-req-{}-eval.txt", scratch, pid);
+    let resp_path_eval = format!("{}/llm-req-{}-eval-resp.txt", scratch, pid);
 
     let original_content = if output_path.exists() {
         fs::read_to_string(output_file).unwrap_or_default()
@@ -80,7 +79,7 @@ fn main() {
         .unwrap_or_else(|_| panic!("Failed to write request file: {}", req_path_gen));
 
     eprintln!("Calling Groq API");
-    let groq = lib::groq::Groq::new();
+    let groq = lib::groq::Groq::for_spec(input_file);
     let response = groq.evaluate(&prompt);
 
     eprintln!("Saving response to: {}", resp_path_gen);
@@ -88,10 +87,12 @@ fn main() {
         .unwrap_or_else(|_| panic!("Failed to write response file: {}", resp_path_gen));
 
     eprintln!("Writing draft to: {}", draft_path);
+    lib::atomic::register(&draft_path);
     fs::write(&draft_path, &response)
         .unwrap_or_else(|_| panic!("Failed to write draft file: {}", draft_path));
 
     let temp_path = format!("{}.tmp", output_file);
+    lib::atomic::register(&temp_path);
     fs::write(&temp_path, &response)
         .unwrap_or_else(|_| panic!("Failed to write temporary file"));
     
@@ -107,7 +108,7 @@ fn main() {
         .unwrap_or_else(|_| panic!("Failed to write evaluation request file"));
 
     eprintln!("Calling Groq API for evaluation");
-    let groq_eval = lib::groq::Groq::new();
+    let groq_eval = lib::groq::Groq::for_spec(input_file);
     let eval_response = groq_eval.evaluate(&eval_prompt);
     let trimmed = eval_response.trim();
 
@@ -117,6 +118,10 @@ fn main() {
 
     eprintln!("Evaluation result: {}", trimmed);
 
+    eprintln!("Removing temporary file: {}", temp_path);
+    fs::remove_file(&temp_path).unwrap_or_else(|_| panic!("Failed to remove temporary file"));
+    lib::atomic::forget(&temp_path);
+
     if trimmed == "First result is better." {
         eprintln!("First result is better");
         if first_compiler_errors.is_empty() {
@@ -125,30 +130,36 @@ fn main() {
                 fs::rename(&draft_path, &rej_path)
                     .unwrap_or_else(|_| panic!("Failed to rename rejected draft"));
             }
-            let now = SystemTime::now();
-            filetime::set_file_mtime(output_file, FileTime::from_system_time(now))
-                .expect("Failed to update mtime");
+            lib::lockfile::record(input_file, output_file, "", trimmed);
+            lib::atomic::touch(output_file).expect("Failed to update mtime");
         } else {
             eprintln!("First result better but has compile errors");
             if Path::new(&draft_path).exists() {
                 fs::rename(&draft_path, &rej_path)
                     .unwrap_or_else(|_| panic!("Failed to rename rejected draft"));
             }
+            lock.release();
             std::process::exit(1);
         }
     } else if trimmed == "The second implementation is better." {
         eprintln!("Second implementation is better");
-        fs::rename(&temp_path, output_file)
-            .unwrap_or_else(|_| panic!("Failed to move temporary file to output file"));
+        eprintln!("Writing accepted result to: {}", output_file);
+        lib::atomic::write(output_file, &response)
+            .unwrap_or_else(|e| panic!("Failed to write output file {}: {}", output_file, e));
+        let model = format!("groq:{}", groq.model());
+        lib::lockfile::record(input_file, output_file, &model, trimmed);
         if Path::new(&draft_path).exists() {
             fs::remove_file(&draft_path)
                 .unwrap_or_else(|_| panic!("Failed to remove draft file"));
         }
     } else {
         eprintln!("Unexpected evaluation response: {}", trimmed);
+        lock.release();
         std::process::exit(1);
     }
 
+    lib::atomic::forget(&draft_path);
+    lock.release();
     eprintln!("Program completed successfully");
 }
 
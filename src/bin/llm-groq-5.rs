@@ -1,67 +1,673 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::path::{Path};
-use std::process::{Command};
-use std::time::SystemTime;
-use filetime::FileTime;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
 
+#[allow(dead_code)]
 mod lib;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: {} <input_file> <output_file>", args[0]);
+use lib::cargo::{check_candidate_set_dependents, run_candidate_set_check, run_cargo_check_dependents, test_candidate_set};
+use lib::history::{CandidateRecord, RunRecord, TierRecord};
+use lib::deterministic::JudgeKind;
+use lib::git::Safety;
+use lib::judge::{JudgeMode, Verdict};
+use lib::provider::{FallbackRecord, Provider, WithFallback};
+use lib::runs::RunDir;
+
+struct Options {
+    input_file: String,
+    output_file: String,
+    /// Every file generated from the spec, the output file first; more than one if the spec
+    /// has an "outputs" setting, and then the model answers with a multi-file result.
+    outputs: Vec<String>,
+    judge: JudgeKind,
+    judge_model: String,
+    require_interface: bool,
+    /// Candidates must pass the target's tests, as on the escalation ladder.
+    require_tests: bool,
+    /// Named shell commands from the configuration that candidates must pass.
+    check_commands: Vec<(String, String)>,
+    dependents: Dependents,
+    /// The sources using the outputs, when they are library modules, checked along with every
+    /// candidate; empty with "--dependents off".
+    dependent_files: Vec<String>,
+    judge_mode: JudgeMode,
+    diff_context: usize,
+    candidates: usize,
+    models: Vec<String>,
+    temperatures: Vec<f32>,
+    synthesise: bool,
+    critique: bool,
+    agent: bool,
+    agent_steps: usize,
+    ladder: Vec<String>,
+    fallbacks: Vec<String>,
+    retries: u32,
+    route: Vec<String>,
+    resume: bool,
+    commit: bool,
+    safety: Safety,
+    /// The configuration of the spec, which the providers are built from.
+    config: lib::config::Config,
+    /// Filled in by the providers as they fall back; copied into the run record at the end.
+    fallback_log: Rc<RefCell<Vec<FallbackRecord>>>,
+    /// The provider of each model spec, created on first use and kept for the whole run.
+    providers: RefCell<HashMap<String, Rc<WithFallback>>>,
+    /// What has been measured of each result, by content, so that no result is compiled or
+    /// tested twice.
+    measurements: RefCell<HashMap<String, Measurement>>,
+}
+
+/// The warnings and test outcome of one result, each measured the first time it is needed.
+#[derive(Debug, Clone, Default)]
+struct Measurement {
+    warnings: Option<usize>,
+    /// Whether the tests passed, and the tail of their output.
+    tests: Option<(bool, String)>,
+}
+
+/// One generated draft, compiled in a scratch copy of the crate.
+struct Candidate {
+    label: String,
+    model: String,
+    tier: usize,
+    draft_path: String,
+    rej_path: String,
+    content: String,
+    errors: Vec<String>,
+    /// Errors the candidate causes in the dependent files, which they did not have before.
+    downstream: Vec<String>,
+    prompt_bytes: usize,
+}
+
+impl Candidate {
+    /// The errors the judge and the synthesis see: with "--dependents judge" the breakages in
+    /// the dependent files count as the candidate's own.
+    fn judged_errors(&self, options: &Options) -> Vec<String> {
+        let mut errors = self.errors.clone();
+        if options.dependents == Dependents::Judge {
+            errors.extend(self.downstream.iter().cloned());
+        }
+        errors
+    }
+}
+
+/// What to do with a candidate for a library module that breaks the files using it.
+#[derive(Clone, Copy, PartialEq)]
+enum Dependents {
+    /// Drop it, like a candidate that does not compile.
+    Reject,
+    /// Show the breakages to the judge, with the candidate's own errors.
+    Judge,
+    /// Do not check the dependent files.
+    Off,
+}
+
+impl Dependents {
+    fn parse(value: &str) -> Option<Dependents> {
+        match value {
+            "reject" => Some(Dependents::Reject),
+            "judge" => Some(Dependents::Judge),
+            "off" => Some(Dependents::Off),
+            _ => None,
+        }
+    }
+}
+
+/// What every tier works from.
+struct Inputs {
+    description: String,
+    original_content: String,
+    first_compiler_errors: Vec<String>,
+    /// The errors the dependent files had before, with the file each is in.
+    dependent_errors: Vec<(String, String)>,
+    prompt: String,
+}
+
+/// Where the files of the run, or of one tier of the escalation ladder, are written.
+struct RunFiles<'a> {
+    run_dir: &'a RunDir,
+    tier: Option<usize>,
+    output_file: String,
+}
+
+impl RunFiles<'_> {
+    /// "<run_dir>/[tier<t>/]<name>", for requests, responses, diagnostics and the like.
+    fn run_file(&self, name: &str) -> String {
+        match self.tier {
+            Some(tier) => self.run_dir.file(&format!("tier{}/{}", tier, name)),
+            None => self.run_dir.file(name),
+        }
+    }
+
+    /// "[tier<t>/]<name>", the name of a stage in the manifest's list of completed stages.
+    fn stage(&self, name: &str) -> String {
+        match self.tier {
+            Some(tier) => format!("tier{}/{}", tier, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// Records `stage` as completed and saves the manifest, so that "--resume" can skip it.
+    fn complete(&self, record: &mut RunRecord, stage: String) {
+        if !record.completed.contains(&stage) {
+            record.completed.push(stage);
+        }
+        self.run_dir.write_manifest(record);
+    }
+
+    /// On "--resume", the saved response of `stage`, if the stage was completed and its saved
+    /// request is the same as `prompt`.
+    fn reuse(&self, record: &RunRecord, stage: &str, req_path: &str, prompt: &str, resp_path: &str) -> Option<String> {
+        if !record.completed.iter().any(|s| s == stage) {
+            return None;
+        }
+        if fs::read_to_string(req_path).ok()? != prompt {
+            eprintln!("Request of {} has changed since the interrupted run, not reusing its response", stage);
+            return None;
+        }
+        let response = fs::read_to_string(resp_path).ok()?;
+        eprintln!("Reusing response of {} from {}", stage, resp_path);
+        Some(response)
+    }
+
+    /// "<output_file>.<kind>[.tier<t>]<suffix>", e.g. the draft and rejected files.
+    fn beside_output(&self, kind: &str, suffix: &str) -> String {
+        match self.tier {
+            Some(tier) => format!("{}.{}.tier{}{}", self.output_file, kind, tier, suffix),
+            None => format!("{}.{}{}", self.output_file, kind, suffix),
+        }
+    }
+}
+
+/// How one tier's attempt ended.
+enum Attempt {
+    Accepted(Candidate),
+    /// The judge preferred the original.
+    Lost,
+    NoCompile,
+    TestsFailed,
+    CheckFailed,
+    /// Every candidate broke the files using the output.
+    BreaksDependents,
+    NoCandidate,
+    InvalidVerdict,
+}
+
+impl Attempt {
+    fn name(&self) -> &'static str {
+        match self {
+            Attempt::Accepted(_) => "accepted",
+            Attempt::Lost => "lost",
+            Attempt::NoCompile => "no-compile",
+            Attempt::TestsFailed => "tests-failed",
+            Attempt::CheckFailed => "check-failed",
+            Attempt::BreaksDependents => "breaks-dependents",
+            Attempt::NoCandidate => "no-candidate",
+            Attempt::InvalidVerdict => "invalid-verdict",
+        }
+    }
+}
+
+enum RunResult {
+    Accepted,
+    Kept,
+    Rejected,
+    Failed,
+}
+
+impl RunResult {
+    fn name(&self) -> &'static str {
+        match self {
+            RunResult::Accepted => "accepted",
+            RunResult::Kept => "kept",
+            RunResult::Rejected => "rejected",
+            RunResult::Failed => "failed",
+        }
+    }
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--judge llm|deterministic|filter] [--judge-model <m>] [--require-interface] [--require-tests] [--dependents reject|judge|off] [--judge-mode full|diff] [--diff-context <lines>] [--candidates <n>] [--models <m1,m2,..>] [--temperatures <t1,t2,..>] [--synthesise] [--critique] [--agent] [--agent-steps <n>] [--ladder <m1,m2,..>] [--fallback <m1,m2,..>] [--retries <n>] [--route <m1,m2,..>] [--resume] [--commit] [--allow-dirty] [--clean-spec] <input_file> [<output_file>]",
+        program
+    );
+    std::process::exit(1);
+}
+
+fn parse_args(args: &[String]) -> Options {
+    let mut positional = Vec::new();
+    let mut judge = JudgeKind::Llm;
+    let mut judge_model = None;
+    let mut require_interface = false;
+    let mut require_tests = false;
+    let mut dependents = Dependents::Reject;
+    let mut judge_mode = JudgeMode::Full;
+    let mut diff_context = 5;
+    let mut candidates = 1;
+    let mut models = None;
+    let mut temperatures = Vec::new();
+    let mut synthesise = false;
+    let mut critique = false;
+    let mut agent = false;
+    let mut agent_steps = lib::agent::DEFAULT_MAX_STEPS;
+    let mut ladder = Vec::new();
+    let mut fallbacks = Vec::new();
+    let mut retries = lib::provider::DEFAULT_RETRIES;
+    let mut route = Vec::new();
+    let mut resume = false;
+    let mut commit = false;
+    let mut safety = Safety::default();
+
+    let mut iter = args[1..].iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--judge" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                judge = JudgeKind::parse(value).unwrap_or_else(|| usage(&args[0]));
+            }
+            "--judge-model" => judge_model = Some(iter.next().unwrap_or_else(|| usage(&args[0])).clone()),
+            "--require-interface" => require_interface = true,
+            "--require-tests" => require_tests = true,
+            "--dependents" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                dependents = Dependents::parse(value).unwrap_or_else(|| usage(&args[0]));
+            }
+            "--judge-mode" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                judge_mode = JudgeMode::parse(value).unwrap_or_else(|| usage(&args[0]));
+            }
+            "--diff-context" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                diff_context = value.parse().unwrap_or_else(|_| usage(&args[0]));
+            }
+            "--candidates" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                candidates = value.parse().unwrap_or_else(|_| usage(&args[0]));
+                if candidates == 0 {
+                    usage(&args[0]);
+                }
+            }
+            "--models" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                models = Some(value.split(',').map(|m| m.trim().to_string()).collect());
+            }
+            "--temperatures" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                temperatures = value
+                    .split(',')
+                    .map(|t| t.trim().parse().unwrap_or_else(|_| usage(&args[0])))
+                    .collect();
+            }
+            "--synthesise" => synthesise = true,
+            "--critique" => critique = true,
+            "--agent" => agent = true,
+            "--agent-steps" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                agent_steps = value.parse().unwrap_or_else(|_| usage(&args[0]));
+            }
+            "--ladder" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                ladder = value
+                    .split(',')
+                    .map(|m| m.trim().to_string())
+                    .filter(|m| !m.is_empty())
+                    .collect();
+            }
+            "--fallback" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                fallbacks = value
+                    .split(',')
+                    .map(|m| m.trim().to_string())
+                    .filter(|m| !m.is_empty())
+                    .collect();
+            }
+            "--retries" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                retries = value.parse().unwrap_or_else(|_| usage(&args[0]));
+            }
+            "--resume" => resume = true,
+            "--commit" => commit = true,
+            "--allow-dirty" => safety.allow_dirty = true,
+            "--clean-spec" => safety.clean_spec = true,
+            "--route" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                route = value
+                    .split(',')
+                    .map(|m| m.trim().to_string())
+                    .filter(|m| !m.is_empty())
+                    .collect();
+            }
+            _ if arg.starts_with("--") => usage(&args[0]),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    if positional.is_empty() || positional.len() > 2 || (!route.is_empty() && !ladder.is_empty()) {
+        usage(&args[0]);
+    }
+    if positional.len() == 1 {
+        let Some(output) = lib::mapping::output_for(&positional[0]) else {
+            eprintln!("Error: no mapping rule gives an output file for {}", positional[0]);
+            std::process::exit(1);
+        };
+        eprintln!("Output file: {}", output);
+        positional.push(output);
+    }
+    // The spec's settings apply where the command line does not say otherwise.
+    let settings = lib::frontmatter::settings_for(&positional[0]).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    if settings != lib::frontmatter::Settings::default() {
+        eprintln!("Spec settings: {:?}", settings);
+    }
+    // The providers, their URLs and default models included, are those of the spec's directory.
+    let config = lib::config::for_spec(&positional[0]).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    let default_model = config.provider("groq").default_model.clone();
+    let models = models
+        .or_else(|| settings.model_spec().map(|model| vec![model]))
+        .unwrap_or_else(|| vec![default_model.clone()]);
+    if temperatures.is_empty()
+        && let Some(temperature) = settings.temperature
+    {
+        temperatures = vec![temperature];
+    }
+    let judge_model = judge_model
+        .or(settings.judge_model.clone())
+        .unwrap_or(default_model);
+    let require_interface = require_interface || settings.has_check("interface");
+    let require_tests = require_tests || settings.has_check("tests");
+    let check_commands = config
+        .checks
+        .iter()
+        .filter(|(name, _)| settings.has_check(name))
+        .map(|(name, command)| (name.clone(), command.clone()))
+        .collect();
+    if candidates > 1 && temperatures.is_empty() {
+        temperatures = vec![0.2, 0.6, 1.0];
+    }
+    let mut outputs = vec![positional[1].clone()];
+    outputs.extend(settings.outputs.clone().unwrap_or_default());
+    if outputs.len() > 1 && agent {
+        eprintln!("Error: --agent can not generate the several outputs of {}", positional[0]);
         std::process::exit(1);
     }
+    if outputs.len() > 1 && judge != JudgeKind::Llm {
+        eprintln!("Error: --judge {} can not compare the several outputs of {}", judge.name(), positional[0]);
+        std::process::exit(1);
+    }
+    let mut dependent_files: Vec<String> = Vec::new();
+    if dependents != Dependents::Off {
+        for output in &outputs {
+            for dependent in lib::targets::dependents(output) {
+                if !outputs.contains(&dependent) && !dependent_files.contains(&dependent) {
+                    dependent_files.push(dependent);
+                }
+            }
+        }
+    }
+    Options {
+        input_file: positional[0].clone(),
+        output_file: positional[1].clone(),
+        outputs,
+        judge,
+        judge_model,
+        require_interface,
+        require_tests,
+        check_commands,
+        dependents,
+        dependent_files,
+        judge_mode,
+        diff_context,
+        candidates,
+        models,
+        temperatures,
+        synthesise,
+        critique,
+        agent,
+        agent_steps,
+        ladder,
+        fallbacks,
+        retries,
+        route,
+        resume,
+        commit,
+        safety,
+        config,
+        fallback_log: Rc::new(RefCell::new(Vec::new())),
+        providers: RefCell::new(HashMap::new()),
+        measurements: RefCell::new(HashMap::new()),
+    }
+}
+
+/// The provider for `spec`, retrying transient failures and falling back to the "--fallback"
+/// providers on outages. It is created once per run, so that its fallbacks stick.
+fn provider(options: &Options, spec: &str) -> Rc<WithFallback> {
+    if let Some(provider) = options.providers.borrow().get(spec) {
+        return provider.clone();
+    }
+    let mut chain = vec![lib::provider::from_spec_in(&options.config, spec)];
+    chain.extend(
+        options
+            .fallbacks
+            .iter()
+            .filter(|fallback| fallback.as_str() != spec)
+            .map(|fallback| lib::provider::from_spec_in(&options.config, fallback)),
+    );
+    let provider = Rc::new(WithFallback::new(chain, options.retries, options.fallback_log.clone()));
+    options.providers.borrow_mut().insert(spec.to_string(), provider.clone());
+    provider
+}
+
+/// The models of each tier: one tier per ladder entry, or a single tier with all "--models".
+fn tiers(options: &Options) -> Vec<Vec<String>> {
+    if options.ladder.is_empty() {
+        vec![options.models.clone()]
+    } else {
+        options.ladder.iter().map(|m| vec![m.clone()]).collect()
+    }
+}
+
+/// Picks the "--route" model with the best acceptance record for the output file.
+fn route_model(options: &Options) -> String {
+    eprintln!("Reading run history from {}", lib::history::HISTORY_FILE);
+    let records = lib::history::load();
+    match lib::history::route(&records, &options.output_file, &options.route) {
+        Some(model) => {
+            eprintln!("Routing to {} based on {} recorded runs", model, records.len());
+            model
+        }
+        None => {
+            eprintln!("No history for the routed models, using {}", options.route[0]);
+            options.route[0].clone()
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut options = parse_args(&args);
     eprintln!("Starting program {}", args[0]);
-    let input_file = &args[1];
-    let output_file = &args[2];
-
-    eprintln!("Checking output file status with git");
-    if Path::new(output_file).exists() {
-        let git_status = Command::new("git")
-            .args(&["status", "--porcelain", output_file])
-            .output()
-            .expect("Failed to execute git status");
-        
-        let output = String::from_utf8_lossy(&git_status.stdout);
-        if !output.trim().is_empty() {
-            eprintln!("Error: Output file has uncommitted changes");
+
+    // A refusal is not a run: it leaves no lock, run directory or history record behind.
+    for output in &options.outputs {
+        if let Err(e) = options.safety.check(&options.input_file, output) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // Candidates are compiled, and maybe tested, on this machine: their tests must not get at
+    // the API keys, and their build scripts get at them only where nothing better is possible.
+    if let Some(e) = lib::cargo::isolation_error() {
+        let executes = options.agent
+            || options.require_tests
+            || !options.ladder.is_empty()
+            || !options.check_commands.is_empty()
+            || options.judge != JudgeKind::Llm;
+        if executes {
+            eprintln!("Error: {}", e);
             std::process::exit(1);
         }
+        eprintln!(
+            "Warning: \"unshare\" can not isolate model-written code here: the build scripts and procedural macros of the candidates run without API keys in their environment, killed after {:?}, but could read the API key files",
+            lib::cargo::TEST_TIMEOUT
+        );
+    }
+    if options.agent {
+        let isolation = if lib::cargo::is_isolated() {
+            "without network access, API keys or API key files"
+        } else {
+            "with network access, without API keys"
+        };
+        eprintln!(
+            "Warning: --agent runs model-written tests on this machine as the current user, {}, killed after {:?}",
+            isolation,
+            lib::cargo::TEST_TIMEOUT
+        );
+    }
+    if !options.route.is_empty() {
+        options.models = vec![route_model(&options)];
+    }
+
+    let locks: Vec<lib::atomic::OutputLock> = options
+        .outputs
+        .iter()
+        .map(|output| {
+            eprintln!("Taking the lock on {}", output);
+            lib::atomic::OutputLock::acquire(output).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            })
+        })
+        .collect();
+    lib::atomic::install_signal_cleanup();
+
+    let interrupted = if options.resume {
+        eprintln!("Looking for an interrupted run of {}", options.output_file);
+        let found = lib::runs::find_interrupted(&options.output_file);
+        if found.is_none() {
+            eprintln!("No interrupted run to resume, starting a new one");
+        }
+        found
+    } else {
+        None
+    };
+    let (run_dir, mut record) = match interrupted {
+        Some((run_dir, mut record)) => {
+            eprintln!("Resuming run {} after stages: {}", record.run_id, record.completed.join(", "));
+            record.restart();
+            (run_dir, record)
+        }
+        None => {
+            let run_dir = RunDir::create(&options.output_file);
+            let mut record = RunRecord::new(&options.input_file, &options.output_file);
+            record.run_id = run_dir.id();
+            record.args = args[1..].to_vec();
+            record.output_hashes = options
+                .outputs
+                .iter()
+                .map(|output| (output.clone(), lib::runs::output_hash(output)))
+                .collect();
+            (run_dir, record)
+        }
+    };
+    eprintln!("Writing run files to: {}", run_dir.path().display());
+    run_dir.write_manifest(&record);
+
+    let result = run(&options, &run_dir, &mut record);
+    record.fallbacks = options.fallback_log.borrow().clone();
+    record.finish(result.name());
+    eprintln!("Writing run manifest to: {}", run_dir.path().join(lib::runs::MANIFEST_FILE).display());
+    run_dir.write_manifest(&record);
+    eprintln!("Recording run in {}", lib::history::HISTORY_FILE);
+    lib::history::append(&record);
+    locks.into_iter().for_each(lib::atomic::OutputLock::release);
+
+    match result {
+        RunResult::Accepted | RunResult::Kept => eprintln!("Program completed successfully"),
+        RunResult::Rejected | RunResult::Failed => std::process::exit(1),
     }
+}
+
+fn run(options: &Options, run_dir: &RunDir, record: &mut RunRecord) -> RunResult {
+    let input_file = &options.input_file;
+    let output_file = &options.output_file;
 
     eprintln!("Reading input file: {}", input_file);
     let description = lib::preprocess::preprocess(input_file);
 
-    let output_path = Path::new(output_file);
-    let draft_path = format!("{}.draft", output_file);
-    let rej_path = format!("{}.rej", output_file);
+    let files = RunFiles {
+        run_dir,
+        tier: None,
+        output_file: output_file.clone(),
+    };
+    let req_path_gen = files.run_file("gen.txt");
 
-    let pid = std::process::id();
-    let req_path_gen = format!("/tmp/llm-req-{}-gen.txt", pid);
-    let resp_path_gen = format!("/tmp/llm-req-{}-gen-resp.txt", pid);
-    let req_path_eval = format!("/tmp/llm-req-{}-eval.txt", pid);
-    let resp_path_eval = format!("/tmp/llm-req-{}-eval-resp.txt", pid);
+    let existing: Vec<&str> = options
+        .outputs
+        .iter()
+        .map(String::as_str)
+        .filter(|output| Path::new(output).exists())
+        .collect();
+    let multi_file = options.outputs.len() > 1;
+    if multi_file {
+        eprintln!("Generating {} files together: {}", options.outputs.len(), options.outputs.join(", "));
+    }
 
-    let original_content = if output_path.exists() {
-        fs::read_to_string(output_file).unwrap_or_default()
-    } else {
+    let original_content = if existing.is_empty() {
         String::new()
+    } else if multi_file {
+        lib::multifile::read(&options.outputs)
+    } else {
+        fs::read_to_string(output_file).unwrap_or_default()
     };
 
-    let first_compiler_errors = if output_path.exists() {
-        run_cargo_check(output_file)
+    let dependents: Vec<&str> = options.dependent_files.iter().map(String::as_str).collect();
+    let (first_compiler_errors, dependent_errors) = if !existing.is_empty() || !dependents.is_empty() {
+        eprintln!("Running cargo check on {}", existing.join(", "));
+        if !dependents.is_empty() {
+            eprintln!("Checking {} files depending on it: {}", dependents.len(), dependents.join(", "));
+        }
+        let started = Instant::now();
+        let (errors, dependent_errors) = run_cargo_check_dependents(&existing, &dependents);
+        record.time("original-check", started);
+        save_diagnostics(&files.run_file("original-diagnostics.txt"), &errors);
+        (errors, dependent_errors)
     } else {
         eprintln!("No cargo check");
-        Vec::new()
+        (Vec::new(), Vec::new())
     };
 
-    let prompt = if !output_path.exists()
-        || fs::metadata(output_path)
-            .map(|m| m.len() == 0)
-            .unwrap_or(true)
-    {
+    let output_is_empty = options
+        .outputs
+        .iter()
+        .all(|output| fs::metadata(output).map(|m| m.len() == 0).unwrap_or(true));
+
+    let tiers = tiers(options);
+
+    let findings = if options.critique && !output_is_empty {
+        let started = Instant::now();
+        let findings = critique(options, &files, record, &tiers[0][0], &description, &original_content, &first_compiler_errors);
+        record.time("critique", started);
+        findings
+    } else {
+        None
+    };
+    if findings.as_ref().is_some_and(|f| f.is_empty()) && first_compiler_errors.is_empty() {
+        eprintln!("Critic found no mismatches and there are no compile errors, marking the file up to date");
+        mark_up_to_date(options, "Kept (critic found no mismatches)");
+        return RunResult::Kept;
+    }
+
+    let mut prompt = if output_is_empty {
         eprintln!("Output file doesn't exist or is empty - using initial prompt");
         format!(
             "Please produce single output result, which would match the description below as well as you can:\n\n{}",
@@ -75,162 +681,862 @@ fn main() {
         )
     };
 
+    if let Some(findings) = findings.as_ref().filter(|f| !f.is_empty()) {
+        eprintln!("Passing {} critic findings to the generator", findings.len());
+        prompt.push_str(&lib::critic::findings_section(findings));
+    }
+    if multi_file {
+        prompt.push_str(&lib::multifile::instructions(&options.outputs));
+    }
+
+    if fs::read_to_string(&req_path_gen).is_ok_and(|saved| saved != prompt) {
+        eprintln!("Generation request has changed since the interrupted run, not reusing its responses");
+        record.completed.retain(|stage| !stage.contains("generate-"));
+    }
     eprintln!("Saving request to: {}", req_path_gen);
     fs::write(&req_path_gen, &prompt)
         .unwrap_or_else(|_| panic!("Failed to write request file: {}", req_path_gen));
 
-    eprintln!("Calling Groq API");
-    let groq = lib::groq::Groq::new();
-    let response = groq.evaluate(&prompt);
+    let inputs = Inputs {
+        description,
+        original_content,
+        first_compiler_errors,
+        dependent_errors,
+        prompt,
+    };
+
+    let laddered = !options.ladder.is_empty();
+    let mut last = Attempt::NoCandidate;
+    for (index, models) in tiers.iter().enumerate() {
+        let tier = index + 1;
+        let files = RunFiles {
+            run_dir,
+            tier: laddered.then_some(tier),
+            output_file: output_file.clone(),
+        };
+        if laddered {
+            eprintln!("Tier {} of {}: {}", tier, tiers.len(), models.join(", "));
+        }
+
+        let started = Instant::now();
+        let attempt = attempt_tier(options, &inputs, &files, models, tier, record);
+        record.time(&format!("tier{}", tier), started);
+        record.tiers.push(TierRecord {
+            tier,
+            models: models.join(","),
+            outcome: attempt.name().to_string(),
+        });
+
+        match attempt {
+            Attempt::Accepted(candidate) => {
+                if laddered {
+                    eprintln!("Accepted result produced by tier {} ({})", tier, candidate.model);
+                }
+                accept(options, &candidate);
+                record.candidates.push(candidate_record(&candidate, "accepted"));
+                record.accepted_tier = Some(tier);
+                for output in &options.outputs {
+                    lib::lockfile::record(input_file, output, &candidate.model, &record.verdict);
+                }
+                record.accepted_model = Some(candidate.model);
+                if options.commit {
+                    commit(options, record);
+                }
+                return RunResult::Accepted;
+            }
+            Attempt::InvalidVerdict => return RunResult::Failed,
+            other => {
+                if tier < tiers.len() {
+                    eprintln!("Tier {} ended with {}, escalating to tier {}", tier, other.name(), tier + 1);
+                }
+                last = other;
+            }
+        }
+    }
+
+    if matches!(last, Attempt::Lost) {
+        eprintln!("First result is better");
+        if inputs.first_compiler_errors.is_empty() {
+            eprintln!("No compile errors, restoring original");
+            mark_up_to_date(options, &record.verdict);
+            return RunResult::Kept;
+        }
+        eprintln!("First result better but has compile errors");
+    }
+    RunResult::Rejected
+}
+
+/// Generates, compiles and judges the candidates of one tier. On the escalation ladder the
+/// candidates must compile and pass the target's tests, otherwise the next tier is tried.
+fn attempt_tier(
+    options: &Options,
+    inputs: &Inputs,
+    files: &RunFiles,
+    models: &[String],
+    tier: usize,
+    record: &mut RunRecord,
+) -> Attempt {
+    let laddered = !options.ladder.is_empty();
+    let strict = options.candidates > 1 || laddered;
+
+    let started = Instant::now();
+    let mut candidates = generate_candidates(options, inputs, files, record, models, tier);
+    record.time(&format!("tier{}-generate", tier), started);
+    if candidates.is_empty() {
+        eprintln!("Error: no candidates were generated");
+        return Attempt::NoCandidate;
+    }
+
+    if strict {
+        let (compiling, failing): (Vec<_>, Vec<_>) =
+            candidates.into_iter().partition(|c| c.errors.is_empty());
+        for candidate in &failing {
+            eprintln!("Dropping {}: {} compile errors", candidate.label, candidate.errors.len());
+            reject(record, candidate, "no-compile");
+        }
+        if compiling.is_empty() {
+            eprintln!("Error: none of the candidates compiles");
+            return Attempt::NoCompile;
+        }
+        candidates = compiling;
+    }
+    if options.dependents == Dependents::Reject {
+        let (sound, breaking): (Vec<_>, Vec<_>) = candidates.into_iter().partition(|c| c.downstream.is_empty());
+        for candidate in &breaking {
+            eprintln!("Dropping {}: {} new errors in dependent files", candidate.label, candidate.downstream.len());
+            reject(record, candidate, "breaks-dependents");
+        }
+        if sound.is_empty() {
+            eprintln!("Error: every candidate breaks the files depending on {}", options.output_file);
+            return Attempt::BreaksDependents;
+        }
+        candidates = sound;
+    }
+
+    let mut remaining = candidates.into_iter();
+    let Some(mut champion) = remaining.next() else {
+        return Attempt::NoCandidate;
+    };
+    let mut runner_up = None;
+    let mut round = 0;
+    for challenger in remaining {
+        round += 1;
+        let Some((winner, loser)) = play_round(options, inputs, files, champion, challenger, round, record) else {
+            return Attempt::InvalidVerdict;
+        };
+        champion = winner;
+        runner_up = Some(loser);
+    }
+
+    if options.synthesise {
+        match runner_up {
+            Some(runner_up) => {
+                let merged = synthesise(options, inputs, files, record, &models[0], tier, &champion, &runner_up);
+                if strict && !merged.errors.is_empty() {
+                    eprintln!("Dropping {}: {} compile errors", merged.label, merged.errors.len());
+                    reject(record, &merged, "no-compile");
+                } else if options.dependents == Dependents::Reject && !merged.downstream.is_empty() {
+                    eprintln!("Dropping {}: {} new errors in dependent files", merged.label, merged.downstream.len());
+                    reject(record, &merged, "breaks-dependents");
+                } else {
+                    round += 1;
+                    let Some((winner, _)) = play_round(options, inputs, files, champion, merged, round, record) else {
+                        return Attempt::InvalidVerdict;
+                    };
+                    champion = winner;
+                }
+            }
+            None => eprintln!("Only one candidate left, skipping synthesis"),
+        }
+    }
+
+    // A multi-file result that does not parse can not be written, whatever the judge thinks.
+    let champion_files = match candidate_files(options, &champion.content) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("Dropping {}: {}", champion.label, e);
+            reject(record, &champion, "no-compile");
+            return Attempt::NoCompile;
+        }
+    };
+
+    if laddered || options.require_tests {
+        let (passed, output) = run_tests(options, &champion.label, &champion.content, &champion_files);
+        if !passed {
+            eprintln!("Tests failed for {}:\n{}", champion.label, output);
+            reject(record, &champion, "tests-failed");
+            return Attempt::TestsFailed;
+        }
+    }
+    for (name, command) in &options.check_commands {
+        eprintln!("Running check {} for {} in a scratch crate", name, champion.label);
+        let (passed, output) = run_candidate_set_check(&champion_files, command);
+        if !passed {
+            eprintln!("Check {} failed for {}:\n{}", name, champion.label, output);
+            reject(record, &champion, "check-failed");
+            return Attempt::CheckFailed;
+        }
+    }
+
+    eprintln!("Final round: original vs {}", champion.label);
+    let verdict = judge(
+        options,
+        &inputs.description,
+        &inputs.original_content,
+        &champion.content,
+        &inputs.first_compiler_errors,
+        &champion.judged_errors(options),
+        &files.run_file("eval.txt"),
+        &files.run_file("eval-resp.txt"),
+    );
+    if let Some((verdict, reason)) = &verdict {
+        let verdict_path = files.run_file("verdict.txt");
+        eprintln!("Saving verdict to: {}", verdict_path);
+        fs::write(&verdict_path, format!("{:?} ({} by {})\n{}\n", verdict, champion.label, champion.model, reason))
+            .unwrap_or_else(|_| panic!("Failed to write verdict file: {}", verdict_path));
+        record.verdict = format!("{:?} ({})", verdict, reason);
+    }
+    match verdict.map(|(verdict, _)| verdict) {
+        Some(Verdict::First) => {
+            reject(record, &champion, "lost");
+            Attempt::Lost
+        }
+        Some(Verdict::Second) => {
+            eprintln!("Second implementation is better ({})", champion.label);
+            Attempt::Accepted(champion)
+        }
+        None => Attempt::InvalidVerdict,
+    }
+}
+
+/// Generates the requested number of candidates, cycling through the models and temperatures,
+/// and compiles each of them in a scratch copy of the crate.
+fn generate_candidates(
+    options: &Options,
+    inputs: &Inputs,
+    files: &RunFiles,
+    record: &mut RunRecord,
+    models: &[String],
+    tier: usize,
+) -> Vec<Candidate> {
+    let output_file = &options.output_file;
+    let prompt = &inputs.prompt;
+    let single = options.candidates == 1;
+    let mut candidates = Vec::new();
+
+    for index in 0..options.candidates {
+        let model = &models[index % models.len()];
+        let temperature = if options.temperatures.is_empty() {
+            None
+        } else {
+            Some(options.temperatures[index % options.temperatures.len()])
+        };
+        let seed = if single { None } else { Some(index as u64) };
+
+        let (label, draft_path, rej_path, resp_path, diagnostics_path) = if single {
+            (
+                "candidate".to_string(),
+                files.beside_output("draft", ""),
+                files.beside_output("rej", ""),
+                files.run_file("gen-resp.txt"),
+                files.run_file("gen-diagnostics.txt"),
+            )
+        } else {
+            (
+                format!("candidate {}", index + 1),
+                files.beside_output("draft", &format!(".{}", index + 1)),
+                files.beside_output("rej", &format!(".{}", index + 1)),
+                files.run_file(&format!("gen-{}-resp.txt", index + 1)),
+                files.run_file(&format!("gen-{}-diagnostics.txt", index + 1)),
+            )
+        };
+
+        let provider = provider(options, model);
+        let stage = files.stage(&format!("generate-{}:{}", index + 1, model));
+        let saved = record
+            .completed
+            .contains(&stage)
+            .then(|| fs::read_to_string(&resp_path).ok())
+            .flatten();
+        let reused = saved.is_some();
+        let response = if let Some(saved) = saved {
+            eprintln!("Reusing response for {} from {}", label, resp_path);
+            saved
+        } else if options.agent {
+            let transcript_path = if single {
+                files.run_file("agent.jsonl")
+            } else {
+                files.run_file(&format!("agent-{}.jsonl", index + 1))
+            };
+            eprintln!("Running agent for {} with {}, transcript: {}", label, provider.name(), transcript_path);
+            let root = env::current_dir().expect("Failed to get current directory");
+            let toolbox = lib::agent::Toolbox::new(&root, output_file);
+            let run = lib::agent::Agent::new(provider.as_ref(), &toolbox, options.agent_steps)
+                .with_transcript(&transcript_path)
+                .run(prompt);
+            match run.outcome {
+                lib::agent::Outcome::Submitted(content) => {
+                    eprintln!("Agent submitted {} after {} steps", label, run.steps);
+                    content
+                }
+                lib::agent::Outcome::BudgetExhausted => {
+                    eprintln!("Agent did not submit {} within {} steps, skipping it", label, options.agent_steps);
+                    continue;
+                }
+            }
+        } else {
+            eprintln!(
+                "Calling {} for {} (temperature {})",
+                provider.name(),
+                label,
+                temperature.map(|t| t.to_string()).unwrap_or_else(|| "default".to_string())
+            );
+            provider.evaluate_with(prompt, temperature, seed)
+        };
+
+        if !reused {
+            eprintln!("Saving response to: {}", resp_path);
+            fs::write(&resp_path, &response)
+                .unwrap_or_else(|_| panic!("Failed to write response file: {}", resp_path));
+            files.complete(record, stage);
+        }
+
+        eprintln!("Writing draft to: {}", draft_path);
+        lib::atomic::register(&draft_path);
+        fs::write(&draft_path, &response)
+            .unwrap_or_else(|_| panic!("Failed to write draft file: {}", draft_path));
+
+        eprintln!("Running cargo check on {} in a scratch crate", label);
+        let (errors, downstream) = compile(options, inputs, &response);
+        eprintln!("{} has {} compile errors", label, errors.len());
+        save_diagnostics(&diagnostics_path, &errors);
+        report_downstream(&label, &diagnostics_path, &downstream);
+
+        candidates.push(Candidate {
+            label,
+            model: provider.name(),
+            tier,
+            draft_path,
+            rej_path,
+            prompt_bytes: prompt.len(),
+            content: response,
+            errors,
+            downstream,
+        });
+    }
+    candidates
+}
+
+/// Runs the critic over the current file and saves its findings as a review report.
+/// Returns `None` if the critic response could not be parsed.
+fn critique(
+    options: &Options,
+    files: &RunFiles,
+    record: &mut RunRecord,
+    model: &str,
+    description: &str,
+    original_content: &str,
+    compiler_errors: &[String],
+) -> Option<Vec<lib::critic::Finding>> {
+    let req_path = files.run_file("critic.txt");
+    let resp_path = files.run_file("critic-resp.txt");
+    let report_path = files.run_file("review.md");
+
+    let prompt = lib::critic::prompt(description, original_content, compiler_errors);
+    let stage = files.stage("critique");
+    let response = match files.reuse(record, &stage, &req_path, &prompt, &resp_path) {
+        Some(response) => response,
+        None => {
+            eprintln!("Saving critic request to: {}", req_path);
+            fs::write(&req_path, &prompt)
+                .unwrap_or_else(|_| panic!("Failed to write critic request file: {}", req_path));
+
+            let provider = provider(options, model);
+            eprintln!("Calling {} for critique", provider.name());
+            let response = provider.evaluate(&prompt);
+
+            eprintln!("Saving critic response to: {}", resp_path);
+            fs::write(&resp_path, &response)
+                .unwrap_or_else(|_| panic!("Failed to write critic response file: {}", resp_path));
+            files.complete(record, stage);
+            response
+        }
+    };
+
+    let Some(findings) = lib::critic::parse_findings(&response) else {
+        eprintln!("Warning: could not parse the critic response, continuing without findings");
+        return None;
+    };
+    eprintln!("Critic found {} mismatches, saving review report to: {}", findings.len(), report_path);
+    let report = lib::critic::report(&options.input_file, &options.output_file, &findings);
+    fs::write(&report_path, report)
+        .unwrap_or_else(|_| panic!("Failed to write review report: {}", report_path));
+    Some(findings)
+}
+
+/// Judges `challenger` against `champion` and returns `(winner, loser)`, rejecting the loser's draft.
+/// Returns `None` if the judge response is not one of the expected phrases.
+fn play_round(
+    options: &Options,
+    inputs: &Inputs,
+    files: &RunFiles,
+    champion: Candidate,
+    challenger: Candidate,
+    round: usize,
+    record: &mut RunRecord,
+) -> Option<(Candidate, Candidate)> {
+    eprintln!("Tournament round {}: {} vs {}", round, champion.label, challenger.label);
+    let verdict = judge(
+        options,
+        &inputs.description,
+        &champion.content,
+        &challenger.content,
+        &champion.judged_errors(options),
+        &challenger.judged_errors(options),
+        &files.run_file(&format!("eval-{}.txt", round)),
+        &files.run_file(&format!("eval-{}-resp.txt", round)),
+    );
+    let (winner, loser) = match verdict?.0 {
+        Verdict::First => (champion, challenger),
+        Verdict::Second => (challenger, champion),
+    };
+    eprintln!("{} wins", winner.label);
+    reject(record, &loser, "lost");
+    Some((winner, loser))
+}
+
+/// Asks the model to merge the strengths of two candidates into one, and compiles the result.
+#[allow(clippy::too_many_arguments)]
+fn synthesise(
+    options: &Options,
+    inputs: &Inputs,
+    files: &RunFiles,
+    record: &mut RunRecord,
+    model: &str,
+    tier: usize,
+    first: &Candidate,
+    second: &Candidate,
+) -> Candidate {
+    let req_path = files.run_file("synth.txt");
+    let resp_path = files.run_file("synth-resp.txt");
+    let draft_path = files.beside_output("draft", ".synth");
+    let rej_path = files.beside_output("rej", ".synth");
 
-    eprintln!("Saving response to: {}", resp_path_gen);
-    fs::write(&resp_path_gen, &response)
-        .unwrap_or_else(|_| panic!("Failed to write response file: {}", resp_path_gen));
+    let prompt = format!(
+        "Please CAREFULLY study the description below (enclosed into <result-description></result-description>) and two results implementing it, first one enclosed into \"<first-result></first-result>\" and the second enclosed into \"<second-result></second-result>\", with compile errors of first result included into \"<first-compile-errors></first-compile-errors>\" and second compile errors as \"<second-compile-errors></second-compile-errors>\". Each of the two results may fulfil different parts of the description better than the other one. Produce a single merged result, which combines the strengths of both, fulfils the description as completely as possible, and compiles. Output the full merged result. Do not delimit the result with anything, output it verbatim.\n\n<result-description>\n{}\n</result-description>\n\n<first-result>\n{}</first-result>\n\n<second-result>\n{}</second-result>\n\n<first-compile-errors>\n{}</first-compile-errors>\n\n<second-compile-errors>\n{}</second-compile-errors>",
+        inputs.description,
+        first.content,
+        second.content,
+        first.judged_errors(options).join("\n"),
+        second.judged_errors(options).join("\n")
+    );
+    let prompt = if options.outputs.len() > 1 {
+        format!("{}{}", prompt, lib::multifile::instructions(&options.outputs))
+    } else {
+        prompt
+    };
+
+    let provider = provider(options, model);
+    let stage = files.stage("synthesise");
+    let response = match files.reuse(record, &stage, &req_path, &prompt, &resp_path) {
+        Some(response) => response,
+        None => {
+            eprintln!("Saving synthesis request to: {}", req_path);
+            fs::write(&req_path, &prompt)
+                .unwrap_or_else(|_| panic!("Failed to write synthesis request file: {}", req_path));
+
+            eprintln!("Calling {} to synthesise {} and {}", provider.name(), first.label, second.label);
+            let response = provider.evaluate(&prompt);
+
+            eprintln!("Saving synthesis response to: {}", resp_path);
+            fs::write(&resp_path, &response)
+                .unwrap_or_else(|_| panic!("Failed to write synthesis response file: {}", resp_path));
+            files.complete(record, stage);
+            response
+        }
+    };
 
     eprintln!("Writing draft to: {}", draft_path);
+    lib::atomic::register(&draft_path);
     fs::write(&draft_path, &response)
         .unwrap_or_else(|_| panic!("Failed to write draft file: {}", draft_path));
 
-    let temp_path = format!("{}.tmp", output_file);
-    fs::write(&temp_path, &response)
-        .unwrap_or_else(|_| panic!("Failed to write temporary file"));
-    
-    let second_compiler_errors = run_cargo_check(&temp_path);
+    eprintln!("Running cargo check on the merged candidate in a scratch crate");
+    let (errors, downstream) = compile(options, inputs, &response);
+    eprintln!("merged candidate has {} compile errors", errors.len());
+    let diagnostics_path = files.run_file("synth-diagnostics.txt");
+    save_diagnostics(&diagnostics_path, &errors);
+    report_downstream("merged candidate", &diagnostics_path, &downstream);
 
-    let eval_prompt = format!(
-        "Please CAREFULLY evaluate the below description (enclosed into <result-description></result-description>), and two outputs corresponding to this description, first one enclosed into \"<first-result></first-result>\" and the second enclosed into \"<second-result></second-result>\", with compile errors of first result included into \"<first-compile-errors></first-compile-errors>\" and second compile errors as \"<second-compile-errors></second-compile-errors>\", and evaluate which of the two is more precise and correct in implementing the description - and also which of them compiles! Then, if the first result is better, output the phrase 'First result is better.', if the second result is better, output the phrase 'The second implementation is better.'. Output only one of the two phrases, and nothing else\n\n<result-description>\n{}\n</result-description>\n\n<first-result>\n{}</first-result>\n\n<second-result>\n{}</second-result>\n\n<first-compile-errors>\n{}</first-compile-errors>\n\n<second-compile-errors>\n{}</second-compile-errors>",
-        description, original_content, response, first_compiler_errors.join("\n"), second_compiler_errors.join("\n")
+    Candidate {
+        label: "merged candidate".to_string(),
+        model: provider.name(),
+        tier,
+        draft_path,
+        rej_path,
+        prompt_bytes: prompt.len(),
+        content: response,
+        errors,
+        downstream,
+    }
+}
+
+/// Asks the judge which of the two results is better, saving the request and the response.
+/// Returns the verdict with the reason for it, or `None` (after reporting it) if the response
+/// is neither of the expected phrases.
+#[allow(clippy::too_many_arguments)]
+fn judge(
+    options: &Options,
+    description: &str,
+    first: &str,
+    second: &str,
+    first_errors: &[String],
+    second_errors: &[String],
+    req_path: &str,
+    resp_path: &str,
+) -> Option<(Verdict, String)> {
+    if options.judge != JudgeKind::Llm {
+        let decision = judge_without_model(options, description, first, second, first_errors, second_errors, req_path);
+        if let Some(verdict) = decision.verdict {
+            eprintln!("Deterministic judge: {}", decision.reason);
+            return Some((verdict, format!("{} judge: {}", options.judge.name(), decision.reason)));
+        }
+        eprintln!("Deterministic judge undecided ({}), asking the model", decision.reason);
+    }
+
+    eprintln!("Building evaluation prompt in {} mode", options.judge_mode.name());
+    let eval_prompt = lib::judge::prompt(
+        options.judge_mode,
+        description,
+        first,
+        second,
+        first_errors,
+        second_errors,
+        options.diff_context,
     );
+    if options.judge_mode == JudgeMode::Diff {
+        let full_len = lib::judge::full_prompt(description, first, second, first_errors, second_errors).len();
+        eprintln!(
+            "Diff evaluation prompt is {} bytes, full-file prompt would be {} bytes",
+            eval_prompt.len(),
+            full_len
+        );
+    }
 
-    eprintln!("Saving evaluation request to: {}", req_path_eval);
-    fs::write(&req_path_eval, &eval_prompt)
+    eprintln!("Saving evaluation request to: {}", req_path);
+    fs::write(req_path, &eval_prompt)
         .unwrap_or_else(|_| panic!("Failed to write evaluation request file"));
+    let inputs_dir = lib::judge::inputs_dir(req_path);
+    eprintln!("Saving evaluation inputs to: {}", inputs_dir);
+    let inputs = lib::judge::Inputs {
+        description: description.to_string(),
+        first: first.to_string(),
+        second: second.to_string(),
+        first_errors: first_errors.to_vec(),
+        second_errors: second_errors.to_vec(),
+    };
+    lib::judge::save_inputs(&inputs_dir, &inputs)
+        .unwrap_or_else(|e| panic!("Failed to write evaluation inputs to {}: {}", inputs_dir, e));
 
-    eprintln!("Calling Groq API for evaluation");
-    let groq_eval = lib::groq::Groq::new();
-    let eval_response = groq_eval.evaluate(&eval_prompt);
+    let judge = provider(options, &options.judge_model);
+    eprintln!("Calling {} for evaluation", judge.name());
+    let eval_response = judge.evaluate(&eval_prompt);
     let trimmed = eval_response.trim();
 
-    eprintln!("Saving evaluation response to: {}", resp_path_eval);
-    fs::write(&resp_path_eval, &eval_response)
+    eprintln!("Saving evaluation response to: {}", resp_path);
+    fs::write(resp_path, &eval_response)
         .unwrap_or_else(|_| panic!("Failed to write evaluation response file"));
 
     eprintln!("Evaluation result: {}", trimmed);
+    let verdict = lib::judge::parse_verdict(trimmed);
+    if verdict.is_none() {
+        eprintln!("Unexpected evaluation response: {}", trimmed);
+    }
+    verdict.map(|verdict| (verdict, format!("{} judge: {}", judge.name(), trimmed)))
+}
 
-    if trimmed == "First result is better." {
-        eprintln!("First result is better");
-        if first_compiler_errors.is_empty() {
-            eprintln!("No compile errors, restoring original");
-            if Path::new(&draft_path).exists() {
-                fs::rename(&draft_path, &rej_path)
-                    .unwrap_or_else(|_| panic!("Failed to rename rejected draft"));
-            }
-            let now = SystemTime::now();
-            filetime::set_file_mtime(output_file, FileTime::from_system_time(now))
-                .expect("Failed to update mtime");
-        } else {
-            eprintln!("First result better but has compile errors");
-            if Path::new(&draft_path).exists() {
-                fs::rename(&draft_path, &rej_path)
-                    .unwrap_or_else(|_| panic!("Failed to rename rejected draft"));
-            }
-            std::process::exit(1);
+/// Commits the accepted outputs, the generated mod.rs beside each of them and their entries of
+/// the lock file, with trailers naming the spec and its hash, the provider and model, the run and the
+/// verdict. A failed commit is reported but leaves the accepted result in place.
+fn commit(options: &Options, record: &mut RunRecord) {
+    let spec = fs::read(&options.input_file)
+        .unwrap_or_else(|_| panic!("Failed to read input file: {}", options.input_file));
+    let accepted_model = record.accepted_model.clone().unwrap_or_default();
+    let (provider, model) = accepted_model.split_once(':').unwrap_or(("groq", &accepted_model));
+    let message = lib::git::message(
+        &format!("Regenerate {} from {}", options.output_file, options.input_file),
+        &[
+            ("Spec", options.input_file.clone()),
+            ("Spec-Hash", lib::git::content_hash(&spec)),
+            ("Provider", provider.to_string()),
+            ("Model", model.to_string()),
+            ("Run-Id", record.run_id.clone()),
+            ("Verdict", record.verdict.clone()),
+        ],
+    );
+    eprintln!("Committing {}", options.outputs.join(", "));
+    let mod_files: Vec<String> = options
+        .outputs
+        .iter()
+        .filter_map(|output| lib::modules::generated_beside(output))
+        .collect();
+    let mut paths: Vec<&str> = options.outputs.iter().map(String::as_str).collect();
+    for mod_file in &mod_files {
+        if !paths.contains(&mod_file.as_str()) {
+            paths.push(mod_file);
         }
-    } else if trimmed == "The second implementation is better." {
-        eprintln!("Second implementation is better");
-        fs::rename(&temp_path, output_file)
-            .unwrap_or_else(|_| panic!("Failed to move temporary file to output file"));
-        if Path::new(&draft_path).exists() {
-            fs::remove_file(&draft_path)
-                .unwrap_or_else(|_| panic!("Failed to remove draft file"));
+    }
+    let committed = lib::lockfile::commit_entries(&options.outputs, |lock| {
+        lib::git::commit_with(&paths, &[(lib::lockfile::LOCK_FILE, lock)], &message)
+    });
+    match committed {
+        Ok(hash) => {
+            eprintln!("Committed {} as {}", options.output_file, hash);
+            record.commit = Some(hash);
         }
+        Err(e) => eprintln!("Warning: failed to commit {}: {}", options.output_file, e),
+    }
+}
+
+/// Compares the two results by compiler diagnostics, tests and, with "--require-interface",
+/// the interface declared in the spec. The compile errors are those already found for each
+/// result, with "--dependents judge" its breakages of the dependent files included. Saves the
+/// evidence and the decision beside the evaluation request and response. In "filter" mode only
+/// the hard rules are applied.
+fn judge_without_model(
+    options: &Options,
+    description: &str,
+    first: &str,
+    second: &str,
+    first_errors: &[String],
+    second_errors: &[String],
+    req_path: &str,
+) -> lib::deterministic::Decision {
+    let interface = if options.require_interface {
+        lib::deterministic::declared_interface(description)
     } else {
-        eprintln!("Unexpected evaluation response: {}", trimmed);
-        std::process::exit(1);
+        Vec::new()
+    };
+    let first_evidence = evidence(options, first, first_errors, &interface);
+    let second_evidence = evidence(options, second, second_errors, &interface);
+    let decision = match options.judge {
+        JudgeKind::Filter => lib::deterministic::screen(&first_evidence, &second_evidence),
+        _ => lib::deterministic::decide(&first_evidence, &second_evidence),
+    };
+
+    let evidence_path = format!("{}-evidence.txt", req_path.trim_end_matches(".txt"));
+    let decision_path = format!("{}-decision.txt", req_path.trim_end_matches(".txt"));
+    eprintln!("Saving evidence to: {}", evidence_path);
+    fs::write(
+        &evidence_path,
+        format!("interface: {:?}\nfirst: {:?}\nsecond: {:?}\n", interface, first_evidence, second_evidence),
+    )
+    .unwrap_or_else(|_| panic!("Failed to write evidence file: {}", evidence_path));
+    fs::write(&decision_path, format!("{:?}: {}\n", decision.verdict, decision.reason))
+        .unwrap_or_else(|_| panic!("Failed to write decision file: {}", decision_path));
+    decision
+}
+
+/// The evidence for the result `content`, whose compile errors `errors` are already known. Its
+/// warnings and, if it has no errors, its tests are only measured the first time it is judged.
+fn evidence(options: &Options, content: &str, errors: &[String], interface: &[String]) -> lib::deterministic::Evidence {
+    if content.trim().is_empty() {
+        return lib::deterministic::evidence(content, errors, 0, None, interface);
     }
+    let known = options.measurements.borrow().get(content).and_then(|m| m.warnings);
+    let warnings = known.unwrap_or_else(|| {
+        eprintln!("Compiling a result in a scratch crate for its warnings");
+        let warnings = lib::cargo::diagnose_candidate(&options.output_file, content).warnings.len();
+        options
+            .measurements
+            .borrow_mut()
+            .entry(content.to_string())
+            .or_default()
+            .warnings = Some(warnings);
+        warnings
+    });
+    let tests_passed = errors.is_empty().then(|| {
+        let files = vec![(options.output_file.clone(), content.to_string())];
+        run_tests(options, "a result", content, &files).0
+    });
+    lib::deterministic::evidence(content, errors, warnings, tests_passed, interface)
+}
+
+/// Runs the tests of the result `content`, written as `files`, in a scratch crate, or returns
+/// the outcome of the earlier run for the same result.
+fn run_tests(options: &Options, label: &str, content: &str, files: &[(String, String)]) -> (bool, String) {
+    if let Some(tests) = options.measurements.borrow().get(content).and_then(|m| m.tests.clone()) {
+        eprintln!("Reusing the test outcome of {}", label);
+        return tests;
+    }
+    eprintln!("Running tests for {} in a scratch crate", label);
+    let tests = test_candidate_set(files);
+    options
+        .measurements
+        .borrow_mut()
+        .entry(content.to_string())
+        .or_default()
+        .tests = Some(tests.clone());
+    tests
+}
+
+fn candidate_record(candidate: &Candidate, outcome: &str) -> CandidateRecord {
+    CandidateRecord {
+        tier: candidate.tier,
+        model: candidate.model.clone(),
+        label: candidate.label.clone(),
+        compile_errors: candidate.errors.len(),
+        prompt_bytes: candidate.prompt_bytes,
+        response_bytes: candidate.content.len(),
+        outcome: outcome.to_string(),
+    }
+}
+
+/// Renames the draft of a candidate that was not accepted to its `.rej` name, and records why.
+fn reject(record: &mut RunRecord, candidate: &Candidate, outcome: &str) {
+    record.candidates.push(candidate_record(candidate, outcome));
+    if Path::new(&candidate.draft_path).exists() {
+        eprintln!("Renaming {} to {}", candidate.draft_path, candidate.rej_path);
+        fs::rename(&candidate.draft_path, &candidate.rej_path)
+            .unwrap_or_else(|_| panic!("Failed to rename rejected draft"));
+    }
+    lib::atomic::forget(&candidate.draft_path);
+}
 
-    eprintln!("Program completed successfully");
-}
-
-
-use serde_json::Value;
-
-/// Runs `cargo check --message-format json` and returns compilation errors 
-/// for the specified source file only.
-/// 
-/// # Arguments
-/// * `source_file` - The path to the source file to check for errors
-/// 
-/// # Returns
-/// A vector of error message strings for the specified source file
-/// 
-/// # Panics
-/// Panics if the cargo command cannot be executed or if JSON parsing fails
-fn run_cargo_check(source_file: &str) -> Vec<String> {
-    // Execute cargo check with JSON output
-    let output = Command::new("cargo")
-        .args(&["check", "--message-format", "json"])
-        .output()
-        .expect("Failed to execute cargo check command");
-
-    // Convert output to string
-    let stdout = String::from_utf8(output.stdout)
-        .expect("Failed to convert cargo output to UTF-8");
-
-    let mut errors = Vec::new();
-    let source_path = Path::new(source_file);
-    
-    // Parse each line of JSON output
-    for line in stdout.lines() {
-        if line.trim().is_empty() {
+/// Atomically replaces the output files with the accepted candidate, all of them or none, and
+/// removes its draft. New modules are declared in the generated mod.rs beside them.
+fn accept(options: &Options, candidate: &Candidate) {
+    let files = candidate_files(options, &candidate.content).expect("Accepted candidate does not parse");
+    eprintln!("Writing accepted result to: {}", options.outputs.join(", "));
+    lib::atomic::write_all(&files)
+        .unwrap_or_else(|e| panic!("Failed to write output files {}: {}", options.outputs.join(", "), e));
+    for output in &options.outputs {
+        let Some(dir) = Path::new(output).parent() else {
             continue;
+        };
+        match lib::modules::refresh(dir) {
+            Ok(true) => eprintln!("Declared the modules of {} in its mod.rs", dir.display()),
+            Ok(false) => {}
+            Err(e) => eprintln!("Warning: {}", e),
         }
-        
-        // Parse JSON line
-        let json: Value = serde_json::from_str(line)
-            .expect("Failed to parse JSON output from cargo");
-        
-        // Check if this is a compiler message
-        if let Some(reason) = json.get("reason") {
-            if reason == "compiler-message" {
-                if let Some(message) = json.get("message") {
-                    // Check if this message has spans (location information)
-                    if let Some(spans) = message.get("spans").and_then(|s| s.as_array()) {
-                        for span in spans {
-                            if let Some(file_name) = span.get("file_name").and_then(|f| f.as_str()) {
-                                let span_path = Path::new(file_name);
-                                
-                                // Check if this error is from our target source file
-                                if span_path == source_path || 
-                                   span_path.file_name() == source_path.file_name() {
-                                    
-                                    // Extract the error message
-                                    if let Some(rendered) = message.get("rendered").and_then(|r| r.as_str()) {
-                                        errors.push(rendered.to_string());
-                                    } else if let Some(msg_text) = message.get("message").and_then(|m| m.as_str()) {
-                                        errors.push(msg_text.to_string());
-                                    }
-                                    break; // Found matching file, no need to check other spans
-                                }
-                            }
-                        }
-                    }
-                    // Handle messages without spans (global errors)
-                    else if message.get("level").and_then(|l| l.as_str()) == Some("error") {
-                        if let Some(rendered) = message.get("rendered").and_then(|r| r.as_str()) {
-                            errors.push(rendered.to_string());
-                        } else if let Some(msg_text) = message.get("message").and_then(|m| m.as_str()) {
-                            errors.push(msg_text.to_string());
-                        }
-                    }
-                }
-            }
-        }
     }
+    if Path::new(&candidate.draft_path).exists() {
+        eprintln!("Removing draft file: {}", candidate.draft_path);
+        fs::remove_file(&candidate.draft_path)
+            .unwrap_or_else(|_| panic!("Failed to remove draft file"));
+    }
+    lib::atomic::forget(&candidate.draft_path);
+}
+
+/// Saves compile errors into the run directory, one message after the other.
+fn save_diagnostics(path: &str, errors: &[String]) {
+    eprintln!("Saving {} compile errors to: {}", errors.len(), path);
+    fs::write(path, errors.join("\n"))
+        .unwrap_or_else(|_| panic!("Failed to write diagnostics file: {}", path));
+}
+
+/// Reports the errors a candidate causes in the dependent files, and saves them beside its
+/// diagnostics as "<name>-dependents.txt".
+fn report_downstream(label: &str, diagnostics_path: &str, downstream: &[String]) {
+    if downstream.is_empty() {
+        return;
+    }
+    let path = format!("{}-dependents.txt", diagnostics_path.trim_end_matches(".txt"));
+    eprintln!("{} causes {} new errors in dependent files, saving them to: {}", label, downstream.len(), path);
+    fs::write(&path, downstream.join("\n")).unwrap_or_else(|_| panic!("Failed to write diagnostics file: {}", path));
+}
 
-    if errors.len() > 20 {
-       errors.truncate(20);
+/// Records the kept output file in the lock file, and updates its mtime so that make, which
+/// only compares times, sees it as up to date too.
+fn mark_up_to_date(options: &Options, verdict: &str) {
+    for output in options.outputs.iter().filter(|output| Path::new(output).exists()) {
+        lib::lockfile::record(&options.input_file, output, "", verdict);
+        lib::atomic::touch(output).expect("Failed to update mtime");
+    }
+}
+
+/// The files of a candidate: the output file alone, or the files of a multi-file result.
+fn candidate_files(options: &Options, content: &str) -> Result<Vec<(String, String)>, String> {
+    if options.outputs.len() > 1 {
+        lib::multifile::parse(content, &options.outputs)
+    } else {
+        Ok(vec![(options.output_file.clone(), content.to_string())])
     }
-    
-    errors
-}
\ No newline at end of file
+}
+
+/// Compiles a candidate in a scratch copy of the crate and returns the errors for its files,
+/// and the errors it causes in the dependent files that they did not have before. A
+/// multi-file result that does not parse gets that as its only error.
+fn compile(options: &Options, inputs: &Inputs, content: &str) -> (Vec<String>, Vec<String>) {
+    let files = match candidate_files(options, content) {
+        Ok(files) => files,
+        Err(e) => return (vec![format!("error: malformed multi-file result: {}", e)], Vec::new()),
+    };
+    let dependents: Vec<&str> = options.dependent_files.iter().map(String::as_str).collect();
+    let (errors, dependent_errors) = check_candidate_set_dependents(&files, &dependents);
+    let downstream = lib::cargo::new_errors(&inputs.dependent_errors, &dependent_errors)
+        .into_iter()
+        .map(|(_, error)| error)
+        .collect();
+    (errors, downstream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// Options parsed from `args`, for the spec `spec` and the output `output`.
+    fn options(args: &[&str], spec: &str, output: &str) -> Options {
+        let mut all = vec!["llm-groq-5".to_string()];
+        all.extend(args.iter().map(|a| a.to_string()));
+        all.extend([spec.to_string(), output.to_string()]);
+        parse_args(&all)
+    }
+
+    #[test]
+    fn test_deterministic_judge_counts_dependent_breakages() {
+        let dir = tempdir().unwrap();
+        let spec = dir.path().join("run.md").to_string_lossy().into_owned();
+        let output = dir.path().join("run.rs").to_string_lossy().into_owned();
+        fs::write(&spec, "# Library Interface\n\n- fn run(a: u32)\n").unwrap();
+        let options = options(&["--judge", "deterministic", "--dependents", "judge"], &spec, &output);
+
+        // Both results were measured by earlier rounds: the judge compiles and tests neither again.
+        let original = "pub fn run(a: u32) {}\n";
+        let candidate = Candidate {
+            label: "candidate".to_string(),
+            model: "mock".to_string(),
+            tier: 1,
+            draft_path: String::new(),
+            rej_path: String::new(),
+            content: "pub fn run(a: u32, b: u32) {}\n".to_string(),
+            errors: Vec::new(),
+            downstream: vec!["error[E0061]: this function takes 2 arguments but 1 argument was supplied".to_string()],
+            prompt_bytes: 0,
+        };
+        for content in [original, candidate.content.as_str()] {
+            let measurement = Measurement {
+                warnings: Some(0),
+                tests: Some((true, String::new())),
+            };
+            options.measurements.borrow_mut().insert(content.to_string(), measurement);
+        }
+
+        let req_path = dir.path().join("eval.txt").to_string_lossy().into_owned();
+        let resp_path = dir.path().join("eval-resp.txt").to_string_lossy().into_owned();
+        let (verdict, reason) = judge(
+            &options,
+            "",
+            original,
+            &candidate.content,
+            &[],
+            &candidate.judged_errors(&options),
+            &req_path,
+            &resp_path,
+        )
+        .unwrap();
+        assert_eq!(verdict, Verdict::First);
+        assert!(reason.contains("second result has 1 compile errors"), "{}", reason);
+    }
+}
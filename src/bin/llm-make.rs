@@ -0,0 +1,603 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+#[allow(dead_code)]
+mod lib;
+
+use lib::lockfile::Status;
+use lib::targets::Target;
+
+/// The output of every driver run goes to "<LOG_DIR>/<slug of the output file>.log".
+const LOG_DIR: &str = ".llm-make";
+
+struct Options {
+    jobs: usize,
+    driver: Option<String>,
+    dry_run: bool,
+    /// Only write the Make dependency files of the selected targets.
+    depfiles: bool,
+    /// Only check the selected targets against the lock file, failing if a spec changed.
+    verify: bool,
+    /// Only record the selected targets in the lock file as they are now.
+    relock: bool,
+    /// Only regenerate the mod.rs of the module directories.
+    modules: bool,
+    keep_going: bool,
+    /// Regenerate this spec, or output file, with the targets depending on it as one change.
+    changeset: Option<String>,
+    /// Only these specs or output files, if any are given.
+    only: Vec<String>,
+    driver_args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Accepted,
+    Kept,
+    Rejected,
+    Failed,
+    /// Not run because a target it depends on was rejected or failed.
+    Skipped,
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Accepted => "accepted",
+            Outcome::Kept => "kept",
+            Outcome::Rejected => "rejected",
+            Outcome::Failed => "failed",
+            Outcome::Skipped => "skipped",
+        }
+    }
+
+    fn is_success(&self) -> bool {
+        matches!(self, Outcome::Accepted | Outcome::Kept)
+    }
+}
+
+/// What one driver run reported back to the scheduler.
+struct Finished {
+    index: usize,
+    outcome: Outcome,
+    seconds: f64,
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [-j <jobs>] [--driver <binary>] [--dry-run] [--depfiles] [--verify] [--relock] [--modules] [--keep-going] [--changeset <spec|output>] [<spec|output>..] [-- <driver options>..]",
+        program
+    );
+    std::process::exit(1);
+}
+
+fn parse_args(args: &[String]) -> Options {
+    let mut jobs = 1;
+    let mut driver = None;
+    let mut dry_run = false;
+    let mut depfiles = false;
+    let mut verify = false;
+    let mut relock = false;
+    let mut modules = false;
+    let mut keep_going = false;
+    let mut changeset = None;
+    let mut only = Vec::new();
+    let mut driver_args = Vec::new();
+
+    let mut iter = args[1..].iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-j" | "--jobs" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                jobs = value.parse().unwrap_or_else(|_| usage(&args[0]));
+                if jobs == 0 {
+                    usage(&args[0]);
+                }
+            }
+            "--driver" => driver = Some(iter.next().unwrap_or_else(|| usage(&args[0])).clone()),
+            "--dry-run" => dry_run = true,
+            "--depfiles" => depfiles = true,
+            "--verify" => verify = true,
+            "--relock" => relock = true,
+            "--modules" => modules = true,
+            "--keep-going" => keep_going = true,
+            "--changeset" => {
+                let value = iter.next().unwrap_or_else(|| usage(&args[0]));
+                changeset = Some(value.trim_start_matches("./").to_string());
+            }
+            "--" => driver_args = iter.by_ref().cloned().collect(),
+            _ if arg.starts_with('-') => usage(&args[0]),
+            _ => only.push(arg.trim_start_matches("./").to_string()),
+        }
+    }
+
+    Options {
+        jobs,
+        driver,
+        dry_run,
+        depfiles,
+        verify,
+        relock,
+        modules,
+        keep_going,
+        changeset,
+        only,
+        driver_args,
+    }
+}
+
+fn driver_for(options: &Options, target: &Target) -> String {
+    if let Some(driver) = &options.driver {
+        return driver.clone();
+    }
+    lib::frontmatter::settings_for(&target.spec)
+        .ok()
+        .and_then(|settings| settings.driver)
+        .unwrap_or_else(|| lib::config::project().make_driver.clone())
+}
+
+/// Orders `planned` so that every target comes after the planned targets it depends on.
+/// Targets on a dependency cycle keep their discovery order, after a warning.
+fn schedule_order(planned: &[usize], graph: &[Vec<usize>], targets: &[Target]) -> Vec<usize> {
+    let mut order = Vec::new();
+    let mut remaining: Vec<usize> = planned.to_vec();
+    while !remaining.is_empty() {
+        let ready: Vec<usize> = remaining
+            .iter()
+            .copied()
+            .filter(|&i| graph[i].iter().all(|dep| !remaining.contains(dep)))
+            .collect();
+        if ready.is_empty() {
+            let cycle: Vec<&str> = remaining.iter().map(|&i| targets[i].output.as_str()).collect();
+            eprintln!("Warning: dependency cycle between {}", cycle.join(", "));
+            order.append(&mut remaining);
+            break;
+        }
+        remaining.retain(|i| !ready.contains(i));
+        order.extend(ready);
+    }
+    order
+}
+
+/// Runs the driver for one target with its output going to the log file, and works out how
+/// the run ended: from the run manifest if the driver writes one, otherwise from the exit
+/// status and whether the output file changed. Successful results are recorded in the lock
+/// file if the driver did not do it itself.
+fn run_target(driver: PathBuf, driver_args: Vec<String>, target: Target, log_path: String) -> Outcome {
+    let name = driver.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let (outcome, model) = run_driver(driver, driver_args, &target, log_path);
+    let recorded = lib::lockfile::Lockfile::load().status(&target.spec, &target.output) == Status::UpToDate;
+    if outcome.is_success() && !recorded {
+        let model = match outcome {
+            Outcome::Accepted => model.unwrap_or(name),
+            _ => String::new(),
+        };
+        lib::lockfile::record(&target.spec, &target.output, &model, outcome.name());
+    }
+    outcome
+}
+
+/// Runs the driver, returning how the run ended and the accepted model if the run manifest
+/// names one.
+fn run_driver(driver: PathBuf, driver_args: Vec<String>, target: &Target, log_path: String) -> (Outcome, Option<String>) {
+    let before = fs::read(&target.output).ok();
+    let latest_run = lib::runs::latest_for(&target.output);
+    let log = File::create(&log_path).unwrap_or_else(|_| panic!("Failed to create log file: {}", log_path));
+    let status = Command::new(&driver)
+        .args(&driver_args)
+        .args([&target.spec, &target.output])
+        .stdout(Stdio::from(log.try_clone().expect("Failed to clone log file handle")))
+        .stderr(Stdio::from(log))
+        .status();
+    let Ok(status) = status else {
+        return (Outcome::Failed, None);
+    };
+
+    let run = lib::runs::latest_for(&target.output).filter(|id| Some(id) != latest_run.as_ref());
+    if let Some(record) = run.and_then(|id| lib::runs::load_manifest(&id)) {
+        let outcome = match record.result.as_str() {
+            "accepted" => Some(Outcome::Accepted),
+            "kept" => Some(Outcome::Kept),
+            "rejected" => Some(Outcome::Rejected),
+            "failed" => Some(Outcome::Failed),
+            _ => None,
+        };
+        // A driver that exits with an error has failed, whatever its manifest says.
+        if let Some(outcome) = outcome
+            && (status.success() || !outcome.is_success())
+        {
+            return (outcome, record.accepted_model);
+        }
+    }
+    let changed = fs::read(&target.output).ok() != before;
+    let outcome = match (status.success(), changed) {
+        (false, _) => Outcome::Failed,
+        (true, true) => Outcome::Accepted,
+        (true, false) => Outcome::Kept,
+    };
+    (outcome, None)
+}
+
+/// Checks the selected targets against the lock file and lists the ones that are not up to
+/// date. Fails if a spec changed, an output is missing, or a target is not in the lock file at
+/// all, without a regeneration.
+fn verify(targets: &[Target], selected: &[usize]) -> bool {
+    let lock = lib::lockfile::Lockfile::load();
+    let mut counts: HashMap<Status, usize> = HashMap::new();
+    for &i in selected {
+        let status = lock.status(&targets[i].spec, &targets[i].output);
+        *counts.entry(status).or_default() += 1;
+        if status != Status::UpToDate {
+            println!("{:<15} {} ({})", status.name(), targets[i].output, targets[i].spec);
+        }
+    }
+    let count = |status: Status| counts.get(&status).copied().unwrap_or(0);
+    let failed = count(Status::SpecChanged) + count(Status::MissingOutput) + count(Status::Untracked);
+    eprintln!(
+        "{} up to date, {} need regenerating ({} of them not in {}), {} edited since",
+        count(Status::UpToDate),
+        failed,
+        count(Status::Untracked),
+        lib::lockfile::LOCK_FILE,
+        count(Status::OutputEdited)
+    );
+    if failed > 0 {
+        eprintln!("Run llm-make, or \"llm-make --relock\" if the outputs already match their specs");
+    }
+    failed == 0
+}
+
+/// Builds the driver of every target in `order`, and creates the log directory.
+fn build_drivers(options: &Options, targets: &[Target], order: &[usize]) -> HashMap<String, PathBuf> {
+    let mut drivers: HashMap<String, PathBuf> = HashMap::new();
+    for &i in order {
+        drivers.entry(driver_for(options, &targets[i])).or_insert_with_key(|name| {
+            eprintln!("Building {}", name);
+            lib::cargo::build_binary(name).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            })
+        });
+    }
+    fs::create_dir_all(LOG_DIR).unwrap_or_else(|_| panic!("Failed to create log directory: {}", LOG_DIR));
+    drivers
+}
+
+/// The target `root` and every target depending on it, directly or through other targets.
+fn with_dependents(root: usize, graph: &[Vec<usize>]) -> Vec<usize> {
+    let mut members = vec![root];
+    let mut index = 0;
+    while index < members.len() {
+        let current = members[index];
+        for (i, deps) in graph.iter().enumerate() {
+            if deps.contains(&current) && !members.contains(&i) {
+                members.push(i);
+            }
+        }
+        index += 1;
+    }
+    members
+}
+
+/// Puts every file of `snapshot` back as it was, removing the ones that did not exist.
+fn restore(snapshot: &[(String, Option<Vec<u8>>)]) {
+    for (path, content) in snapshot {
+        let result = match content {
+            Some(content) if fs::read(path).ok().as_ref() != Some(content) => lib::atomic::write(path, content),
+            None if Path::new(path).exists() => fs::remove_file(path),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            eprintln!("Error: failed to restore {}: {}", path, e);
+        }
+    }
+}
+
+/// Regenerates `root` and the targets depending on it, one after the other, as one change: once
+/// all of them succeeded, the whole crate is checked with all the results in place, and the
+/// changed files are committed together. If a driver does not succeed, or the crate has errors
+/// it did not have before, every file of the changeset, and the lock file, is put back as it was.
+fn run_changeset(options: &Options, targets: &[Target], graph: &[Vec<usize>], root: usize) -> bool {
+    let members = with_dependents(root, graph);
+    let order = schedule_order(&members, graph, targets);
+    let outputs: Vec<&str> = order.iter().map(|&i| targets[i].output.as_str()).collect();
+    eprintln!("Changeset of {}: {}", targets[root].spec, outputs.join(", "));
+    if options.dry_run {
+        for &i in &order {
+            println!("{} {} {}", driver_for(options, &targets[i]), targets[i].spec, targets[i].output);
+        }
+        return true;
+    }
+
+    let mut files = Vec::new();
+    for &i in &order {
+        files.push(targets[i].output.clone());
+        files.extend(lib::mapping::extra_outputs(&targets[i].spec));
+        if let Some(mod_file) = lib::modules::generated_beside(&targets[i].output)
+            && !files.contains(&mod_file)
+        {
+            files.push(mod_file);
+        }
+    }
+    files.push(lib::lockfile::LOCK_FILE.to_string());
+    let snapshot: Vec<(String, Option<Vec<u8>>)> = files.iter().map(|f| (f.clone(), fs::read(f).ok())).collect();
+
+    eprintln!("Checking the crate before the changeset");
+    let baseline = lib::cargo::run_cargo_check_crate();
+    let drivers = build_drivers(options, targets, &order);
+    // The changeset is committed as a whole, not by the drivers one file at a time.
+    let driver_args: Vec<String> = options.driver_args.iter().filter(|a| *a != "--commit").cloned().collect();
+
+    let mut results = Vec::new();
+    let mut succeeded = true;
+    for &i in &order {
+        let name = driver_for(options, &targets[i]);
+        let log_path = format!("{}/{}.log", LOG_DIR, lib::runs::slug(&targets[i].output));
+        eprintln!("Running {} {} (log: {})", name, targets[i].output, log_path);
+        let started = Instant::now();
+        let mut args = driver_args.clone();
+        // The dependents of the root are regenerated next and the crate is checked as a whole
+        // at the end, so llm-groq-5 must not drop the candidates that break them meanwhile.
+        if i == root && name == "llm-groq-5" {
+            args.splice(0..0, ["--dependents".to_string(), "off".to_string()]);
+        }
+        let outcome = run_target(drivers[&name].clone(), args, targets[i].clone(), log_path);
+        results.push((i, outcome, started.elapsed().as_secs_f64()));
+        eprintln!("Finished {}: {}", targets[i].output, outcome.name());
+        if !outcome.is_success() {
+            succeeded = false;
+            break;
+        }
+    }
+    if succeeded {
+        eprintln!("Checking the crate with the whole changeset applied");
+        let broken = lib::cargo::new_errors(&baseline, &lib::cargo::run_cargo_check_crate());
+        for (file, error) in &broken {
+            eprintln!("New error in {}:\n{}", file, error);
+        }
+        succeeded = broken.is_empty();
+    }
+    print_summary(targets, &results, 0);
+
+    if !succeeded {
+        eprintln!("Rolling back the changeset of {}", targets[root].spec);
+        restore(&snapshot);
+        return false;
+    }
+    let changed: Vec<&str> = snapshot
+        .iter()
+        .filter(|(path, before)| &fs::read(path).ok() != before)
+        .map(|(path, _)| path.as_str())
+        .collect();
+    if changed.is_empty() {
+        eprintln!("Nothing changed");
+        return true;
+    }
+    if !lib::git::is_repository() {
+        eprintln!("Warning: not in a git repository, leaving the changeset uncommitted");
+        return true;
+    }
+    let message = lib::git::message(
+        &format!("Regenerate {} with its dependents", targets[root].spec),
+        &[("Spec", targets[root].spec.clone()), ("Changeset", changed.join(", "))],
+    );
+    eprintln!("Committing {}", changed.join(", "));
+    match lib::git::commit(&changed, &message) {
+        Ok(hash) => {
+            eprintln!("Committed the changeset as {}", hash);
+            true
+        }
+        Err(e) => {
+            eprintln!("Error: failed to commit the changeset, leaving it in place: {}", e);
+            false
+        }
+    }
+}
+
+/// Regenerates the mod.rs of every module directory but those maintained by hand, so that
+/// they declare the modules in them. Returns false if one could not be written.
+fn sync_modules() -> bool {
+    match lib::modules::sync_all() {
+        Ok(written) => {
+            for path in written {
+                eprintln!("Wrote {}", path);
+            }
+            true
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            false
+        }
+    }
+}
+
+fn print_summary(targets: &[Target], results: &[(usize, Outcome, f64)], up_to_date: usize) {
+    let width = results
+        .iter()
+        .map(|(i, _, _)| targets[*i].output.len())
+        .chain(["output".len()])
+        .max()
+        .unwrap_or(0);
+    println!("{:<width$}  {:<9} {:>8}", "output", "result", "seconds", width = width);
+    for (index, outcome, seconds) in results {
+        println!("{:<width$}  {:<9} {:>8.1}", targets[*index].output, outcome.name(), seconds, width = width);
+    }
+    let count = |outcome: Outcome| results.iter().filter(|(_, o, _)| *o == outcome).count();
+    println!(
+        "{} accepted, {} kept, {} rejected, {} failed, {} skipped, {} up to date",
+        count(Outcome::Accepted),
+        count(Outcome::Kept),
+        count(Outcome::Rejected),
+        count(Outcome::Failed),
+        count(Outcome::Skipped),
+        up_to_date
+    );
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = parse_args(&args);
+
+    eprintln!("Discovering specs in {}", lib::config::project().instruct_dir);
+    let targets = lib::targets::discover();
+    let graph = lib::targets::dependency_graph(&targets);
+    let selected: Vec<usize> = (0..targets.len())
+        .filter(|&i| {
+            options.only.is_empty() || options.only.contains(&targets[i].spec) || options.only.contains(&targets[i].output)
+        })
+        .collect();
+
+    if options.depfiles {
+        for &i in &selected {
+            match lib::targets::write_depfile(&targets[i]) {
+                Ok(path) => eprintln!("Wrote {}", path),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        return;
+    }
+    if options.verify {
+        if !verify(&targets, &selected) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if options.relock {
+        for &i in &selected {
+            eprintln!("Recording {}", targets[i].output);
+            lib::lockfile::record(&targets[i].spec, &targets[i].output, "", "relocked");
+        }
+        return;
+    }
+    if options.modules {
+        if !sync_modules() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(changeset) = &options.changeset {
+        let Some(root) = targets.iter().position(|t| &t.spec == changeset || &t.output == changeset) else {
+            eprintln!("Error: {} is not a spec or the output of one", changeset);
+            std::process::exit(1);
+        };
+        if !run_changeset(&options, &targets, &graph, root) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let lock = lib::lockfile::Lockfile::load();
+    let planned: Vec<usize> = selected
+        .iter()
+        .copied()
+        .filter(|&i| lib::targets::is_stale(&targets[i], &lock))
+        .collect();
+    let up_to_date = selected.len() - planned.len();
+    let order = schedule_order(&planned, &graph, &targets);
+    eprintln!("{} targets, {} stale, {} up to date", selected.len(), planned.len(), up_to_date);
+
+    if options.dry_run {
+        for &i in &order {
+            let deps: Vec<&str> = graph[i]
+                .iter()
+                .filter(|d| planned.contains(d))
+                .map(|&d| targets[d].output.as_str())
+                .collect();
+            print!("{} {} {}", driver_for(&options, &targets[i]), targets[i].spec, targets[i].output);
+            if !deps.is_empty() {
+                print!("  (after {})", deps.join(", "));
+            }
+            println!();
+        }
+        return;
+    }
+    if order.is_empty() {
+        eprintln!("Nothing to do");
+        return;
+    }
+
+    let drivers = build_drivers(&options, &targets, &order);
+
+    let (sender, receiver) = mpsc::channel::<Finished>();
+    let mut pending = order.clone();
+    let mut done: HashMap<usize, Outcome> = HashMap::new();
+    let mut results = Vec::new();
+    let mut running = 0;
+    let mut stop = false;
+    loop {
+        // Skip the targets whose dependencies did not succeed, start the ready ones.
+        let mut index = 0;
+        while index < pending.len() && running < options.jobs && !stop {
+            let i = pending[index];
+            // Only the targets scheduled before this one count; on a cycle that breaks it.
+            let position = |target: &usize| order.iter().position(|o| o == target);
+            let deps: Vec<usize> = graph[i]
+                .iter()
+                .copied()
+                .filter(|d| position(d).is_some_and(|p| Some(p) < position(&i)))
+                .collect();
+            if deps.iter().any(|d| done.get(d).is_some_and(|o| !o.is_success())) {
+                eprintln!("Skipping {}: a target it depends on did not succeed", targets[i].output);
+                pending.remove(index);
+                done.insert(i, Outcome::Skipped);
+                results.push((i, Outcome::Skipped, 0.0));
+                continue;
+            }
+            if !deps.iter().all(|d| done.contains_key(d)) {
+                index += 1;
+                continue;
+            }
+            pending.remove(index);
+            let target = targets[i].clone();
+            let name = driver_for(&options, &target);
+            let log_path = format!("{}/{}.log", LOG_DIR, lib::runs::slug(&target.output));
+            eprintln!("Starting {} {} (log: {})", name, target.output, log_path);
+            let driver = drivers[&name].clone();
+            let driver_args = options.driver_args.clone();
+            let sender = sender.clone();
+            running += 1;
+            thread::spawn(move || {
+                let started = Instant::now();
+                let outcome = run_target(driver, driver_args, target, log_path);
+                let _ = sender.send(Finished {
+                    index: i,
+                    outcome,
+                    seconds: started.elapsed().as_secs_f64(),
+                });
+            });
+        }
+        if running == 0 {
+            break;
+        }
+
+        let finished = receiver.recv().expect("Failed to receive the result of a driver run");
+        running -= 1;
+        eprintln!("Finished {}: {}", targets[finished.index].output, finished.outcome.name());
+        if !finished.outcome.is_success() && !options.keep_going {
+            eprintln!("Stopping after {} {}, waiting for running targets", targets[finished.index].output, finished.outcome.name());
+            stop = true;
+        }
+        done.insert(finished.index, finished.outcome);
+        results.push((finished.index, finished.outcome, finished.seconds));
+    }
+    // Declare the modules that were generated for the first time.
+    sync_modules();
+
+    print_summary(&targets, &results, up_to_date);
+    if !pending.is_empty() {
+        println!("{} targets not started", pending.len());
+    }
+    if stop || results.iter().any(|(_, o, _)| !o.is_success()) {
+        std::process::exit(1);
+    }
+}
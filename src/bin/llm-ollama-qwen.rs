@@ -7,15 +7,25 @@ use std::{
 };
 use serde_json::json;
 
+#[allow(dead_code)]
+mod lib;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let safety = lib::git::Safety::from_args(&mut args);
     if args.len() != 3 {
-        eprintln!("Usage: {} <input_description_file> <output_file>", args[0]);
+        eprintln!("Usage: {} [--allow-dirty] [--clean-spec] <input_description_file> <output_file>", args[0]);
         std::process::exit(1);
     }
 
     let input_desc_file = &args[1];
     let output_file = &args[2];
+
+    if let Err(e) = safety.check(input_desc_file, output_file) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+
     let draft_file = format!("{}.draft", output_file);
     let reject_file = format!("{}.rej", output_file);
 
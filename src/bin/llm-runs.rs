@@ -0,0 +1,126 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[allow(dead_code)]
+mod lib;
+
+use lib::runs::{self, RUNS_DIR};
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} list [--file <output_file>] | show <run_id|latest>", program);
+    std::process::exit(1);
+}
+
+fn list(program: &str, args: &[String]) {
+    let file = match args {
+        [] => None,
+        [flag, file] if flag == "--file" => Some(file),
+        _ => usage(program),
+    };
+
+    for id in runs::list() {
+        let Some(record) = runs::load_manifest(&id) else {
+            println!("{}  (no manifest)", id);
+            continue;
+        };
+        if file.is_some_and(|f| &record.output_file != f) {
+            continue;
+        }
+        let result = if record.result.is_empty() { "running" } else { &record.result };
+        println!(
+            "{}  {:<9} {:>5}s  {}  {}",
+            id,
+            result,
+            record.finished.saturating_sub(record.started),
+            record.output_file,
+            record.accepted_model.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+/// Lists the files of the run directory with their sizes, descending into tier subdirectories.
+fn print_files(dir: &Path, prefix: &str) {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .map(|entries| entries.filter_map(Result::ok).collect())
+        .unwrap_or_default();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let path = entry.path();
+        if path.is_dir() {
+            print_files(&path, &format!("{}/", name));
+        } else {
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            println!("  {:<40} {:>9} bytes", name, size);
+        }
+    }
+}
+
+fn show(program: &str, args: &[String]) {
+    let [id] = args else {
+        usage(program);
+    };
+    let id = if id == "latest" {
+        runs::list().pop().unwrap_or_else(|| {
+            eprintln!("No runs in {}", RUNS_DIR);
+            std::process::exit(1);
+        })
+    } else {
+        id.clone()
+    };
+    let dir = Path::new(RUNS_DIR).join(&id);
+    if !dir.is_dir() {
+        eprintln!("No such run: {}", id);
+        std::process::exit(1);
+    }
+
+    println!("Run {}", id);
+    match runs::load_manifest(&id) {
+        Some(record) => {
+            println!("  input:    {}", record.input_file);
+            println!("  output:   {}", record.output_file);
+            println!("  args:     {}", record.args.join(" "));
+            println!("  result:   {}", if record.result.is_empty() { "running" } else { &record.result });
+            if let (Some(tier), Some(model)) = (record.accepted_tier, &record.accepted_model) {
+                println!("  accepted: tier {} ({})", tier, model);
+            }
+            if !record.verdict.is_empty() {
+                println!("  verdict:  {}", record.verdict);
+            }
+            if let Some(commit) = &record.commit {
+                println!("  commit:   {}", commit);
+            }
+            for fallback in &record.fallbacks {
+                println!("  fallback: {} -> {} ({})", fallback.from, fallback.to, fallback.error);
+            }
+            if !record.candidates.is_empty() {
+                println!("Candidates:");
+                for c in &record.candidates {
+                    println!(
+                        "  tier {} {:<18} {:<40} {:>3} errors  {}",
+                        c.tier, c.label, c.model, c.compile_errors, c.outcome
+                    );
+                }
+            }
+            if !record.timings.is_empty() {
+                println!("Timings:");
+                for t in &record.timings {
+                    println!("  {:<20} {:>8.1}s", t.step, t.seconds);
+                }
+            }
+        }
+        None => println!("  (no manifest)"),
+    }
+    println!("Files:");
+    print_files(&dir, "");
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("list") => list(&args[0], &args[2..]),
+        Some("show") => show(&args[0], &args[2..]),
+        _ => usage(&args[0]),
+    }
+}
@@ -0,0 +1,86 @@
+use std::env;
+
+#[allow(dead_code)]
+mod lib;
+
+use lib::history::{self, ModelStats};
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} models [--file <output_file>] [--overall]", program);
+    std::process::exit(1);
+}
+
+fn print_table(stats: &[ModelStats], per_file: bool) {
+    let file_width = stats
+        .iter()
+        .map(|s| s.output_file.len())
+        .chain(["file".len()])
+        .max()
+        .unwrap_or(0);
+    let model_width = stats
+        .iter()
+        .map(|s| s.model.len())
+        .chain(["model".len()])
+        .max()
+        .unwrap_or(0);
+
+    if per_file {
+        print!("{:<width$}  ", "file", width = file_width);
+    }
+    println!(
+        "{:<width$}  {:>10}  {:>9}  {:>9}  {:>10}",
+        "model",
+        "candidates",
+        "compiled",
+        "accepted",
+        "bytes/cand",
+        width = model_width
+    );
+    for s in stats {
+        if per_file {
+            print!("{:<width$}  ", s.output_file, width = file_width);
+        }
+        println!(
+            "{:<width$}  {:>10}  {:>8.0}%  {:>8.0}%  {:>10}",
+            s.model,
+            s.candidates,
+            s.compile_rate() * 100.0,
+            s.acceptance_rate() * 100.0,
+            s.bytes_per_candidate(),
+            width = model_width
+        );
+    }
+}
+
+fn models(program: &str, args: &[String]) {
+    let mut file = None;
+    let mut overall = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--file" => file = Some(iter.next().unwrap_or_else(|| usage(program)).clone()),
+            "--overall" => overall = true,
+            _ => usage(program),
+        }
+    }
+
+    eprintln!("Reading run history from {}", history::HISTORY_FILE);
+    let records: Vec<_> = history::load()
+        .into_iter()
+        .filter(|r| file.as_ref().is_none_or(|f| &r.output_file == f))
+        .collect();
+    if records.is_empty() {
+        eprintln!("No recorded runs");
+        return;
+    }
+    eprintln!("{} recorded runs", records.len());
+    print_table(&history::model_stats(&records, !overall), !overall);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("models") => models(&args[0], &args[2..]),
+        _ => usage(&args[0]),
+    }
+}
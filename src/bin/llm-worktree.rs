@@ -0,0 +1,201 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[allow(dead_code)]
+mod lib;
+
+use lib::targets::Target;
+
+/// Drivers that commit accepted results themselves, with provenance trailers, when given "--commit".
+const COMMITTING_DRIVERS: &[&str] = &["llm-groq-5"];
+
+const DEFAULT_DRIVER: &str = "llm-groq-5";
+
+struct Options {
+    branch: String,
+    driver: String,
+    keep: bool,
+    specs: Vec<String>,
+    driver_args: Vec<String>,
+}
+
+/// How the regeneration of one target ended.
+enum Outcome {
+    Committed(String),
+    Kept,
+    NotAccepted(Option<i32>),
+    CommitFailed(String),
+}
+
+impl Outcome {
+    fn describe(&self) -> String {
+        match self {
+            Outcome::Committed(hash) => format!("committed {}", &hash[..hash.len().min(12)]),
+            Outcome::Kept => "kept".to_string(),
+            Outcome::NotAccepted(Some(code)) => format!("not accepted (exit {})", code),
+            Outcome::NotAccepted(None) => "not accepted (killed)".to_string(),
+            Outcome::CommitFailed(e) => format!("commit failed: {}", e),
+        }
+    }
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--branch <name>] [--driver <binary>] [--keep] [<spec>..] [-- <driver options>..]",
+        program
+    );
+    std::process::exit(1);
+}
+
+fn parse_args(args: &[String]) -> Options {
+    let mut branch = None;
+    let mut driver = DEFAULT_DRIVER.to_string();
+    let mut keep = false;
+    let mut specs = Vec::new();
+    let mut driver_args = Vec::new();
+
+    let mut iter = args[1..].iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--branch" => branch = Some(iter.next().unwrap_or_else(|| usage(&args[0])).clone()),
+            "--driver" => driver = iter.next().unwrap_or_else(|| usage(&args[0])).clone(),
+            "--keep" => keep = true,
+            "--" => {
+                driver_args = iter.by_ref().cloned().collect();
+            }
+            _ if arg.starts_with("--") => usage(&args[0]),
+            _ => specs.push(arg.trim_start_matches("./").to_string()),
+        }
+    }
+
+    Options {
+        branch: branch.unwrap_or_else(|| format!("llm-bulk-{}", lib::runs::timestamp(lib::history::now()))),
+        driver,
+        keep,
+        specs,
+        driver_args,
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("Error: {}", message);
+    std::process::exit(1);
+}
+
+/// Runs the driver on one target inside the worktree and commits the result if the driver
+/// left it uncommitted.
+fn regenerate(options: &Options, driver: &Path, target_dir: &Path, target: &Target) -> Outcome {
+    let head_before = lib::git::head().unwrap_or_default();
+    let mut command = Command::new(driver);
+    command.env("CARGO_TARGET_DIR", target_dir);
+    command.args(&options.driver_args);
+    if COMMITTING_DRIVERS.contains(&options.driver.as_str()) {
+        command.arg("--commit");
+    }
+    command.args([&target.spec, &target.output]);
+
+    eprintln!("Regenerating {} from {}", target.output, target.spec);
+    let status = command
+        .status()
+        .unwrap_or_else(|_| panic!("Failed to execute {}", driver.display()));
+
+    let changes = lib::git::changes(&[&target.output]).unwrap_or_default();
+    if !changes.is_empty() {
+        let spec = fs::read(&target.spec).unwrap_or_default();
+        let message = lib::git::message(
+            &format!("Regenerate {} from {}", target.output, target.spec),
+            &[
+                ("Spec", target.spec.clone()),
+                ("Spec-Hash", lib::git::content_hash(&spec)),
+                ("Driver", options.driver.clone()),
+            ],
+        );
+        eprintln!("Committing {}", target.output);
+        return match lib::git::commit(&[&target.output], &message) {
+            Ok(hash) => Outcome::Committed(hash),
+            Err(e) => Outcome::CommitFailed(e),
+        };
+    }
+    let head_after = lib::git::head().unwrap_or_default();
+    if head_after != head_before {
+        return Outcome::Committed(head_after);
+    }
+    if status.success() {
+        Outcome::Kept
+    } else {
+        Outcome::NotAccepted(status.code())
+    }
+}
+
+fn print_summary(results: &[(Target, Outcome)]) {
+    let width = results
+        .iter()
+        .map(|(t, _)| t.output.len())
+        .chain(["output".len()])
+        .max()
+        .unwrap_or(0);
+    println!("{:<width$}  result", "output", width = width);
+    for (target, outcome) in results {
+        println!("{:<width$}  {}", target.output, outcome.describe(), width = width);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = parse_args(&args);
+
+    if !lib::git::is_repository() {
+        fail("not in a git repository");
+    }
+    eprintln!("Building {}", options.driver);
+    let driver = lib::cargo::build_binary(&options.driver).unwrap_or_else(|e| fail(&e));
+    let base = lib::git::head().unwrap_or_else(|e| fail(&e));
+    let checkout = env::current_dir().expect("Failed to get the current directory");
+    let worktree = env::temp_dir().join(format!("llm-worktree-{}", lib::runs::slug(&options.branch)));
+    let worktree = worktree.to_string_lossy().into_owned();
+    // Shared by all worktrees, so that the dependencies are not compiled again for every one.
+    let target_dir = env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| checkout.join("target").join("llm-worktree"));
+
+    eprintln!("Creating worktree {} on new branch {}", worktree, options.branch);
+    lib::git::add_worktree(&worktree, &options.branch).unwrap_or_else(|e| fail(&e));
+    env::set_current_dir(&worktree).unwrap_or_else(|_| panic!("Failed to enter worktree: {}", worktree));
+
+    let targets: Vec<Target> = lib::targets::discover()
+        .into_iter()
+        .filter(|t| options.specs.is_empty() || options.specs.contains(&t.spec))
+        .collect();
+    if targets.is_empty() {
+        eprintln!("No specs to regenerate");
+    }
+
+    let total = targets.len();
+    let mut results = Vec::new();
+    for (index, target) in targets.into_iter().enumerate() {
+        eprintln!("[{}/{}] {}", index + 1, total, target.spec);
+        let outcome = regenerate(&options, &driver, &target_dir, &target);
+        eprintln!("{}: {}", target.output, outcome.describe());
+        results.push((target, outcome));
+    }
+
+    let range = format!("{}..{}", base, options.branch);
+    print_summary(&results);
+    println!();
+    println!("{}", lib::git::log_oneline(&range).unwrap_or_else(|e| e));
+    println!("{}", lib::git::diff_stat(&range).unwrap_or_else(|e| e));
+
+    env::set_current_dir(&checkout).expect("Failed to return to the original checkout");
+    if options.keep {
+        eprintln!("Keeping worktree {} with the drafts and run files", worktree);
+    } else {
+        eprintln!("Removing worktree {}", worktree);
+        if let Err(e) = lib::git::remove_worktree(&worktree) {
+            eprintln!("Warning: {}", e);
+        }
+    }
+    println!("Review with: git log -p {}", range);
+    println!("Merge with:  git merge {}", options.branch);
+}
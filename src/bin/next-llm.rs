@@ -2,18 +2,33 @@ use std::{
     env,
     fs::{self, copy},
     path::Path,
-    process::{Command, Stdio},
 };
 
+#[allow(dead_code)]
+mod lib;
+
 fn main() {
     // Get the prefix argument
     let arg = env::args().nth(1).expect("Usage: program <prefix>");
     let prefix = format!("llm-{}-", arg);
 
+    let spec_dir = Path::new(&lib::config::project().instruct_dir).join("bin");
+    let spec_path = |num: usize| spec_dir.join(format!("{}{}.md", prefix, num)).to_string_lossy().into_owned();
+    // Where the outputs of the specs go, by the mapping rules
+    let probe = lib::mapping::rule_output_for(&spec_path(0)).unwrap_or_else(|| {
+        eprintln!("Error: no mapping rule gives an output file for {}", spec_path(0));
+        std::process::exit(1);
+    });
+    let output_dir = Path::new(&probe).parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
+    let output_suffix = Path::new(&probe)
+        .file_name()
+        .and_then(|name| name.to_str()?.strip_prefix(&format!("{}0", prefix)).map(str::to_string))
+        .unwrap_or_else(|| ".rs".to_string());
+
     let mut max_num = None;
 
-    // Scan instruct/bin/ for .md files
-    if let Ok(entries) = fs::read_dir("instruct/bin") {
+    // Scan the spec directory for .md files
+    if let Ok(entries) = fs::read_dir(&spec_dir) {
         for entry in entries.filter_map(Result::ok) {
             let name = entry.file_name().into_string().unwrap_or_default();
             if let Some(num) = extract_number(&name, &prefix, ".md") {
@@ -21,19 +36,19 @@ fn main() {
             }
         }
     } else {
-        eprintln!("Warning: could not read instruct/bin/");
+        eprintln!("Warning: could not read {}", spec_dir.display());
     }
 
-    // Scan src/bin/ for .rs files
-    if let Ok(entries) = fs::read_dir("src/bin") {
+    // Scan the output directory for their outputs
+    if let Ok(entries) = fs::read_dir(&output_dir) {
         for entry in entries.filter_map(Result::ok) {
             let name = entry.file_name().into_string().unwrap_or_default();
-            if let Some(num) = extract_number(&name, &prefix, ".rs") {
+            if let Some(num) = extract_number(&name, &prefix, &output_suffix) {
                 max_num = Some(max_num.map_or(num, |m: usize| m.max(num)));
             }
         }
     } else {
-        eprintln!("Warning: could not read src/bin/");
+        eprintln!("Warning: could not read {}", output_dir.display());
     }
 
     let current_num = match max_num {
@@ -47,59 +62,32 @@ fn main() {
     let next_num = current_num + 1;
 
     // Copy .md file
-    let md_src = format!("instruct/bin/llm-{}-{}.md", arg, current_num);
-    let md_dst = format!("instruct/bin/llm-{}-{}.md", arg, next_num);
+    let md_src = spec_path(current_num);
+    let md_dst = spec_path(next_num);
     if !Path::new(&md_src).exists() {
         eprintln!("Error: source file {} does not exist", md_src);
         return;
     }
-    copy(&md_src, &md_dst).expect("Failed to copy .md file");
 
-    // Copy .rs file
-    let rs_src = format!("src/bin/llm-{}-{}.rs", arg, current_num);
-    let rs_dst = format!("src/bin/llm-{}-{}.rs", arg, next_num);
+    // Copy the output file
+    let (Some(rs_src), Some(rs_dst)) = (lib::mapping::output_for(&md_src), lib::mapping::rule_output_for(&md_dst)) else {
+        eprintln!("Error: no mapping rule gives an output file for {}", md_src);
+        return;
+    };
     if !Path::new(&rs_src).exists() {
         eprintln!("Error: source file {} does not exist", rs_src);
         return;
     }
-    copy(&rs_src, &rs_dst).expect("Failed to copy .rs file");
-
-    // Git add and commit .md file
-    let status = Command::new("git")
-        .args(&["add", &md_dst])
-        .status()
-        .expect("Failed to execute git add");
-    if !status.success() {
-        eprintln!("Failed to git add {}", md_dst);
-        return;
-    }
-
-    let status = Command::new("git")
-        .args(&["commit", "-m", &format!("first commit for llm-{}-{}", arg, next_num)])
-        .status()
-        .expect("Failed to execute git commit");
-    if !status.success() {
-        eprintln!("Failed to commit {}", md_dst);
-        return;
-    }
-
-    // Git add and commit .rs file
-    let status = Command::new("git")
-        .args(&["add", &rs_dst])
-        .status()
-        .expect("Failed to execute git add");
-    if !status.success() {
-        eprintln!("Failed to git add {}", rs_dst);
-        return;
-    }
+    copy(&md_src, &md_dst).expect("Failed to copy .md file");
+    copy(&rs_src, &rs_dst).expect("Failed to copy output file");
 
-    let status = Command::new("git")
-        .args(&["commit", "-m", &format!("first commit for llm-{}-{}", arg, next_num)])
-        .status()
-        .expect("Failed to execute git commit");
-    if !status.success() {
-        eprintln!("Failed to commit {}", rs_dst);
-        return;
+    // Commit each file on its own, leaving anything else that is staged alone
+    let message = format!("first commit for llm-{}-{}", arg, next_num);
+    for path in [&md_dst, &rs_dst] {
+        if let Err(e) = lib::git::commit(&[path], &message) {
+            eprintln!("Failed to commit {}: {}", path, e);
+            return;
+        }
     }
 
     println!("Successfully created llm-{}-{} files and committed them", arg, next_num);
@@ -8,20 +8,29 @@ use std::{
 
 use serde_json::json;
 
+#[allow(dead_code)]
+mod lib;
+
 const GROQ_API_URL: &str = "https://api.groq.com/openai/v1/chat/completions";
 const MODEL: &str = "moonshotai/kimi-k2-instruct";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let safety = lib::git::Safety::from_args(&mut args);
     if args.len() != 3 {
-        eprintln!("Usage: {} <input_file> <output_file>", args[0]);
+        eprintln!("Usage: {} [--allow-dirty] [--clean-spec] <input_file> <output_file>", args[0]);
         std::process::exit(1);
     }
 
     let input_file = &args[1];
     let output_file = &args[2];
 
+    if let Err(e) = safety.check(input_file, output_file) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+
     let api_key = env::var("GROQ_API_KEY")
         .expect("Environment variable GROQ_API_KEY must be set");
 
@@ -1,10 +1,17 @@
 use std::{
+    collections::BTreeSet,
     env,
     fs::{self, File},
     io::{self, BufRead, BufReader},
     path::{Path, PathBuf},
 };
 
+#[allow(dead_code)]
+mod lib;
+
+use lib::mapping::Rule;
+use lib::targets::Target;
+
 #[derive(Debug, Default)]
 struct Stats {
     bytes: u64,
@@ -33,124 +40,127 @@ impl Stats {
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: {} <md_dir> <rs_dir>", args[0]);
-        std::process::exit(1);
-    }
-
-    let md_root = Path::new(&args[1]);
-    let rs_root = Path::new(&args[2]);
-
-    if !md_root.is_dir() {
-        eprintln!("Error: '{}' is not a directory", md_root.display());
-        std::process::exit(1);
-    }
-    if !rs_root.is_dir() {
-        eprintln!("Error: '{}' is not a directory", rs_root.display());
-        std::process::exit(1);
-    }
+    let (targets, rules) = match args.len() {
+        // The specs of the project, paired with their outputs by its mapping rules
+        1 => (lib::targets::discover(), lib::config::project().mapping.clone()),
+        3 => {
+            let md_root = Path::new(&args[1]);
+            let rs_root = Path::new(&args[2]);
+            if !md_root.is_dir() {
+                eprintln!("Error: '{}' is not a directory", md_root.display());
+                std::process::exit(1);
+            }
+            if !rs_root.is_dir() {
+                eprintln!("Error: '{}' is not a directory", rs_root.display());
+                std::process::exit(1);
+            }
+            let rule = Rule::mirror(&args[1], &args[2]);
+            let mut specs = Vec::new();
+            lib::targets::collect_specs(md_root, &mut specs);
+            specs.sort();
+            let targets = specs
+                .into_iter()
+                .filter_map(|spec| {
+                    let output = rule.apply(&spec)?;
+                    Some(Target { spec, output })
+                })
+                .collect();
+            (targets, vec![rule])
+        }
+        _ => {
+            eprintln!("Usage: {} [<md_dir> <rs_dir>]", args[0]);
+            std::process::exit(1);
+        }
+    };
 
     let mut total_md = Stats::default();
     let mut total_matching_rs = Stats::default();
-    let mut unmatched_rs = Stats::default();
-    let mut unmatched_count = 0usize;
-
-    visit_md_dir(md_root, md_root, rs_root, &mut total_md, &mut total_matching_rs);
+    count_pairs(&targets, &mut total_md, &mut total_matching_rs);
 
+    let outputs: BTreeSet<PathBuf> = targets
+        .iter()
+        .flat_map(|t| std::iter::once(t.output.clone()).chain(lib::mapping::extra_outputs(&t.spec)))
+        .map(PathBuf::from)
+        .collect();
+    let mut unmatched_rs = Stats::default();
     let mut unmatched_rs_files = Vec::new();
-    visit_rs_dir(rs_root, rs_root, md_root, &mut unmatched_rs, &mut unmatched_count, &mut unmatched_rs_files);
+    let mut generated_files = Vec::new();
+    let mut seen = BTreeSet::new();
+    for rule in &rules {
+        // Outputs in the crate root are not looked for below it, where target/ is
+        let dir = rule.output_dir();
+        let (dir, recursive) = if dir.is_empty() { (".", false) } else { (dir, true) };
+        visit_output_dir(Path::new(dir), recursive, rule.output_suffix(), &mut |path, stats| {
+            if outputs.contains(&path) || !seen.insert(path.clone()) {
+                return;
+            }
+            // Module declarations generated from the instruct tree have no spec of their own
+            if lib::modules::is_generated(&path) {
+                generated_files.push(path);
+            } else {
+                unmatched_rs.bytes += stats.bytes;
+                unmatched_rs.lines += stats.lines;
+                unmatched_rs_files.push((path, stats));
+            }
+        });
+    }
 
     println!("=== Summary ===");
     println!("Total .md files: bytes={}, lines={}", total_md.bytes, total_md.lines);
-    println!("Total matching .rs files: bytes={}, lines={}", total_matching_rs.bytes, total_matching_rs.lines);
-    println!("Unmatched .rs files: {} files, bytes={}, lines={}", unmatched_count, unmatched_rs.bytes, unmatched_rs.lines);
+    println!("Total matching output files: bytes={}, lines={}", total_matching_rs.bytes, total_matching_rs.lines);
+    println!(
+        "Unmatched output files: {} files, bytes={}, lines={}",
+        unmatched_rs_files.len(),
+        unmatched_rs.bytes,
+        unmatched_rs.lines
+    );
+
+    if !generated_files.is_empty() {
+        let names: Vec<String> = generated_files.iter().map(|p| p.display().to_string()).collect();
+        println!("Generated module files: {}", names.join(", "));
+    }
 
     if !unmatched_rs_files.is_empty() {
-        println!("\n=== Unmatched .rs files ===");
+        println!("\n=== Unmatched output files ===");
         for (path, stats) in unmatched_rs_files {
             println!("{}: bytes={}, lines={}", path.display(), stats.bytes, stats.lines);
         }
     }
 }
 
-fn visit_md_dir(
-    dir: &Path,
-    md_root: &Path,
-    rs_root: &Path,
-    total_md: &mut Stats,
-    total_matching_rs: &mut Stats,
-) {
-    let walker = match fs::read_dir(dir) {
-        Ok(w) => w,
-        Err(e) => {
-            eprintln!("Error reading dir {}: {}", dir.display(), e);
-            return;
-        }
-    };
-
-    for entry in walker {
-        let entry = match entry {
-            Ok(e) => e,
+fn count_pairs(targets: &[Target], total_md: &mut Stats, total_matching_rs: &mut Stats) {
+    for target in targets {
+        let md_stats = match Stats::from_path(Path::new(&target.spec)) {
+            Ok(s) => s,
             Err(e) => {
-                eprintln!("Error enumerating {}: {}", dir.display(), e);
+                eprintln!("Error reading {}: {}", target.spec, e);
                 continue;
             }
         };
-        let path = entry.path();
-        let meta = match fs::metadata(&path) {
-            Ok(m) => m,
+        total_md.bytes += md_stats.bytes;
+        total_md.lines += md_stats.lines;
+
+        if !Path::new(&target.output).exists() {
+            eprintln!("Warning: matching output file not found: {}", target.output);
+            continue;
+        }
+        let rs_stats = match Stats::from_path(Path::new(&target.output)) {
+            Ok(s) => s,
             Err(e) => {
-                eprintln!("Error accessing {}: {}", path.display(), e);
+                eprintln!("Error reading {}: {}", target.output, e);
                 continue;
             }
         };
-
-        if meta.is_dir() {
-            visit_md_dir(&path, md_root, rs_root, total_md, total_matching_rs);
-        } else if path.extension().and_then(|s| s.to_str()) == Some("md") {
-            let rs_path = build_rs_path(&path, md_root, rs_root);
-            let md_stats = match Stats::from_path(&path) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("Error reading {}: {}", path.display(), e);
-                    continue;
-                }
-            };
-            *total_md = Stats {
-                bytes: total_md.bytes + md_stats.bytes,
-                lines: total_md.lines + md_stats.lines,
-            };
-
-            if !rs_path.exists() {
-                eprintln!("Warning: matching .rs file not found: {}", rs_path.display());
-                continue;
-            }
-            let rs_stats = match Stats::from_path(&rs_path) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("Error reading {}: {}", rs_path.display(), e);
-                    continue;
-                }
-            };
-            *total_matching_rs = Stats {
-                bytes: total_matching_rs.bytes + rs_stats.bytes,
-                lines: total_matching_rs.lines + rs_stats.lines,
-            };
-            println!("Pair: {} .md -> {} .rs", path.display(), rs_path.display());
-            println!("  md:  bytes={}, lines={}", md_stats.bytes, md_stats.lines);
-            println!("  rs:  bytes={}, lines={}", rs_stats.bytes, rs_stats.lines);
-        }
+        total_matching_rs.bytes += rs_stats.bytes;
+        total_matching_rs.lines += rs_stats.lines;
+        println!("Pair: {} -> {}", target.spec, target.output);
+        println!("  md:  bytes={}, lines={}", md_stats.bytes, md_stats.lines);
+        println!("  out: bytes={}, lines={}", rs_stats.bytes, rs_stats.lines);
     }
 }
 
-fn visit_rs_dir(
-    dir: &Path,
-    rs_root: &Path,
-    md_root: &Path,
-    unmatched_rs: &mut Stats,
-    unmatched_count: &mut usize,
-    unmatched_rs_files: &mut Vec<(PathBuf, Stats)>,
-) {
+/// Calls `found` with every file in `dir` whose name ends with `suffix`, and its stats.
+fn visit_output_dir(dir: &Path, recursive: bool, suffix: &str, found: &mut dyn FnMut(PathBuf, Stats)) {
     let walker = match fs::read_dir(dir) {
         Ok(w) => w,
         Err(e) => {
@@ -167,7 +177,8 @@ fn visit_rs_dir(
                 continue;
             }
         };
-        let path = entry.path();
+        // Paths in the crate root are compared without the "./" they are looked for with
+        let path = entry.path().strip_prefix(".").map(Path::to_path_buf).unwrap_or_else(|_| entry.path());
         let meta = match fs::metadata(&path) {
             Ok(m) => m,
             Err(e) => {
@@ -177,36 +188,18 @@ fn visit_rs_dir(
         };
 
         if meta.is_dir() {
-            visit_rs_dir(&path, rs_root, md_root, unmatched_rs, unmatched_count, unmatched_rs_files);
-        } else if path.extension().and_then(|s| s.to_str()) == Some("rs") {
-            let md_path = build_md_path(&path, rs_root, md_root);
-            if !md_path.exists() {
-                let stats = match Stats::from_path(&path) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        eprintln!("Error reading {}: {}", path.display(), e);
-                        continue;
-                    }
-                };
-                unmatched_rs.bytes += stats.bytes;
-                unmatched_rs.lines += stats.lines;
-                *unmatched_count += 1;
-                unmatched_rs_files.push((path, stats));
+            if recursive {
+                visit_output_dir(&path, recursive, suffix, found);
             }
+        } else if path.to_string_lossy().ends_with(suffix) {
+            let stats = match Stats::from_path(&path) {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("Error reading {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            found(path, stats);
         }
     }
 }
-
-fn build_rs_path(md_path: &Path, md_root: &Path, rs_root: &Path) -> PathBuf {
-    let rel = md_path.strip_prefix(md_root).unwrap();
-    let mut new_stem = rel.to_path_buf();
-    new_stem.set_extension("rs");
-    rs_root.join(new_stem)
-}
-
-fn build_md_path(rs_path: &Path, rs_root: &Path, md_root: &Path) -> PathBuf {
-    let rel = rs_path.strip_prefix(rs_root).unwrap();
-    let mut new_stem = rel.to_path_buf();
-    new_stem.set_extension("md");
-    md_root.join(new_stem)
-}
\ No newline at end of file
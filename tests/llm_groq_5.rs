@@ -0,0 +1,167 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+const WORKING_TIER: &str = "fn main() {}\n\n#[cfg(test)]\nmod tests {\n    #[test]\n    fn test_runs() {}\n}\n";
+
+/// A crate with the spec "tiny.md" for "src/bin/tiny.rs", whose scratch directories go to
+/// "scratch".
+fn write_crate(root: &Path) {
+    fs::create_dir_all(root.join("src/bin")).unwrap();
+    fs::create_dir_all(root.join("scratch")).unwrap();
+    fs::write(root.join("Cargo.toml"), "[package]\nname = \"driven\"\nversion = \"0.1.0\"\nedition = \"2021\"\n").unwrap();
+    fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+    fs::write(root.join("tiny.md"), "Please write a Rust program that does nothing.\n").unwrap();
+    let config = format!("[paths]\nscratch = \"{}\"\n", root.join("scratch").display());
+    fs::write(root.join("llm-templated.toml"), config).unwrap();
+}
+
+fn llm_groq_5(root: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_llm-groq-5"));
+    command.args(args).current_dir(root);
+    command
+}
+
+fn git(root: &Path, args: &[&str]) -> String {
+    let output = Command::new("git").args(args).current_dir(root).output().unwrap();
+    assert!(output.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// Whether the process `pid` is gone, or only waits to be reaped.
+fn is_gone(pid: &str) -> bool {
+    match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => stat.rsplit(") ").next().is_some_and(|rest| rest.starts_with('Z')),
+        Err(_) => true,
+    }
+}
+
+#[test]
+fn test_interrupt_stops_tests_and_removes_scratch_dirs() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write_crate(root);
+    let pid_file = root.join("test.pid");
+    let sleeping = format!(
+        "fn main() {{}}\n\n#[cfg(test)]\nmod tests {{\n    #[test]\n    fn test_sleeps() {{\n        std::fs::write({:?}, std::process::id().to_string()).unwrap();\n        std::thread::sleep(std::time::Duration::from_secs(300));\n    }}\n}}\n",
+        pid_file
+    );
+    fs::write(root.join("reply.json"), serde_json::to_string(&[sleeping]).unwrap()).unwrap();
+
+    let mut driver = llm_groq_5(root, &["--models", "mock:reply.json", "--require-tests", "tiny.md", "src/bin/tiny.rs"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    while !pid_file.exists() {
+        assert!(started.elapsed() < Duration::from_secs(300), "the tests of the candidate never ran");
+        thread::sleep(Duration::from_millis(100));
+    }
+    thread::sleep(Duration::from_millis(100));
+    let test_pid = fs::read_to_string(&pid_file).unwrap();
+
+    Command::new("kill")
+        .args(["-TERM", &driver.id().to_string()])
+        .status()
+        .unwrap();
+    assert_eq!(driver.wait().unwrap().code(), Some(130));
+    let started = Instant::now();
+    while !is_gone(&test_pid) {
+        assert!(started.elapsed() < Duration::from_secs(10), "the tests outlived the driver");
+        thread::sleep(Duration::from_millis(100));
+    }
+    let left: Vec<_> = fs::read_dir(root.join("scratch")).unwrap().map(|e| e.unwrap().path()).collect();
+    assert!(left.is_empty(), "left behind: {:?}", left);
+    assert!(!root.join("src/bin/tiny.rs").exists());
+}
+
+#[test]
+fn test_ladder_escalates_past_a_failing_tier() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write_crate(root);
+    let broken = "fn main() {\n    let x: u32 = \"no\";\n}\n";
+    fs::write(root.join("tier1.json"), serde_json::to_string(&[broken]).unwrap()).unwrap();
+    fs::write(root.join("tier2.json"), serde_json::to_string(&[WORKING_TIER]).unwrap()).unwrap();
+
+    let output = llm_groq_5(
+        root,
+        &["--ladder", "mock:tier1.json,mock:tier2.json", "--judge", "deterministic", "tiny.md", "src/bin/tiny.rs"],
+    )
+    .output()
+    .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(fs::read_to_string(root.join("src/bin/tiny.rs")).unwrap(), WORKING_TIER);
+    let lock = fs::read_to_string(root.join("instruct.lock")).unwrap();
+    assert!(lock.contains("src/bin/tiny.rs"), "{}", lock);
+
+    let history = fs::read_to_string(root.join(".llm-history.jsonl")).unwrap();
+    let record: serde_json::Value = serde_json::from_str(history.lines().last().unwrap()).unwrap();
+    assert_eq!(record["result"], "accepted");
+    let outcomes: Vec<&str> = record["tiers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tier| tier["outcome"].as_str().unwrap())
+        .collect();
+    assert_eq!(outcomes, ["no-compile", "accepted"]);
+    assert_eq!(record["accepted_tier"], 2);
+    assert_eq!(record["accepted_model"], "mock:tier2.json");
+}
+
+#[test]
+fn test_deterministic_judge_refuses_several_outputs() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write_crate(root);
+    fs::write(root.join("pair.md"), "+++\noutputs = [\"src/bin/other.rs\"]\n+++\nTwo programs.\n").unwrap();
+
+    let output = llm_groq_5(root, &["--judge", "deterministic", "pair.md", "src/bin/pair.rs"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(
+        stderr.contains("Error: --judge deterministic can not compare the several outputs of pair.md"),
+        "{}",
+        stderr
+    );
+    assert!(!root.join(".llm-runs").exists());
+}
+
+#[test]
+fn test_commit_leaves_out_the_lock_entries_of_other_targets() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write_crate(root);
+    fs::write(root.join(".gitignore"), "/target\n/scratch\n").unwrap();
+    fs::write(root.join("reply.json"), serde_json::to_string(&[WORKING_TIER]).unwrap()).unwrap();
+    git(root, &["init", "--quiet"]);
+    git(root, &["config", "user.name", "Test"]);
+    git(root, &["config", "user.email", "test@example.com"]);
+    git(root, &["add", "."]);
+    git(root, &["commit", "--quiet", "-m", "Crate"]);
+    // Recorded by another driver of the same "llm-make -j" run, which commits it itself.
+    let other = "[[target]]\nspec = \"other.md\"\noutput = \"src/bin/other.rs\"\nspec_hash = \"sha256:1\"\noutput_hash = \"sha256:2\"\n";
+    fs::write(root.join("instruct.lock"), other).unwrap();
+
+    let output = llm_groq_5(
+        root,
+        &["--models", "mock:reply.json", "--judge", "deterministic", "--commit", "tiny.md", "src/bin/tiny.rs"],
+    )
+    .output()
+    .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let committed = git(root, &["show", "HEAD:instruct.lock"]);
+    assert!(committed.contains("src/bin/tiny.rs"), "{}", committed);
+    assert!(!committed.contains("src/bin/other.rs"), "{}", committed);
+    let lock = fs::read_to_string(root.join("instruct.lock")).unwrap();
+    assert!(lock.contains("src/bin/tiny.rs") && lock.contains("src/bin/other.rs"), "{}", lock);
+    assert_eq!(git(root, &["show", "HEAD:src/bin/tiny.rs"]), WORKING_TIER);
+    assert_eq!(git(root, &["diff", "--cached", "--name-only"]), "");
+}
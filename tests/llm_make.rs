@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use tempfile::tempdir;
+
+const BASE: &str = "pub fn base() -> u32 {\n    1\n}\n";
+
+/// A driver that regenerates the library module and fails on everything else.
+const FAKE_DRIVER: &str = r#"fn main() {
+    let output = std::env::args().last().unwrap();
+    if !output.ends_with("lib/base.rs") {
+        std::process::exit(1);
+    }
+    std::fs::write(&output, "pub fn base() -> u32 {\n    2\n}\n").unwrap();
+    eprintln!("Regenerated {}", output);
+}
+"#;
+
+fn llm_make(root: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_llm-make"))
+        .args(args)
+        .current_dir(root)
+        .output()
+        .expect("Failed to run llm-make")
+}
+
+/// A crate with a library module, a binary using it, their specs and the fake driver.
+fn write_crate(root: &Path) {
+    fs::create_dir_all(root.join("src/bin/lib")).unwrap();
+    fs::create_dir_all(root.join("instruct/bin/lib")).unwrap();
+    fs::write(root.join("Cargo.toml"), "[package]\nname = \"changeset\"\nversion = \"0.1.0\"\nedition = \"2021\"\n").unwrap();
+    fs::write(root.join("src/bin/fake-driver.rs"), FAKE_DRIVER).unwrap();
+    fs::write(root.join("src/bin/lib/mod.rs"), "pub mod base;\n").unwrap();
+    fs::write(root.join("src/bin/lib/base.rs"), BASE).unwrap();
+    fs::write(root.join("src/bin/user.rs"), "mod lib;\n\nfn main() {\n    println!(\"{}\", lib::base::base());\n}\n").unwrap();
+    fs::write(root.join("instruct/bin/lib/base.md"), "A module with a function returning a number.\n").unwrap();
+    fs::write(root.join("instruct/bin/user.md"), "A program printing that number.\n").unwrap();
+}
+
+#[test]
+fn test_failing_dependent_rolls_back_changeset() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    write_crate(root);
+    let relock = llm_make(root, &["--relock"]);
+    assert!(relock.status.success(), "{}", String::from_utf8_lossy(&relock.stderr));
+    let lock = fs::read(root.join("instruct.lock")).unwrap();
+
+    let changeset = llm_make(root, &["--driver", "fake-driver", "--changeset", "src/bin/lib/base.rs"]);
+    let stderr = String::from_utf8_lossy(&changeset.stderr);
+    assert!(!changeset.status.success(), "{}", stderr);
+    assert!(stderr.contains("Rolling back the changeset of instruct/bin/lib/base.md"), "{}", stderr);
+    assert_eq!(fs::read_to_string(root.join("src/bin/lib/base.rs")).unwrap(), BASE);
+    assert_eq!(fs::read(root.join("instruct.lock")).unwrap(), lock);
+    // The root was regenerated before its dependent failed.
+    let log = fs::read_to_string(root.join(".llm-make/src-bin-lib-base.rs.log")).unwrap();
+    assert_eq!(log, "Regenerated src/bin/lib/base.rs\n");
+}
+
+/// A driver that writes its output and then fails.
+const FAILING_DRIVER: &str = r#"fn main() {
+    let output = std::env::args().last().unwrap();
+    std::fs::write(&output, "fn main() {}\n").unwrap();
+    std::process::exit(1);
+}
+"#;
+
+#[test]
+fn test_driver_exiting_with_an_error_fails() {
+    let dir = tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join("src/bin")).unwrap();
+    fs::create_dir_all(root.join("instruct/bin")).unwrap();
+    fs::write(root.join("Cargo.toml"), "[package]\nname = \"failing\"\nversion = \"0.1.0\"\nedition = \"2021\"\n").unwrap();
+    fs::write(root.join("src/bin/failing-driver.rs"), FAILING_DRIVER).unwrap();
+    fs::write(root.join("instruct/bin/tool.md"), "A program doing nothing.\n").unwrap();
+
+    let run = llm_make(root, &["--driver", "failing-driver", "instruct/bin/tool.md"]);
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(!run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+    assert!(stdout.contains("src/bin/tool.rs  failed"), "{}", stdout);
+    assert!(stdout.contains("0 accepted, 0 kept, 0 rejected, 1 failed"), "{}", stdout);
+    // A failed run is not recorded in the lock file.
+    let lock = fs::read_to_string(root.join("instruct.lock")).unwrap_or_default();
+    assert!(!lock.contains("src/bin/tool.rs"), "{}", lock);
+}
@@ -0,0 +1,26 @@
+use std::process::Command;
+
+/// The command the Makefile's "regenerate" runs for wcr.rs with every driver option set.
+fn regenerate(binary: &str) -> String {
+    let output = Command::new("make")
+        .args(["-s", "print-regenerate", "SPEC=instruct/bin/wcr.md", "OUTPUT=src/bin/wcr.rs"])
+        .args(["FALLBACK=x", "LADDER=y", "COMMIT=1", "ALLOW_DIRTY=1", binary])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to run make, which this test needs");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[test]
+fn test_regenerate_options() {
+    // The default driver only takes the git safety options.
+    assert_eq!(
+        regenerate("BINARY="),
+        "cargo run --bin llm-groq-4 -- --allow-dirty instruct/bin/wcr.md src/bin/wcr.rs"
+    );
+    assert_eq!(
+        regenerate("BINARY=llm-groq-5"),
+        "cargo run --bin llm-groq-5 -- --ladder y --fallback x --commit --allow-dirty instruct/bin/wcr.md src/bin/wcr.rs"
+    );
+}